@@ -0,0 +1,220 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Serialize, Deserialize};
+use pss::*;
+
+/*
+	Adversarial clients, for testing the server's malicious security
+	A Client runs Honest unless set_behaviour says otherwise,
+	each behaviour cheats at one point of the protocol
+	the way a corrupt client could, and should be caught by:
+		OutOfRange			x_0 past the input bit limit, y and the
+							L2-norm kept consistent with it				EC Bounds
+		WrongBits			same x_0, with bits that aren't 0/1
+							but still add up to it						EC BitAndQuadratic
+		FakeSquares			every y = 0, so the L2-norm is 0			EC BitAndQuadratic
+		InconsistentShares	every other explicit peer's share is
+							off the polynomial, more than T				EC Degree
+		WrongTests			corrections off for every dealer			EC Responses
+		BadAggregate		aggregated shares off						left out at AG
+		Replay				input shares sent twice						replay, excluded
+		ReplayAggregate		last round's signed aggregated shares
+							sent again from round 1 on					AG signature
+	Everything else the client does stays honest.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Behaviour {
+	Honest,
+	OutOfRange,
+	WrongBits,
+	FakeSquares,
+	InconsistentShares,
+	WrongTests,
+	BadAggregate,
+	Replay,
+	ReplayAggregate,
+}
+
+pub const BEHAVIOURS: [Behaviour; 9] = [
+	Behaviour::Honest,
+	Behaviour::OutOfRange,
+	Behaviour::WrongBits,
+	Behaviour::FakeSquares,
+	Behaviour::InconsistentShares,
+	Behaviour::WrongTests,
+	Behaviour::BadAggregate,
+	Behaviour::Replay,
+	Behaviour::ReplayAggregate,
+];
+
+impl Default for Behaviour {
+	fn default() -> Behaviour {
+		Behaviour::Honest
+	}
+}
+
+impl fmt::Display for Behaviour {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let name = match self {
+			Behaviour::Honest => "honest",
+			Behaviour::OutOfRange => "out_of_range",
+			Behaviour::WrongBits => "wrong_bits",
+			Behaviour::FakeSquares => "fake_squares",
+			Behaviour::InconsistentShares => "inconsistent_shares",
+			Behaviour::WrongTests => "wrong_tests",
+			Behaviour::BadAggregate => "bad_aggregate",
+			Behaviour::Replay => "replay",
+			Behaviour::ReplayAggregate => "replay_aggregate",
+		};
+		write!(f, "{}", name)
+	}
+}
+
+impl FromStr for Behaviour {
+	type Err = String;
+	fn from_str(s: &str) -> Result<Behaviour, String> {
+		let s = s.replace('-', "_");
+		match BEHAVIOURS.iter().find(|b| b.to_string() == s) {
+			Some(b) => Ok(*b),
+			None => Err(format!("unknown behaviour {}, use one of {}", s,
+				BEHAVIOURS.iter().map(|b| b.to_string()).collect::<Vec<_>>().join(", "))),
+		}
+	}
+}
+
+/*
+	Where things sit in the malicious input vector,
+	see Client::input_sharing_ml
+		[x (V) | y (V) | ySum, 0.. (L) | ySum bits (Y) | x bits (LSB) | C | A | B]
+*/
+pub(crate) struct Layout {
+	pub V: usize,
+	pub L: usize,
+	pub S: usize,
+	pub Y: usize,
+	pub P: u64,
+}
+
+impl Layout {
+	fn y(&self, i: usize) -> usize {
+		self.V + i
+	}
+
+	fn y_sum(&self) -> usize {
+		2*self.V
+	}
+
+	fn y_sum_bit(&self, k: usize) -> usize {
+		2*self.V + self.L + k
+	}
+
+	fn x_bit(&self, i: usize, j: usize) -> usize {
+		let b = i/self.L;
+		2*self.V + self.L + self.Y + (b*self.S + j)*self.L + (i - self.L*b)
+	}
+
+	fn set_x(&self, input: &mut Vec<u64>, i: usize, x: u64) {
+	/*
+		x_i and everything derived from it but its bits:
+		y_i = x_i^2, ySum and the bits of ySum
+	*/
+		let P = self.P as u128;
+		let old = input[self.y(i)] as u128;
+		let y = (x as u128).mul_mod(x as u128, P);
+		let ySum = (input[self.y_sum()] as u128).sub_mod(old, P).add_mod(y, P) as u64;
+		input[i] = x;
+		input[self.y(i)] = y as u64;
+		self.set_y_sum(input, ySum);
+	}
+
+	fn set_y_sum(&self, input: &mut Vec<u64>, ySum: u64) {
+		input[self.y_sum()] = ySum;
+		for k in 0..self.Y {
+			input[self.y_sum_bit(k)] = match k < 64 {
+				true => (ySum >> k) & 1,
+				false => 0,
+			};
+		}
+	}
+}
+
+impl Behaviour {
+
+	pub fn is_honest(&self) -> bool {
+		*self == Behaviour::Honest
+	}
+
+	pub(crate) fn tamper_input(&self, input: &mut Vec<u64>, layout: &Layout) {
+	/*
+		Malicious input vector, after it's built and before it's shared
+		x_0 goes up by 2^S, out of the range its S bits can hold
+	*/
+		let P = layout.P as u128;
+		let big = input[0] + (1u64 << layout.S);
+		match self {
+			Behaviour::OutOfRange => layout.set_x(input, 0, big),
+			Behaviour::WrongBits => {
+				// bit 0 carries the extra 2^S
+				layout.set_x(input, 0, big);
+				let b = layout.x_bit(0, 0);
+				input[b] = (input[b] as u128).add_mod(1u128 << layout.S, P) as u64;
+			},
+			Behaviour::FakeSquares => {
+				for i in 0..layout.V {
+					input[layout.y(i)] = 0;
+				}
+				layout.set_y_sum(input, 0);
+			},
+			_ => (),
+		}
+	}
+
+	pub(crate) fn tamper_shares(&self, shares: &mut Vec<Vec<u64>>, idx: usize, seeded: &[usize], P: u64) {
+	/*
+		Shares of every peer, before they're encrypted
+		Every other peer after us with explicit shares gets its first one
+		bumped, so more than T holders are off and the row doesn't decode.
+		Seeded peers and the rest stay on the polynomial
+	*/
+		if *self != Behaviour::InconsistentShares {
+			return;
+		}
+		let N = shares.len();
+		let peers: Vec<usize> = (1..N).map(|k| (idx + k) % N).filter(|j| !seeded.contains(j)).collect();
+		for &j in peers.iter().step_by(2) {
+			shares[j][0] = (shares[j][0] as u128).add_mod(1, P as u128) as u64;
+		}
+	}
+
+	pub(crate) fn tamper_tests(&self, tests: &mut Vec<u64>, P: u64) {
+		// [DT, sumA, sumB] for one dealer, all off by one
+		if *self != Behaviour::WrongTests {
+			return;
+		}
+		for t in tests.iter_mut() {
+			*t = (*t as u128).add_mod(1, P as u128) as u64;
+		}
+	}
+
+	pub(crate) fn tamper_aggregate(&self, aggregation: &mut Vec<u64>, P: u64) {
+		if *self != Behaviour::BadAggregate {
+			return;
+		}
+		for a in aggregation.iter_mut() {
+			*a = (*a as u128).add_mod(1, P as u128) as u64;
+		}
+	}
+
+	// Send the input shares a second time
+	pub(crate) fn replays(&self) -> bool {
+		*self == Behaviour::Replay
+	}
+
+	// Send the last round's aggregated shares and signature instead of this round's
+	pub(crate) fn replays_aggregate(&self) -> bool {
+		*self == Behaviour::ReplayAggregate
+	}
+}
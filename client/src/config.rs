@@ -0,0 +1,292 @@
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use serde::{Serialize, Deserialize};
+use structopt::StructOpt;
+use rand_core::OsRng;
+use p256::ecdsa::{SigningKey, VerifyKey};
+
+use crate::Client;
+use crate::logging::LogFormat;
+use crate::adversary::Behaviour;
+
+/*
+	Client configuration
+	Values come from an optional TOML / JSON file (picked by extension),
+	named flags override the file, then everything is validated
+	before the Client connects.
+*/
+
+#[derive(Debug)]
+pub enum ConfigError {
+	Read(String),
+	Parse(String),
+	Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			ConfigError::Read(s) => write!(f, "cannot read config: {}", s),
+			ConfigError::Parse(s) => write!(f, "cannot parse config: {}", s),
+			ConfigError::Invalid(s) => write!(f, "invalid config: {}", s),
+		}
+	}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClientConfig {
+	pub id: String,
+	pub session: String,
+	pub vector_len: usize,
+	pub malicious: bool,
+	pub input_bit_limit: Option<usize>,		// malicious only
+	pub ip: Option<String>,					// None connects to localhost
+	pub msg_port: usize,
+	pub broadcast_port: usize,
+	pub catchup_port: usize,				// REQ/REP for missed broadcasts
+	pub seed_compression: bool,
+	pub dropout_rate: u32,					// % chance to quit each round, simulates dropouts
+	pub key_file: Option<PathBuf>,			// hex signKey, created if missing, None uses a fresh key
+	pub server_key: Option<String>,			// hex server veriKey, None trusts the unauthenticated handshake reply on first use
+	pub log_level: String,					// tracing filter, RUST_LOG overrides
+	pub log_format: LogFormat,				// text or json
+	pub behaviour: Behaviour,				// cheat on purpose to test the server, malicious only
+}
+
+impl Default for ClientConfig {
+	fn default() -> ClientConfig {
+		ClientConfig {
+			id: String::new(),
+			session: crate::DEFAULT_SESSION.to_string(),
+			vector_len: 0,
+			malicious: false,
+			input_bit_limit: None,
+			ip: None,
+			msg_port: 8888,
+			broadcast_port: 9999,
+			catchup_port: 9998,
+			seed_compression: false,
+			dropout_rate: 10,
+			key_file: None,
+			server_key: None,
+			log_level: "info".to_string(),
+			log_format: LogFormat::Text,
+			behaviour: Behaviour::Honest,
+		}
+	}
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "client", about = "Secure aggregation client")]
+pub struct ClientOpt {
+	/// TOML or JSON config file, flags override it
+	#[structopt(short, long, parse(from_os_str))]
+	pub config: Option<PathBuf>,
+	/// Print the effective config and exit
+	#[structopt(long)]
+	pub print_config: bool,
+	/// Print the hex veriKey of --key-file (creating it if missing) and exit
+	#[structopt(long)]
+	pub print_verify_key: bool,
+
+	/// Client name, also the socket identity
+	#[structopt(long)]
+	pub id: Option<String>,
+	#[structopt(long)]
+	pub session: Option<String>,
+	#[structopt(long)]
+	pub vector_len: Option<usize>,
+	/// Run the malicious protocol (needs --input-bit-limit)
+	#[structopt(long)]
+	pub malicious: bool,
+	/// Bits per input element
+	#[structopt(long)]
+	pub input_bit_limit: Option<usize>,
+	#[structopt(long)]
+	pub ip: Option<String>,
+	#[structopt(long)]
+	pub msg_port: Option<usize>,
+	#[structopt(long)]
+	pub broadcast_port: Option<usize>,
+	/// Port serving broadcasts a subscriber missed
+	#[structopt(long)]
+	pub catchup_port: Option<usize>,
+	/// Send PRG seeds instead of shares where possible
+	#[structopt(long)]
+	pub seed_compression: bool,
+	/// Percent chance to drop out each round
+	#[structopt(long)]
+	pub dropout_rate: Option<u32>,
+	/// Long-term signing key, hex, generated on first use
+	#[structopt(long, parse(from_os_str))]
+	pub key_file: Option<PathBuf>,
+	/// Server's hex veriKey (its --print-verify-key), broadcasts must be signed by it.
+	/// Without it the key is trusted on first use from the handshake reply, which is not authenticated
+	#[structopt(long)]
+	pub server_key: Option<String>,
+	/// Log filter: error, warn, info, debug or trace (shares and keys only at trace)
+	#[structopt(long)]
+	pub log_level: Option<String>,
+	/// Log output: text or json
+	#[structopt(long)]
+	pub log_format: Option<LogFormat>,
+	/// Cheat on purpose to test the server: out_of_range, wrong_bits, fake_squares,
+	/// inconsistent_shares, wrong_tests, bad_aggregate, replay or replay_aggregate
+	#[structopt(long)]
+	pub behaviour: Option<Behaviour>,
+}
+
+impl ClientConfig {
+
+	pub fn load(path: &Path) -> Result<ClientConfig, ConfigError> {
+		let text = match fs::read_to_string(path) {
+			Ok(t) => t,
+			Err(e) => return Err(ConfigError::Read(format!("{}: {}", path.display(), e))),
+		};
+		match path.extension().and_then(|e| e.to_str()) {
+			Some("json") => serde_json::from_str(&text)
+				.map_err(|e| ConfigError::Parse(format!("{}: {}", path.display(), e))),
+			_ => toml::from_str(&text)
+				.map_err(|e| ConfigError::Parse(format!("{}: {}", path.display(), e))),
+		}
+	}
+
+	pub fn from_opt(opt: &ClientOpt) -> Result<ClientConfig, ConfigError> {
+	/*
+		File (or defaults) first, then flags on top
+		Not validated yet so --print-config can show a broken one
+	*/
+		let mut cfg = match &opt.config {
+			Some(path) => ClientConfig::load(path)?,
+			None => ClientConfig::default(),
+		};
+		if let Some(v) = &opt.id { cfg.id = v.clone(); }
+		if let Some(v) = &opt.session { cfg.session = v.clone(); }
+		if let Some(v) = opt.vector_len { cfg.vector_len = v; }
+		if opt.malicious { cfg.malicious = true; }
+		if let Some(v) = opt.input_bit_limit { cfg.input_bit_limit = Some(v); }
+		if let Some(v) = &opt.ip { cfg.ip = Some(v.clone()); }
+		if let Some(v) = opt.msg_port { cfg.msg_port = v; }
+		if let Some(v) = opt.broadcast_port { cfg.broadcast_port = v; }
+		if let Some(v) = opt.catchup_port { cfg.catchup_port = v; }
+		if opt.seed_compression { cfg.seed_compression = true; }
+		if let Some(v) = opt.dropout_rate { cfg.dropout_rate = v; }
+		if let Some(v) = &opt.key_file { cfg.key_file = Some(v.clone()); }
+		if let Some(v) = &opt.server_key { cfg.server_key = Some(v.clone()); }
+		if let Some(v) = &opt.log_level { cfg.log_level = v.clone(); }
+		if let Some(v) = opt.log_format { cfg.log_format = v; }
+		if let Some(v) = opt.behaviour { cfg.behaviour = v; }
+		Ok(cfg)
+	}
+
+	pub fn validate(&self) -> Result<(), ConfigError> {
+		let invalid = |s: &str| Err(ConfigError::Invalid(s.to_string()));
+		if self.id.is_empty() {
+			return invalid("id must be set");
+		}
+		if self.session.is_empty() || self.session.contains('/') {
+			return invalid("session must be non-empty and contain no '/'");
+		}
+		if self.vector_len == 0 {
+			return invalid("vector_len must be at least 1");
+		}
+		if self.malicious {
+			match self.input_bit_limit {
+				Some(s) if s > 0 && s <= 32 => (),
+				Some(_) => return invalid("input_bit_limit must be in 1..=32"),
+				None => return invalid("malicious mode needs input_bit_limit"),
+			}
+		}
+		for port in [self.msg_port, self.broadcast_port, self.catchup_port].iter() {
+			if *port == 0 || *port > 65535 {
+				return invalid("ports must be in 1..=65535");
+			}
+		}
+		if self.msg_port == self.broadcast_port
+			|| self.msg_port == self.catchup_port
+			|| self.broadcast_port == self.catchup_port {
+			return invalid("msg_port, broadcast_port and catchup_port must differ");
+		}
+		if !self.behaviour.is_honest() && !self.malicious {
+			return invalid("behaviour other than honest needs malicious mode");
+		}
+		if self.dropout_rate > 100 {
+			return invalid("dropout_rate is a percentage, 0..=100");
+		}
+		if let Some(k) = &self.server_key {
+			let valid = hex::decode(k.trim()).ok()
+				.map_or(false, |b| VerifyKey::new(&b).is_ok());
+			if !valid {
+				return invalid("server_key must be a hex P-256 point");
+			}
+		}
+		Ok(())
+	}
+
+	pub fn to_toml(&self) -> String {
+		toml::to_string_pretty(self).unwrap()
+	}
+
+	pub fn load_or_create_key(&self) -> Result<Option<Vec<u8>>, ConfigError> {
+	/*
+		Read the signKey from key_file,
+		generate and save one (owner-only on unix) on first run
+	*/
+		let path = match &self.key_file {
+			Some(p) => p,
+			None => return Ok(None),
+		};
+		if path.exists() {
+			let text = fs::read_to_string(path)
+				.map_err(|e| ConfigError::Read(format!("{}: {}", path.display(), e)))?;
+			let key = hex::decode(text.trim())
+				.map_err(|e| ConfigError::Parse(format!("{}: {}", path.display(), e)))?;
+			if SigningKey::new(&key).is_err() {
+				return Err(ConfigError::Invalid(format!("{} is not a P-256 signing key", path.display())));
+			}
+			return Ok(Some(key));
+		}
+		let key = SigningKey::to_bytes(&SigningKey::random(&mut OsRng)).to_vec();
+		let mut options = fs::OpenOptions::new();
+		options.write(true).create_new(true);
+		#[cfg(unix)]
+		{
+			use std::os::unix::fs::OpenOptionsExt;
+			options.mode(0o600);
+		}
+		let mut file = options.open(path)
+			.map_err(|e| ConfigError::Read(format!("{}: {}", path.display(), e)))?;
+		file.write_all(hex::encode(&key).as_bytes())
+			.map_err(|e| ConfigError::Read(format!("{}: {}", path.display(), e)))?;
+		Ok(Some(key))
+	}
+
+	pub fn build_client(&self) -> Client {
+		let inputBitLimit = match self.malicious {
+			true => self.input_bit_limit,
+			false => None,
+		};
+		let mut client = Client::new(
+			&self.id,
+			self.vector_len,
+			inputBitLimit,
+			self.ip.as_deref(),
+			self.msg_port,
+			self.broadcast_port,
+			self.catchup_port
+		);
+		if self.seed_compression {
+			client.enable_seed_compression();
+		}
+		client.set_behaviour(self.behaviour);
+		if let Some(k) = &self.server_key {
+			// checked in validate
+			client.set_server_key(&hex::decode(k.trim()).unwrap()).unwrap();
+		}
+		client
+	}
+}
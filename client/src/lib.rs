@@ -12,8 +12,7 @@ use std::sync::*;
 use std::thread;
 use std::thread::sleep;
 
-use zmq::SNDMORE;
-use zmq::Message;
+use transport::{Transport, Connection, Incoming, ZmqTransport};
 
 use rand_core::{RngCore, OsRng};
 use signature::Signature as _;
@@ -32,11 +31,16 @@ use aes_gcm::aead::generic_array::GenericArray;
 use aes_gcm::aead::{Aead, NewAead};
 
 use pss::*;
+use tracing::{trace, debug, info, warn};
 
 mod sockets;
 mod util;
+pub mod config;
+pub mod logging;
+pub mod adversary;
 use sockets::*;
 use util::*;
+use adversary::{Behaviour, Layout};
 
 #[derive(Debug)]
 pub enum ClientError {
@@ -45,9 +49,22 @@ pub enum ClientError {
 	EncryptionError(usize),
 	MutexLockFail(usize),
 	UnidentifiedShare(usize),
+	DecodeFail(usize),
+	InvalidSignature(usize),
+	NoResult(usize),
+	Rejected(String),
+	Aborted(String),
+	TransportFail(usize),
 }
 
 
+// Session the stock server registers from its command line
+pub const DEFAULT_SESSION: &str = "default";
+
+// First plaintext byte of a relayed share
+const SHARE_EXPLICIT: u8 = 0;
+const SHARE_SEED: u8 = 1;
+
 #[derive(Debug, Clone, Copy)]
 pub struct Param {
 	P: u128,
@@ -62,15 +79,16 @@ pub struct Param {
 pub struct Client{
 
 	pub ID: String,							//Unique ID that is field element
-	context: zmq::Context,
-	sender: zmq::Socket,
+	sender: Box<dyn Connection>,			// to the server, replies and relayed shares come back here
 
-	subRx: mpsc::Receiver<Vec<u64>>,
+	subRx: mpsc::Receiver<(String, usize, Vec<u64>)>,
 	subThread :thread::JoinHandle<Result<usize, ClientError>>,
+	subCmd: mpsc::Sender<SubCmd>,			// session to follow, server key to verify with
 	buffer: Arc<RwLock<HashMap<Vec<u8>, RecvType>>>,
 
 	signKey: SigningKey,					//Authentification
 	veriKey: VerifyKey,
+	serverVeriKey: Option<VerifyKey>,		// checks every broadcast, pinned or learnt at handshake
 
 	privateKey: EphemeralSecret,			//ECDH
 	publicKey: EncodedPoint,
@@ -84,47 +102,42 @@ pub struct Client{
 	inputBitLimit: Option<usize>,
 	param: Option<Param>,
 	shares: Vec<Vec<u64>>,
+	seedCompression: bool,					// send PRG seeds to up to D2 - L peers
+	behaviour: Behaviour,					// Honest, or how to cheat for testing
+	session: String,						// session named in handshake
+	round: usize,							// current aggregation round
+	moreRounds: bool,						// server announced another round in AG
+	lastAggregate: Vec<Vec<u8>>,			// AG message of the last round, for ReplayAggregate
 }
 
 
 impl Client{
 
 	pub fn new(ID: &str, vectorSize: usize, inputBitLimit: Option<usize>,
-		ip: Option<&str>, port1: usize, port2: usize) -> Client{
-
-    	let context = zmq::Context::new();
-		let sender = context.socket(zmq::DEALER).unwrap();
-
-		let mut addr1: String;
-		let mut addr2: String;
-
-		match ip {
-			Some(address) => {
-				addr1 = format!("tcp://{}:{:?}", address, port1);
-				addr2 = format!("tcp://{}:{:?}", address, port2);
-				println!("Sender connecting {}", addr1);
-				println!("Subscriber connecting to {}", addr2);
-			},
-			None => {
-				addr1 = format!("tcp://localhost:{:?}", port1);
-				addr2 = format!("tcp://localhost:{:?}", port2);
-				println!("Sender going default {}", addr1);
-				println!("Subscriber going default {}", addr2);
-			},
-		}
+		ip: Option<&str>, port1: usize, port2: usize, port3: usize) -> Client{
+	/*
+		ZMQ to the server's msg, broadcast and catch-up ports,
+		localhost if no ip
+	*/
+		let transport = ZmqTransport::new(zmq::Context::new(), ip, port1, port2, port3);
+		Client::with_transport(ID, vectorSize, inputBitLimit, Arc::new(transport))
+	}
 
-		sender.set_identity(ID.as_bytes());
-		assert!(sender.connect(&addr1).is_ok());
+	pub fn with_transport(ID: &str, vectorSize: usize, inputBitLimit: Option<usize>,
+		transport: Arc<dyn Transport>) -> Client{
+	/*
+		Any transport, e.g. a MemoryTransport shared with
+		a server in the same process
+	*/
+		let sender = transport.connect(ID.as_bytes()).unwrap();
+		let subscriber = transport.subscribe().unwrap();
 
-		let ctx = context.clone();
 		let buffer = Arc::new(RwLock::new(HashMap::<Vec<u8>, RecvType>::new()));
 		let bf = buffer.clone();
     	let (tx, rx) = mpsc::channel();
+    	let (cmdTx, cmdRx) = mpsc::channel();
     	let subThread = thread::spawn(move || {
-    		let subscriber = ctx.socket(zmq::SUB).unwrap();
-			assert!(subscriber.connect(&addr2).is_ok());
-			subscriber.set_subscribe("".as_bytes());
-			return sub_task(subscriber, bf, tx)
+			return sub_task(transport, subscriber, bf, tx, cmdRx)
 	    });
 
 	    let signKey = SigningKey::random(&mut OsRng);
@@ -132,17 +145,18 @@ impl Client{
 
 		Client {
 
-			context: context,
 			sender: sender,
 
 			subRx: rx,
 			subThread: subThread,
+			subCmd: cmdTx,
 			buffer: buffer,
 
 			ID: ID.to_string(),
 
 			veriKey: VerifyKey::from(&signKey),
 			signKey: signKey,
+			serverVeriKey: None,
 
 			publicKey: EncodedPoint::from(&privateKey),
 			privateKey: privateKey,
@@ -154,31 +168,151 @@ impl Client{
 			inputBitLimit: inputBitLimit,
 			param: None,
 			shares: Vec::new(),
+			seedCompression: false,
+			behaviour: Behaviour::Honest,
+			session: String::new(),
+			round: 0,
+			moreRounds: false,
+			lastAggregate: Vec::new(),
+		}
+	}
+
+	pub fn set_sign_key(&mut self, signKey: &[u8]) -> Result<(), ClientError> {
+	/*
+		Use a long-term key instead of the random one from new(),
+		its veriKey is what the server's enrollment lists
+	*/
+		self.signKey = match SigningKey::new(signKey) {
+			Ok(k) => k,
+			Err(_) => return Err(ClientError::EncryptionError(0)),
+		};
+		self.veriKey = VerifyKey::from(&self.signKey);
+		Ok(())
+	}
+
+	pub fn set_server_key(&mut self, serverKey: &[u8]) -> Result<(), ClientError> {
+	/*
+		Pin the server's veriKey, broadcasts not signed by it are dropped
+		and handshake fails if the server presents another key
+		Without one, the key from the handshake reply is trusted on first use,
+		that reply isn't authenticated
+	*/
+		let vk = match VerifyKey::new(serverKey) {
+			Ok(k) => k,
+			Err(_) => return Err(ClientError::EncryptionError(0)),
+		};
+		self.subCmd.send(SubCmd::ServerKey(vk.clone()));
+		self.serverVeriKey = Some(vk);
+		Ok(())
+	}
+
+	// Compressed SEC1 point registered at handshake
+	pub fn veri_key_bytes(&self) -> Vec<u8> {
+		VerifyKey::to_encoded_point(&self.veriKey, true).to_bytes().to_vec()
+	}
+
+	pub fn enable_seed_compression(&mut self) {
+		self.seedCompression = true;
+	}
+
+	pub fn set_behaviour(&mut self, behaviour: Behaviour) {
+	/*
+		Cheat on purpose, see adversary.rs
+		only for testing the server
+	*/
+		if !behaviour.is_honest() {
+			warn!(id = %self.ID, behaviour = %behaviour, "adversarial client");
 		}
+		self.behaviour = behaviour;
 	}
 
+	pub fn behaviour(&self) -> Behaviour {
+		self.behaviour
+	}
+
+
+	pub fn session(&self) -> &str {
+		&self.session
+	}
 
-	pub fn handshake(&mut self) -> Result<usize, ClientError> {
+	pub fn round(&self) -> usize {
+		self.round
+	}
+
+	// Our place in the session's client list, known after key exchange
+	pub fn index(&self) -> Option<usize> {
+		self.shareOrder.iter().position(|s| s == &*self.publicKey.to_bytes())
+	}
+
+	// Span for one round of this client, enter it around the protocol calls
+	pub fn span(&self) -> tracing::Span {
+		tracing::info_span!("client", id = %self.ID, session = %self.session, round = self.round)
+	}
+
+	pub fn next_round(&mut self) -> bool {
+	/*
+			Move on if the server announced another round,
+			keys and shareOrder carry over,
+			per-round shares, params and broadcasts are dropped
+	*/
+		if !self.moreRounds {
+			return false;
+		}
+		let round = self.round;
+		match self.buffer.write() {
+			Ok(mut guard) => guard.retain(|t, _| match split_topic(t) {
+				Some((session, _, r)) => session == self.session && r > round,
+				None => false,
+			}),
+			Err(_) => return false,
+		};
+		self.round += 1;
+		self.moreRounds = false;
+		self.shares = Vec::new();
+		self.param = None;
+		info!(round = self.round, "moving to next round");
+		true
+	}
+
+	pub fn handshake(&mut self, session: &str) -> Result<usize, ClientError> {
 		let BENCH_TIMER = Instant::now();
 	/*
-			Client say Hello
-			Server send unique signKey
-			Generate veriKey from signKey
+			Join session by name, every later message carries it
+			Client say Hello with its own veriKey
+			Server send its veriKey, or an error if not enrolled
 	*/
-		match send(&self.sender, &format!("Hello, I'm {}", self.ID)) {
+		self.session = session.to_string();
+		self.subCmd.send(SubCmd::Watch(self.session.clone()));
+		let hello = vec![format!("Hello, I'm {}", self.ID).into_bytes(), self.veri_key_bytes()];
+		match send_vecs(&self.sender, &self.session, self.round, hello) {
 			Ok(_) => (),
 			Err(_) => return Err(ClientError::SendFailure(1)),
 		};
 		let msg = recv(&self.sender);
-		let sk = match msg {
+		let serverVk = match msg {
+			RecvType::string(s) if s.starts_with("Error") || s.starts_with("Please") => {
+				return Err(ClientError::Rejected(s))
+			},
+			// a point that happens to be valid utf8 comes back as string
+			RecvType::string(s) => s.into_bytes(),
 			RecvType::bytes(b) => b,
 			_ => return Err(ClientError::UnexpectedRecv(msg)),
 		};
-		match SigningKey::new(&sk) {
-			Ok(k) => self.signKey = k,
+		let serverVk = match VerifyKey::new(&serverVk) {
+			Ok(k) => k,
 			Err(_) => return Err(ClientError::EncryptionError(1)),
 		};
-		self.veriKey = VerifyKey::from(&self.signKey);
+		match &self.serverVeriKey {
+			Some(pinned) => {
+				if VerifyKey::to_encoded_point(pinned, true).to_bytes() != VerifyKey::to_encoded_point(&serverVk, true).to_bytes() {
+					return Err(ClientError::Rejected("Error: Server key does not match.".to_string()))
+				}
+			},
+			None => {
+				self.subCmd.send(SubCmd::ServerKey(serverVk.clone()));
+				self.serverVeriKey = Some(serverVk);
+			},
+		};
 
 	/*
 			Wait for Handshake finishing
@@ -186,7 +320,7 @@ impl Client{
 			Server send a list of veriKeys
 	*/
 		let BEFORE = Instant::now();
-		let waitRes = self.state_change_broadcast("HS");
+		let waitRes = self.state_change_broadcast("HS")?;
 		let AFTER = Instant::now();
 		match waitRes {
 			RecvType::matrix(m) => {
@@ -194,8 +328,7 @@ impl Client{
 			},
 			_ => return Err(ClientError::UnexpectedRecv(waitRes)),
 		};
-		println!("State 1 elapse {:?}ms ({})",
-			(BEFORE-BENCH_TIMER+AFTER.elapsed()).as_millis(), self.ID);
+		info!(state = 1, elapse_ms = (BEFORE-BENCH_TIMER+AFTER.elapsed()).as_millis() as u64, "state finished");
 		return Ok(1)
 	}
 
@@ -211,7 +344,7 @@ impl Client{
 
 		let msg = vec![publicKeyVec.to_vec(),
 					signedPublicKey.as_ref().to_vec()];
-		match send_vecs(&self.sender, msg) {
+		match send_vecs(&self.sender, &self.session, self.round, msg) {
 			Ok(_) => (),
 			Err(_) => return Err(ClientError::SendFailure(2)),
 		};
@@ -228,7 +361,7 @@ impl Client{
 			Create shared keys save as (DH pk, sharedKey)
 	*/
 		let BEFORE = Instant::now();
-		let waitRes = self.state_change_broadcast("KE");
+		let waitRes = self.state_change_broadcast("KE")?;
 		let publicKeys = match waitRes {
 			RecvType::matrix(m) => m,
 			_ => return Err(ClientError::UnexpectedRecv(waitRes)),
@@ -245,8 +378,7 @@ impl Client{
 			};
 		}
 		self.shareOrder = publicKeys;
-		println!("State 2 elapse {:?}ms ({})",
-			(BEFORE-BENCH_TIMER+AFTER.elapsed()).as_millis(), self.ID);
+		info!(state = 2, elapse_ms = (BEFORE-BENCH_TIMER+AFTER.elapsed()).as_millis() as u64, "state finished");
 		return Ok(2)
 	}
 
@@ -260,7 +392,7 @@ impl Client{
 	*/
 		assert!(input.len() == self.vectorSize);
 
-		let waitRes = self.state_change_broadcast("IS");
+		let waitRes = self.state_change_broadcast("IS")?;
 		let BENCH_TIMER = Instant::now();
 
 		let sharingParams = match waitRes {
@@ -330,9 +462,10 @@ impl Client{
 		// Insert y = x^2
 		let mut ySum = 0;
 		for i in 0..V {
-			let y = ((x[i] as u128) * (x[i] as u128) % param.P) as u64;
+			let xi = (x[i] as u128).reduce(param.P);
+			let y = xi.mul_mod(xi, param.P) as u64;
 			input.push(y);
-			ySum = (ySum + y) % P;
+			ySum = ySum.add_mod(y, P);
 		}
 		//println!("ySum {:?}, {}", ySum, (ySum as f64).log(2.0));
 
@@ -377,6 +510,7 @@ impl Client{
 		}
 		input.push(P - rand_sum);
 
+		self.behaviour.tamper_input(input, &Layout { V: V, L: L, S: S, Y: Y, P: P });
 		assert!(input.len() == 2*V + L + Y + L*S*B + 3*L);
 		let mut pss = PackedSecretSharing::new(
 			param.P, param.R2, param.R3,
@@ -385,38 +519,19 @@ impl Client{
 			L, N
 		);
 		let SHARE_START = Instant::now();
-		let resultMatrix = pss.share(&input);
+		let seeds = self.pick_seeded_peers(&param);
+		let mut resultMatrix = match seeds.len() {
+			0 => pss.share(&input),
+			_ => pss.share_seeded(&input, &seeds),
+		};
+		self.tamper_shares(&mut resultMatrix, &seeds, P);
 		assert!(resultMatrix.len() == N);
 		assert!(resultMatrix[0].len() == (2*V + L + Y + L*S*B + 3*L)/L);
-		println!("{:?} sharing time {:?}", self.ID, SHARE_START.elapsed().as_millis());
-	/*
-		 	Encrypt shares for each DH sharedKey
-			send [shares_c1, shares_c2, ....  ]
-	*/
-		let mut msg = Vec::new();
-		for (i, pk) in self.shareOrder.iter().enumerate() {
-
-			let shareKey = self.shareKeys.get(pk).unwrap();
-			let k = GenericArray::from_slice(&shareKey);
-			let cipher = Aes256Gcm::new(k);
-			let nonce = GenericArray::from_slice(b"unique nonce"); //Self.pk
-			let mut shareBytes = Vec::new();
-			for r in &resultMatrix[i] {
-				shareBytes.extend(&r.to_le_bytes());
-			}
-			let encryptedShares = cipher.encrypt(nonce, shareBytes
-										.as_slice())
-		    					   		.expect("encryption failure!");
-		    msg.push(encryptedShares);
-		}
+		debug!(elapse_ms = SHARE_START.elapsed().as_millis() as u64, "shares computed");
+		let msg = self.encrypt_shares(&resultMatrix, &seeds, P);
 		self.param = Some(param);
-		println!("State 3 elapse {:?}ms ({})", BENCH_TIMER.elapsed().as_millis(), self.ID);
-		match send_vecs(&self.sender, msg) {
-			Ok(_) => {
-				return Ok(3)
-			},
-			Err(_) => return Err(ClientError::SendFailure(3)),
-		};
+		info!(state = 3, elapse_ms = BENCH_TIMER.elapsed().as_millis() as u64, "state finished");
+		self.send_shares(msg)
 	}
 
 	pub fn input_sharing_sh(&mut self, input: &mut Vec<u64>) -> Result<usize, ClientError> {
@@ -427,7 +542,7 @@ impl Client{
 	*/
 		assert!(input.len() == self.vectorSize);
 
-		let waitRes = self.state_change_broadcast("IS");
+		let waitRes = self.state_change_broadcast("IS")?;
 		let BENCH_TIMER = Instant::now();
 
 		let sharingParams = match waitRes {
@@ -460,14 +575,70 @@ impl Client{
 			V, L, N
 		);
 		let SHARE_START = Instant::now();
-		let resultMatrix = pss.share(&input);
+		let seeds = self.pick_seeded_peers(&param);
+		let mut resultMatrix = match seeds.len() {
+			0 => pss.share(&input),
+			_ => pss.share_seeded(&input, &seeds),
+		};
+		self.tamper_shares(&mut resultMatrix, &seeds, P);
 		assert!(resultMatrix.len() == N);
 		assert!(resultMatrix[0].len() == B);
-		println!("{:?} sharing time {:?}", self.ID, SHARE_START.elapsed().as_millis());
+		debug!(elapse_ms = SHARE_START.elapsed().as_millis() as u64, "shares computed");
+		let msg = self.encrypt_shares(&resultMatrix, &seeds, P);
+		self.param = Some(param);
+		info!(state = 3, elapse_ms = BENCH_TIMER.elapsed().as_millis() as u64, "state finished");
+		self.send_shares(msg)
+	}
+
+	fn tamper_shares(&self, resultMatrix: &mut Vec<Vec<u64>>, seeds: &Vec<(usize, [u8; SEED_LEN])>, P: u64) {
+		let idx = self.shareOrder.iter().position(|s| s == &*self.publicKey.to_bytes()).unwrap();
+		let seeded: Vec<usize> = seeds.iter().map(|(j, _)| *j).collect();
+		self.behaviour.tamper_shares(resultMatrix, idx, &seeded, P);
+	}
+
+	fn send_shares(&self, msg: Vec<Vec<u8>>) -> Result<usize, ClientError> {
+		if self.behaviour.replays() {
+			if send_vecs(&self.sender, &self.session, self.round, msg.clone()).is_err() {
+				return Err(ClientError::SendFailure(3));
+			}
+		}
+		match send_vecs(&self.sender, &self.session, self.round, msg) {
+			Ok(_) => Ok(3),
+			Err(_) => Err(ClientError::SendFailure(3)),
+		}
+	}
+
+	fn pick_seeded_peers(&self, param: &Param) -> Vec<(usize, [u8; SEED_LEN])> {
+	/*
+			Shares of up to D2 - L peers can be PRG expansions,
+			take the peers right after us in shareOrder
+			so the seeded ones differ from sender to sender
+	*/
+		let mut seeds = Vec::new();
+		if !self.seedCompression {
+			return seeds;
+		}
+		let N = self.shareOrder.len();
+		let idx = self.shareOrder.iter().position(|s| s == &*self.publicKey.to_bytes()).unwrap();
+		let mut i = (idx + 1) % N;
+		while seeds.len() < param.D2 - param.L && i != idx {
+			let mut seed = [0u8; SEED_LEN];
+			OsRng.fill_bytes(&mut seed);
+			seeds.push((i, seed));
+			i = (i + 1) % N;
+		}
+		seeds
+	}
+
+	fn encrypt_shares(&self, resultMatrix: &Vec<Vec<u64>>,
+		seeds: &Vec<(usize, [u8; SEED_LEN])>, P: u64) -> Vec<Vec<u8>> {
 	/*
 		 	Encrypt shares for each DH sharedKey
 			send [shares_c1, shares_c2, ....  ]
+			plaintext is [SHARE_SEED, seed] for seeded peers
+			and [SHARE_EXPLICIT, encoded shares] for everyone else
 	*/
+		let codec = ShareCodec::new(P);
 		let mut msg = Vec::new();
 		for (i, pk) in self.shareOrder.iter().enumerate() {
 
@@ -475,23 +646,45 @@ impl Client{
 			let k = GenericArray::from_slice(&shareKey);
 			let cipher = Aes256Gcm::new(k);
 			let nonce = GenericArray::from_slice(b"unique nonce"); //Self.pk
-			let mut shareBytes = Vec::new();
-			for r in &resultMatrix[i] {
-				shareBytes.extend(&r.to_le_bytes());
-			}
+			let shareBytes = match seeds.iter().find(|(j, _)| *j == i) {
+				Some((_, seed)) => {
+					let mut b = vec![SHARE_SEED];
+					b.extend(seed);
+					b
+				},
+				None => {
+					let mut b = vec![SHARE_EXPLICIT];
+					b.extend(codec.encode(&resultMatrix[i]));
+					b
+				},
+			};
 			let encryptedShares = cipher.encrypt(nonce, shareBytes
 										.as_slice())
 		    					   		.expect("encryption failure!");
 		    msg.push(encryptedShares);
 		}
-		self.param = Some(param);
-		println!("State 3 elapse {:?}ms ({})", BENCH_TIMER.elapsed().as_millis(), self.ID);
-		match send_vecs(&self.sender, msg) {
-			Ok(_) => {
-				return Ok(3)
+		msg
+	}
+
+	fn share_len(&self) -> usize {
+	/*
+			Blocks in each share vector
+			semi-honest: V/L
+			malicious: (2V + L + Y + LSB + 3L)/L
+	*/
+		let V = self.vectorSize;
+		let L = self.param.unwrap().L;
+		match self.inputBitLimit {
+			Some(S) => {
+				let B = V/L;
+				let Y = (
+					((2f32*(S as f32) + (V as f32).log2().ceil())/
+					(L as f32)).ceil()*
+					(L as f32)) as usize;
+				(2*V + L + Y + L*S*B + 3*L)/L
 			},
-			Err(_) => return Err(ClientError::SendFailure(3)),
-		};
+			None => V/L,
+		}
 	}
 
 	pub fn shares_recieving(&mut self) -> Result<usize, ClientError> {
@@ -502,45 +695,90 @@ impl Client{
 			Then add to sum
 	*/
 		let N = self.shareKeys.len();
+		let P = self.param.unwrap().P as u64;
+		let codec = ShareCodec::new(P);
+		let shareLen = self.share_len();
 		let mut cnt = 0;
+		let mut dropped: Option<Vec<u64>> = None;
 		self.shares = vec![vec![0u64]; N];
 		loop {
+			if let Some(reason) = self.aborted() {
+				return Err(ClientError::Aborted(reason));
+			}
 			match self.subRx.try_recv() {
-				Ok(dropouts) => {
+				Ok((session, round, dropouts)) => {
 					/* server broadcast dropouts
-					break from waiting for shares...
+					drop their shares, one excluded for a replay did send them,
+					then wait for the rest only, some may still be on the way
 					*/
-					if dropouts.len() == 0 {continue;}
-					for d in dropouts {
-						assert!(self.shares[d as usize] == vec![0u64]);
+					if session != self.session || round != self.round || dropouts.len() == 0 {continue;}
+					for &d in dropouts.iter() {
+						self.shares[d as usize] = vec![0u64];
 					}
-					break;
+					dropped = Some(dropouts);
 				},
 				Err(_) => (),
 			};
-			let mut item = [self.sender.as_poll_item(zmq::POLLIN)];
-	        zmq::poll(&mut item, 2).unwrap();
-	        if item[0].is_readable() {
-	        	let msg = recv(&self.sender);
+			let frames = match self.sender.recv(Some(Duration::from_millis(2))) {
+				Ok(f) => f,
+				Err(_) => return Err(ClientError::TransportFail(4)),
+			};
+	        if let Some(frames) = frames {
+	        	let msg = parse(frames);
 	        	match msg {
-	        	 	RecvType::matrix(m) => {
-	        	 		assert!(m.len() == 2);
-	        	 		let idx = self.shareOrder.iter().position(|s| s == &m[0]).unwrap();
+	        	 	// m = [round, senderPk, Enc(share)], anything else comes from a broken or cheating peer
+	        	 	RecvType::matrix(m) if m.len() != 3 || m[0].len() != 8 => {
+	        	 		warn!(frames = m.len(), "malformed share message");
+	        	 		return Err(ClientError::UnexpectedRecv(RecvType::matrix(m)));
+	        	 	},
+	        	 	RecvType::matrix(mut m) => {
+	        	 		if read_le_u64(m.remove(0))[0] as usize != self.round {
+	        	 			continue;
+	        	 		}
+	        	 		let idx = match self.shareOrder.iter().position(|s| s == &m[0]) {
+	        	 			Some(i) => i,
+	        	 			None => {
+	        	 				warn!("share from an unknown client");
+	        	 				return Err(ClientError::UnidentifiedShare(4));
+	        	 			},
+	        	 		};
+	        	 		if dropped.as_ref().map_or(false, |d| d.contains(&(idx as u64))) {
+	        	 			continue;
+	        	 		}
 	        	 		let cipher = match self.shareKeys.get(&m[0]) {
 	        	 			Some(c) => {
 								let k = GenericArray::from_slice(c);
 								Aes256Gcm::new(k)
 	        	 			},
 	        	 			None => {
-	        	 				println!("fail get client");
+	        	 				warn!("share from an unknown client");
 	        	 				return Err(ClientError::UnidentifiedShare(4));
 	        	 			},
 	        	 		};
 						let nonce = GenericArray::from_slice(b"unique nonce");
 			 			let plaintext = match cipher.decrypt(nonce, m[1].as_ref()) {
-			 				Ok(p) => read_le_u64(p),
+			 				Ok(p) if p.len() == 1 + SEED_LEN && p[0] == SHARE_SEED => {
+			 					let mut seed = [0u8; SEED_LEN];
+			 					seed.copy_from_slice(&p[1..]);
+			 					expand_seed(&seed, P, shareLen)
+			 				},
+			 				Ok(p) if p.len() > 1 && p[0] == SHARE_EXPLICIT => match codec.decode(&p[1..]) {
+			 					Ok(s) if s.len() == shareLen => s,
+			 					Ok(s) => {
+			 						warn!(len = s.len(), expected = shareLen, "share of the wrong length");
+			 						return Err(ClientError::DecodeFail(4));
+			 					},
+			 					Err(e) => {
+			 						warn!(error = ?e, "cannot decode share");
+			 						return Err(ClientError::DecodeFail(4));
+			 					}
+			 				},
+			 				Ok(_) => {
+			 					warn!("cannot decode share");
+			 					return Err(ClientError::DecodeFail(4));
+			 				},
 			 				Err(_) => {
-			 					println!("fail decrypt");
+			 					warn!("cannot decrypt share");
 			 					return Err(ClientError::EncryptionError(4));
 			 				}
 			 			};
@@ -548,15 +786,21 @@ impl Client{
 			 			self.shares[idx] = plaintext;
 			 			cnt += 1;
 	        	 	},
+	        	 	// late ack of last round's aggregated shares
+	        	 	RecvType::string(s) if s.starts_with("Your") => debug!(reply = %s, "server ack"),
 	        	 	_ => return Err(ClientError::UnexpectedRecv(msg)),
 	        	 };
 	        };
-	        // stop when recv shares from each peer
-			if(cnt == N){
+	        // stop when recv shares from each peer, or each that didn't drop out
+			let done = match &dropped {
+				Some(d) => (0..N).all(|i| d.contains(&(i as u64)) || self.shares[i] != vec![0u64]),
+				None => cnt == N,
+			};
+			if done {
 	        	break;
 	        }
 		}
-		println!("State 4 elapse {:?}ms ({})", BENCH_TIMER.elapsed().as_millis(), self.ID);
+		info!(state = 4, elapse_ms = BENCH_TIMER.elapsed().as_millis() as u64, "state finished");
 		Ok(3)
 	}
 
@@ -579,7 +823,7 @@ impl Client{
 			(L as f32)) as usize;
 
 		let idx = self.shareOrder.iter().position(|s| s == &*self.publicKey.to_bytes()).unwrap();
-		let waitRes = self.state_change_broadcast("EC");
+		let waitRes = self.state_change_broadcast("EC")?;
 		let BENCH_TIMER = Instant::now();
 
 		let mut dropouts;
@@ -602,7 +846,7 @@ impl Client{
 			},
 			_ => return Err(ClientError::UnexpectedRecv(waitRes)),
 		};
-		println!("{:?} recv dropout {:?}", self.ID, dropouts);
+		info!(dropouts = ?dropouts, "EC dropouts");
 
 	/*
 			Comput tests only for those who didn't dropout
@@ -620,17 +864,16 @@ impl Client{
 		for i in 0..N {
 			let mut tests = Vec::new();
 			if dropouts.contains(&(i as u64)) {
-				println!("{:?}", self.shares[i]);
+				trace!(client = i, share = ?self.shares[i], "share of a dropout");
 			}
-			if !dropouts.contains(&(i as u64)) || self.shares[i] != vec![0u64] {
+			if !dropouts.contains(&(i as u64)) {
 				tests = vec![0u64; 3];
 
 				// Degree Test
 				let mut DT = 0u128;
 				for j in 0..(2*V + L + Y + L*S*B + 3*L)/L {
 					// r * each share
-					DT += ((degree_rand[j] as u128) * (self.shares[i][j] as u128) % P);
-					DT %= P;
+					DT = DT.add_mod((degree_rand[j] as u128).mul_mod(self.shares[i][j] as u128, P), P);
 				}
 				tests[0] = (DT as u64).try_into().unwrap();
 		// _________________________________________________________
@@ -640,8 +883,8 @@ impl Client{
 				for j in 0..B*S {
 					// r * (x_bit * (1 - x_bit))
 					let x_bit = self.shares[i][(2*V + L + Y)/L + j] as u128;
-					IBTT += (x_bit * (1 + P - x_bit) % P) * (input_bit_rand[j] as u128) % P;
-					IBTT %= P;
+					let t = x_bit.mul_mod(1u128.sub_mod(x_bit, P), P);
+					IBTT = IBTT.add_mod(t.mul_mod(input_bit_rand[j] as u128, P), P);
 				}
 
 				// Quadratic Test
@@ -650,8 +893,8 @@ impl Client{
 					// r * (x^2 - y)
 					let x = self.shares[i][j] as u128;
 					let y = self.shares[i][V/L + j] as u128;
-					QT += ((x * x) % P + P - y) * (quadratic_rand[j] as u128) % P;
-					QT %= P;
+					let t = x.mul_mod(x, P).sub_mod(y, P);
+					QT = QT.add_mod(t.mul_mod(quadratic_rand[j] as u128, P), P);
 				}
 
 				// L2-norm bit test
@@ -659,14 +902,15 @@ impl Client{
 				// r * (ySum_bits * (1 - ySum_bits))
 				for j in 0..Y/L {
 					let ySum_bits = self.shares[i][(2*V + L)/L + j] as u128;
-					L2NBTT += (ySum_bits * (1 + P - ySum_bits) % P) * (l2_norm_bit_rand[j] as u128) % P;
-					L2NBTT %= P;
+					let t = ySum_bits.mul_mod(1u128.sub_mod(ySum_bits, P), P);
+					L2NBTT = L2NBTT.add_mod(t.mul_mod(l2_norm_bit_rand[j] as u128, P), P);
 				}
 
 			/*
 			sum of three tests + randomness A generated by Party i (all 0)
 			*/
-				let sumA = ((IBTT + QT + L2NBTT) % P + self.shares[i][(2*V + L + Y + L*S*B + 1*L)/L] as u128) % P;
+				let sumA = IBTT.add_mod(QT, P).add_mod(L2NBTT, P)
+					.add_mod(self.shares[i][(2*V + L + Y + L*S*B + 1*L)/L] as u128, P);
 				tests[1] = (sumA as u64).try_into().unwrap();
 
 		// _________________________________________________________
@@ -679,11 +923,9 @@ impl Client{
 					let mut sumX_bit = 0u128;
 					for k in 0..S {
 						let x_bit = self.shares[i][(2*V + L + Y + j*S*L)/L + k] as u128;
-						sumX_bit += x_bit * 2u128.pow(k as u32) % P;
-						sumX_bit %= P;
+						sumX_bit = sumX_bit.add_mod(x_bit.mul_mod(2u128.pow(k as u32) % P, P), P);
 					}
-					IBDT += (sumX_bit + P - x) * (input_bound_rand[j] as u128) % P;
-					IBDT %= P;
+					IBDT = IBDT.add_mod(sumX_bit.sub_mod(x, P).mul_mod(input_bound_rand[j] as u128, P), P);
 				}
 
 				// L2-norm sum test
@@ -691,11 +933,10 @@ impl Client{
 				let mut sumY = 0u128;
 				for j in 0..B {
 					let y = self.shares[i][V/L + j] as u128;
-					sumY += y % P;
+					sumY = sumY.add_mod(y, P);
 				}
 				// r * (sum(y) - ySum)
-				//println!("at 698 {:?} Calculate #{:?}", self.ID, i);
-				L2NST = (sumY + P - self.shares[i][(2*V)/L] as u128) * (l2_norm_sum_rand[0] as u128) % P;
+				L2NST = sumY.sub_mod(self.shares[i][(2*V)/L] as u128, P).mul_mod(l2_norm_sum_rand[0] as u128, P);
 
 				// L2-norm bound test
 				let mut L2NBDT;
@@ -704,21 +945,22 @@ impl Client{
 				let mut share_sum = 0u128;
 				for j in 0..Y/L {
 					let ySum_bits = self.shares[i][(2*V + L)/L + j] as u128;
-					share_sum += (ySum_bits * l2_norm_bound_shares[idx*(Y/L)+j] as u128) % P;
-					share_sum %= P;
+					share_sum = share_sum.add_mod(ySum_bits.mul_mod(l2_norm_bound_shares[idx*(Y/L)+j] as u128, P), P);
 				}
-				L2NBDT = (ySum + P - share_sum) % P * (l2_norm_bound_rand[0] as u128) % P;
+				L2NBDT = ySum.sub_mod(share_sum, P).mul_mod(l2_norm_bound_rand[0] as u128, P);
 
 			/*
 			sum of three tests + canceling randomness B generated by Party i (sum to 0)
 			*/
-				let sumB = ((IBDT + L2NST + L2NBDT) % P + self.shares[i][(2*V + L + Y + L*S*B + 2*L)/L] as u128) % P;
+				let sumB = IBDT.add_mod(L2NST, P).add_mod(L2NBDT, P)
+					.add_mod(self.shares[i][(2*V + L + Y + L*S*B + 2*L)/L] as u128, P);
 				tests[2] = (sumB as u64).try_into().unwrap();
+				self.behaviour.tamper_tests(&mut tests, P as u64);
 			}
 			msg[i] = write_u64_le_u8(tests.as_slice()).to_vec();
 		}
-		println!("State 5 elapse {:?}ms ({})", BENCH_TIMER.elapsed().as_millis(), self.ID);
-		match send_vecs(&self.sender, msg) {
+		info!(state = 5, elapse_ms = BENCH_TIMER.elapsed().as_millis() as u64, "state finished");
+		match send_vecs(&self.sender, &self.session, self.round, msg) {
 			Ok(_) => {
 				return Ok(5)
 			},
@@ -726,7 +968,7 @@ impl Client{
 		};
 	}
 
-	pub fn aggregation(&self) -> Result<usize, ClientError> {
+	pub fn aggregation(&mut self) -> Result<usize, ClientError> {
 	/*
 		 	N*N shares
 		 	Skip the rows of Ci who dropouts or fail
@@ -736,55 +978,146 @@ impl Client{
 		let L = self.param.unwrap().L;
 		let B = V/L;
 		let P = self.param.unwrap().P;
-		let waitRes = self.state_change_broadcast("AG");
+		let waitRes = self.state_change_broadcast("AG")?;
 		let BENCH_TIMER = Instant::now();
+		// m = [[dropouts], [magic], [another round follows]]
 		let dropouts = match waitRes {
-			RecvType::matrix(m) => read_le_u64(m[0].clone()),
+			RecvType::matrix(m) => {
+				self.moreRounds = m.len() > 2 && m[2] == vec![1u8];
+				read_le_u64(m[0].clone())
+			},
 			_ => return Err(ClientError::UnexpectedRecv(waitRes)),
 		};
-		println!("{:?} aggregation, skipping {:?}", self.ID, dropouts);
+		info!(dropouts = ?dropouts, "aggregating, skipping dropouts");
 		let mut aggregation = vec![0u64; B];
 		for i in 0..self.shares.len() {
 			if !dropouts.contains(&(i as u64)) {
 				for j in 0..B {
-					aggregation[j] = ((aggregation[j] as u128 + self.shares[i][j] as u128) % P) as u64;
+					aggregation[j] = (aggregation[j] as u128).add_mod(self.shares[i][j] as u128, P) as u64;
 				}
 			}
 		}
+		self.behaviour.tamper_aggregate(&mut aggregation, P as u64);
 		//println!("{} sending aggregation[0] {:?}, len {}", self.ID, aggregation[0], aggregation.len());
-		let mut aggregation_bytes = Vec::new();
-		for a in aggregation.iter() {
-			aggregation_bytes.extend(a.to_le_bytes().to_vec());
-		}
-		let msg = vec![
+		let aggregation_bytes = ShareCodec::new(P as u64).encode(&aggregation);
+		// signed with session and round, so it can't be replayed in another one
+		let mut signed = self.session.as_bytes().to_vec();
+		signed.extend(&(self.round as u64).to_le_bytes());
+		signed.extend(&aggregation_bytes);
+		let mut msg = vec![
 			aggregation_bytes.clone(),
-			self.signKey.sign(&aggregation_bytes).as_ref().to_vec()
+			self.signKey.sign(&signed).as_ref().to_vec()
 		];
-		println!("State 6 elapse {:?}ms ({})", BENCH_TIMER.elapsed().as_millis(), self.ID);
-		match send_vecs(&self.sender, msg.clone()) {
+		if self.behaviour.replays_aggregate() && !self.lastAggregate.is_empty() {
+			msg = self.lastAggregate.clone();
+		}
+		self.lastAggregate = msg.clone();
+		info!(state = 6, elapse_ms = BENCH_TIMER.elapsed().as_millis() as u64, "state finished");
+		match send_vecs(&self.sender, &self.session, self.round, msg.clone()) {
 			Ok(_) => (),//println!("{:?} sent input_sharing {:?}", self.ID, msg[0][0]),
 			Err(_) => return Err(ClientError::SendFailure(5)),
 		};
 		return Ok(3);
 	}
 
-	pub fn state_change_broadcast(&self, curState: &str) -> RecvType {
+	pub fn aggregate_result(&self) -> Result<Vec<u64>, ClientError> {
+	/*
+		Wait for the server's RS broadcast of this round
+		msg = [round, Enc(sum), Sig(session | round | sum)]
+		the signature covers the decoded sum as u64 little endian
+		Empty sum means the server couldn't reconstruct
+	*/
+		let waitRes = self.state_change_broadcast("RS")?;
+		let m = match waitRes {
+			RecvType::matrix(m) if m.len() == 3 => m,
+			_ => return Err(ClientError::UnexpectedRecv(waitRes)),
+		};
+		let sum = match ShareCodec::new(self.param.unwrap().P as u64).decode(&m[1]) {
+			Ok(s) => s,
+			Err(e) => {
+				warn!(error = ?e, "cannot decode sum");
+				return Err(ClientError::DecodeFail(6));
+			},
+		};
+		let mut signed = self.session.as_bytes().to_vec();
+		signed.extend(&m[0]);
+		for x in sum.iter() {
+			signed.extend(&x.to_le_bytes());
+		}
+		let signature = match Signature::from_bytes(&m[2]) {
+			Ok(s) => s,
+			Err(_) => return Err(ClientError::InvalidSignature(6)),
+		};
+		match &self.serverVeriKey {
+			Some(vk) if vk.verify(&signed, &signature).is_ok() => (),
+			_ => return Err(ClientError::InvalidSignature(6)),
+		};
+		if sum.is_empty() {
+			return Err(ClientError::NoResult(6));
+		}
+		Ok(sum)
+	}
+
+	pub fn exclusions(&self) -> Result<Option<Vec<u64>>, ClientError> {
+	/*
+		Clients the server excluded after EC this round, None until it's published
+		msg = [round, excluded, report, Sig(session | round | excluded)]
+	*/
+		let topic = session_topic(&self.session, "BL", self.round);
+		let m = match self.buffer.read().unwrap().get(topic.as_bytes()) {
+			Some(RecvType::matrix(m)) if m.len() == 4 => m.clone(),
+			Some(r) => return Err(ClientError::UnexpectedRecv(r.clone())),
+			None => return Ok(None),
+		};
+		let mut signed = self.session.as_bytes().to_vec();
+		signed.extend(&m[0]);
+		signed.extend(&m[1]);
+		let signature = match Signature::from_bytes(&m[3]) {
+			Ok(s) => s,
+			Err(_) => return Err(ClientError::InvalidSignature(4)),
+		};
+		match &self.serverVeriKey {
+			Some(vk) if vk.verify(&signed, &signature).is_ok() => (),
+			_ => return Err(ClientError::InvalidSignature(4)),
+		};
+		Ok(Some(read_le_u64(m[1].clone())))
+	}
+
+	pub fn aborted(&self) -> Option<String> {
+	/*
+		Server gave up on this round, msg = [reason]
+	*/
+		let topic = session_topic(&self.session, "AB", self.round);
+		match self.buffer.read().ok()?.get(topic.as_bytes()) {
+			Some(RecvType::string(s)) => Some(s.clone()),
+			Some(_) => Some("Abort".to_string()),
+			None => None,
+		}
+	}
+
+	pub fn state_change_broadcast(&self, curState: &str) -> Result<RecvType, ClientError> {
 	/*
 		When state change
 		loops till recieving information from subscriber buffer
+		or the server aborts
 	*/
 		//println!("{} waiting in {} ....", self.ID, curState);
-		let curState = curState.as_bytes().as_ref();
+		let topic = session_topic(&self.session, curState, self.round);
+		let curState = topic.as_bytes();
 		loop {
 			match self.buffer.read() {
 				Ok(guard) => {
 					match guard.get(curState) {
-						Some(m) => return m.clone(),
-						None => sleep(Duration::from_millis(5)),
+						Some(m) => return Ok(m.clone()),
+						None => (),
 					}
 				},
 				Err(_) => continue,
 			};
+			if let Some(reason) = self.aborted() {
+				return Err(ClientError::Aborted(reason));
+			}
+			sleep(Duration::from_millis(5));
 		}
 	}
 }
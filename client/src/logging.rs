@@ -0,0 +1,58 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Serialize, Deserialize};
+use tracing_subscriber::EnvFilter;
+
+/*
+	Logging through tracing
+	Level comes from log_level, RUST_LOG overrides it (e.g. "server=trace")
+	Redaction policy: shares, keys, inputs, corrections and sums are
+	only ever logged at trace level, everything above gets sizes and counts
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+	Text,
+	Json,		// one JSON object per line, for log pipelines
+}
+
+impl Default for LogFormat {
+	fn default() -> LogFormat {
+		LogFormat::Text
+	}
+}
+
+impl FromStr for LogFormat {
+	type Err = String;
+	fn from_str(s: &str) -> Result<LogFormat, String> {
+		match s {
+			"text" => Ok(LogFormat::Text),
+			"json" => Ok(LogFormat::Json),
+			_ => Err(format!("unknown log format {}, use text or json", s)),
+		}
+	}
+}
+
+impl fmt::Display for LogFormat {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			LogFormat::Text => write!(f, "text"),
+			LogFormat::Json => write!(f, "json"),
+		}
+	}
+}
+
+pub fn init(format: LogFormat, level: &str) {
+/*
+	Global subscriber, call once from main
+	Library callers may install their own instead
+*/
+	let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(level));
+	let builder = tracing_subscriber::fmt().with_env_filter(filter).with_thread_names(true);
+	match format {
+		LogFormat::Text => builder.init(),
+		LogFormat::Json => builder.json().with_current_span(true).with_span_list(true).init(),
+	}
+}
@@ -5,117 +5,122 @@
 #![allow(unused_must_use)]
 
 use std::str;
-use std::env;
+use std::process;
 use std::time::{Duration, Instant};
 
 
-use zmq::SNDMORE;
+use structopt::StructOpt;
 use rand_core::{RngCore, OsRng};
 use rand::{thread_rng, Rng};
+use p256::ecdsa::{SigningKey, VerifyKey};
+use tracing::{info, warn};
 
 use client::*;
+use client::config::*;
 
 fn main() {
 
-	let args: Vec<String> = env::args().collect();
-	println!("{:?}", args);
-	let V = args[2].parse::<usize>().unwrap();
-	let malicious = args[3].parse::<bool>().unwrap();
- 
-	let mut client;
-	match malicious {
-		true => {
-			// malicious with port
-		    if args.len() == 8 {
-		        client = Client::new(
-		        	&args[1],								// client name
-		        	V,								 		// vector size
-		        	Some(args[4].parse::<usize>().unwrap()),// input bit limit
-		        	Some(&args[5]),							// IP
-		        	args[6].parse::<usize>().unwrap(), 		// msg port
-		        	args[7].parse::<usize>().unwrap()		// broadcasting port
-		        );
-		    } 
-		    // malicious without port 
-		    else if args.len() == 5 {
-		        client = Client::new(
-		        	&args[1],								// client name
-		        	V,								 		// vector size
-		        	Some(args[4].parse::<usize>().unwrap()),// input bit limit
-		        	None,									// IP
-		        	8888, 									// msg port
-		        	9999									// broadcasting port
-		        );
-		    } else {
-		    	panic!("Wrong Arguments!");
-		    }
+	/*
+		Config file and/or named flags, see --help
+	*/
+	let opt = ClientOpt::from_args();
+	let cfg = match ClientConfig::from_opt(&opt) {
+		Ok(c) => c,
+		Err(e) => {
+			eprintln!("{}", e);
+			process::exit(2);
 		},
-		false => {
-			// semi-honest with port
-		    if args.len() == 7 {
-		        client = Client::new(
-		        	&args[1],								// client name
-		        	V, 										// vector size
-		        	None,
-		        	Some(&args[4]),							// IP
-		        	args[5].parse::<usize>().unwrap(), 		// msg port
-		        	args[6].parse::<usize>().unwrap()		// broadcasting port
-		        );
-		    } 
-		    // semi-honest without port
-		    else if args.len() == 4 {
-		        client = Client::new(
-		        	&args[1],								// client name
-		        	V, 										// vector size
-		        	None,
-		        	None,									// IP
-		        	8888, 									// msg port
-		        	9999									// broadcasting port
-		        );
-		    } else {
-		    	panic!("Wrong Arguments!");
-		    }
+	};
+	if opt.print_config {
+		print!("{}", cfg.to_toml());
+		if let Err(e) = cfg.validate() {
+			eprintln!("{}", e);
+			process::exit(2);
+		}
+		return;
+	}
+	let signKey = match cfg.load_or_create_key() {
+		Ok(k) => k,
+		Err(e) => {
+			eprintln!("{}", e);
+			process::exit(2);
 		},
+	};
+	if opt.print_verify_key {
+		match &signKey {
+			Some(k) => {
+				let vk = VerifyKey::from(&SigningKey::new(k).unwrap());
+				println!("{}", hex::encode(VerifyKey::to_encoded_point(&vk, true).to_bytes()));
+			},
+			None => eprintln!("--print-verify-key needs --key-file"),
+		}
+		return;
+	}
+	if let Err(e) = cfg.validate() {
+		eprintln!("{}", e);
+		process::exit(2);
+	}
+	logging::init(cfg.log_format, &cfg.log_level);
+	let V = cfg.vector_len;
+	let malicious = cfg.malicious;
+	let mut client = cfg.build_client();
+	if let Some(k) = signKey {
+		client.set_sign_key(&k).unwrap();
 	}
 
     let BENCH_TIMER = Instant::now();
 
-    client.handshake().unwrap();
+    let span = client.span();
+    let entered = span.enter();
+    client.handshake(&cfg.session).unwrap();
     client.key_exchange().unwrap();
+    drop(entered);
 
-	let mut input = Vec::<u64>::new();
 	let mut rng = thread_rng();
+	loop {
+		let span = client.span();
+		let _entered = span.enter();
+		let mut input = Vec::<u64>::new();
 
-	// Dropouts
-	if rng.gen_range(0, 100) <= 10 {
-		panic!("{:?} dropout!", client.ID);
-	}
+		// Dropouts
+		if rng.gen_range(0, 100) < cfg.dropout_rate {
+			panic!("{:?} dropout!", client.ID);
+		}
 
-	match malicious {
-		true => {
-			let mut inputBitMod = 0;
-			let S = args[4].parse::<usize>().unwrap();
-			for i in 0..S {
-				inputBitMod += 2u64.pow(i as u32);
-			}
-			for i in 0..V {
-				input.push(rng.gen_range(0, 10) % inputBitMod);
-			}
-			client.input_sharing_ml(&mut input).unwrap();
-		    client.shares_recieving().unwrap();
-		    client.error_correction().unwrap();
-		},
-		false => {
-			for i in 0..V {
-				input.push(rng.gen_range(0, 10));
-			}
-			client.input_sharing_sh(&mut input).unwrap();
-			client.shares_recieving().unwrap();
-		},
-	}
+		match malicious {
+			true => {
+				let mut inputBitMod = 0;
+				let S = cfg.input_bit_limit.unwrap();
+				for i in 0..S {
+					inputBitMod += 2u64.pow(i as u32);
+				}
+				for i in 0..V {
+					input.push(rng.gen_range(0, 10) % inputBitMod);
+				}
+				client.input_sharing_ml(&mut input).unwrap();
+			    client.shares_recieving().unwrap();
+			    client.error_correction().unwrap();
+			},
+			false => {
+				for i in 0..V {
+					input.push(rng.gen_range(0, 10));
+				}
+				client.input_sharing_sh(&mut input).unwrap();
+				client.shares_recieving().unwrap();
+			},
+		}
 
-    client.aggregation().unwrap();
-	println!("Total elapse {:?}ms ({})", BENCH_TIMER.elapsed().as_millis(), &args[1]);
+	    client.aggregation().unwrap();
+	    match client.aggregate_result() {
+	    	Ok(sum) => info!(sum = ?sum, "round result"),
+	    	Err(e) => warn!(error = ?e, "no round result"),
+	    };
+	    // Keys are reused, go straight back to input sharing
+	    if !client.next_round() {
+	    	break;
+	    }
+	}
+	info!(id = %cfg.id, elapse_ms = BENCH_TIMER.elapsed().as_millis() as u64, "all rounds done");
 
 }
 
@@ -1,7 +1,7 @@
 use std::str;
-use zmq::Message;
-use zmq::Socket;
-use zmq::SNDMORE;
+use std::convert::TryInto;
+use std::time::Duration;
+use transport::{Transport, Connection, Incoming, Outgoing, Frames};
 
 use crate::*;
 use std::sync::*;
@@ -13,9 +13,13 @@ pub enum RecvType {
 	matrix(Vec<Vec<u8>>),
 }
 
-pub fn recv(socket: &Socket) -> RecvType {
-	let mut data = socket.recv_multipart(0).unwrap();
-	if data.len() == 1 && data[0] == Vec::new() {
+pub fn recv(socket: &dyn Incoming) -> RecvType {
+	// Blocks for the next message, empty if the transport is gone
+	parse(socket.recv(None).ok().flatten().unwrap_or_default())
+}
+
+pub fn parse(mut data: Frames) -> RecvType {
+	if data.len() == 1 && data[0].is_empty() {
 		return RecvType::bytes(Vec::new());
 	}
 	let mut stringRes = String::new();
@@ -40,18 +44,48 @@ pub fn recv(socket: &Socket) -> RecvType {
 	}
 }
 
-pub fn recv_broadcast(socket: &Socket, topic: &str) -> Result<RecvType, ()> {
-	let mut data = match socket.recv_multipart(0) {
-		Ok(msg) => msg,
-		Err(_) => panic!("Failed to recieve braoadcast."),
+// What the server signs for a broadcast: topic, seq, then each frame length-prefixed
+pub fn broadcast_bytes(topic: &[u8], seq: &[u8], data: &[Vec<u8>]) -> Vec<u8> {
+	let mut bytes = topic.to_vec();
+	bytes.extend(seq);
+	for d in data {
+		bytes.extend(&(d.len() as u64).to_le_bytes());
+		bytes.extend(d);
+	}
+	bytes
+}
+
+pub fn verify_broadcast(key: &Option<VerifyKey>, data: &[Vec<u8>]) -> bool {
+/*
+	Unsigned or mis-signed broadcasts are dropped,
+	without a server key nothing verifies
+*/
+	let key = match key {
+		Some(k) => k,
+		None => return false,
 	};
-	if data.len() == 1 && data[0] == Vec::new() {
-		return Ok(RecvType::bytes(Vec::new()));
+	if data.len() < 3 {
+		return false;
 	}
-	let removed = data.remove(0);
-	if (removed != topic.as_bytes()) {
-		println!("recv_broadcast err {:?}, {:?}", str::from_utf8(&removed), topic);
-		return Err(());
+	let signature = match Signature::from_bytes(&data[2]) {
+		Ok(s) => s,
+		Err(_) => return false,
+	};
+	key.verify(&broadcast_bytes(&data[0], &data[1], &data[3..]), &signature).is_ok()
+}
+
+pub fn split_broadcast(mut data: Vec<Vec<u8>>) -> Option<(Vec<u8>, u64, RecvType)> {
+/*
+	Broadcast: [topic, seq, signature, data...]
+*/
+	if data.len() < 3 || data[1].len() != 8 {
+		return None;
+	}
+	let topic = data.remove(0);
+	let seq = u64::from_le_bytes(data.remove(0)[..].try_into().unwrap());
+	data.remove(0);
+	if data.len() == 1 && data[0].is_empty() {
+		return Some((topic, seq, RecvType::bytes(Vec::new())));
 	}
 	let mut stringRes = String::new();
 	let mut isString = true;
@@ -66,71 +100,179 @@ pub fn recv_broadcast(socket: &Socket, topic: &str) -> Result<RecvType, ()> {
 	}
 	if !isString {
 		if data.len() == 1 {
-			return Ok(RecvType::bytes(data.pop().unwrap()));
+			return Some((topic, seq, RecvType::bytes(data.pop().unwrap())));
 		} else {
-			return Ok(RecvType::matrix(data));
+			return Some((topic, seq, RecvType::matrix(data)));
 		}
 	} else {
-		return Ok(RecvType::string(stringRes));
+		return Some((topic, seq, RecvType::string(stringRes)));
 	}
 }
 
-pub fn consume_broadcast(socket: &Socket) -> (Vec<u8>, RecvType) {
-	let mut data = match socket.recv_multipart(0) {
-		Ok(msg) => msg,
-		Err(_) => panic!("Failed to recieve braoadcast."),
-	};
-	let topic = data.remove(0);
-	if data.len() == 1 && data[0] == Vec::new() {
-		return (topic, RecvType::bytes(Vec::new()));
+// Broadcast topics carry session and round, e.g. "modelA/IS-2"
+pub fn session_topic(session: &str, topic: &str, round: usize) -> String {
+	format!("{}/{}-{}", session, topic, round)
+}
+
+pub fn split_topic(topic: &[u8]) -> Option<(&str, &str, usize)> {
+	let t = str::from_utf8(topic).ok()?;
+	let (session, rest) = t.split_once('/')?;
+	let (base, round) = rest.rsplit_once('-')?;
+	Some((session, base, round.parse::<usize>().ok()?))
+}
+
+// Poll timeout of the subscriber, catch-up is asked when idle this long
+const CATCHUP_IDLE_MS: u64 = 1000;
+// Wait for one catch-up reply
+const CATCHUP_REPLY_MS: u64 = 2000;
+// Broadcasts held until the server key is known, later ones are dropped
+const MAX_PENDING: usize = 1024;
+
+fn deliver(buffer: &Arc<RwLock<HashMap<Vec<u8>, RecvType>>>,
+	sender: &mpsc::Sender<(String, usize, Vec<u64>)>, topic: Vec<u8>, data: RecvType) -> Result<(), ClientError> {
+/*
+	Add to buffer if it's new
+	Dropouts go out tagged with their session and round
+*/
+	if buffer.read().unwrap().contains_key(&topic) {
+		return Ok(());
 	}
-	let mut stringRes = String::new();
-	let mut isString = true;
-	for d in &data {
-		match str::from_utf8(d) {
-			Ok(s) => stringRes += s,
-			Err(_) => {
-				isString = false;
-				break
+	match split_topic(&topic) {
+		Some((session, "EC", round)) | Some((session, "AG", round)) => {
+			match data {
+				// m = [[dorpouts], [degree test], [Input Bit test], ....]
+				RecvType::matrix(ref m) => sender.send((session.to_string(), round, read_le_u64(m[0].clone()))),
+				_ => return Err(ClientError::UnexpectedRecv(data)),
+			};
+		},
+		_ => (),
+	}
+	match buffer.write() {
+		Ok(mut guard) => guard.insert(topic, data),
+		Err(_) => return Err(ClientError::MutexLockFail(0)),
+	};
+	Ok(())
+}
+
+fn catch_up(transport: &dyn Transport, req: &mut Box<dyn Connection>, key: &Option<VerifyKey>,
+	buffer: &Arc<RwLock<HashMap<Vec<u8>, RecvType>>>, sender: &mpsc::Sender<(String, usize, Vec<u64>)>,
+	session: &str, next: &mut u64, until: Option<u64>) -> Result<(), ClientError> {
+/*
+	Ask for seq next, next+1, ... up to until (or until the server has no more)
+	A requester that timed out is stuck, make a new one and retry later
+*/
+	while until.map_or(true, |u| *next < u) {
+		req.send(vec![session.as_bytes().to_vec(), next.to_le_bytes().to_vec()]);
+		let reply = match req.recv(Some(Duration::from_millis(CATCHUP_REPLY_MS))) {
+			Ok(Some(r)) => r,
+			_ => {
+				*req = transport.requester().map_err(|_| ClientError::TransportFail(0))?;
+				return Ok(());
 			},
 		};
-	}
-	if !isString {
-		if data.len() == 1 {
-			return (topic, RecvType::bytes(data.pop().unwrap()));
-		} else {
-			return (topic, RecvType::matrix(data));
+		if !verify_broadcast(key, &reply) {
+			return Ok(());
 		}
-	} else {
-		return (topic, RecvType::string(stringRes));
+		match split_broadcast(reply) {
+			Some((topic, _, data)) => deliver(buffer, sender, topic, data)?,
+			None => return Ok(()),
+		};
+		*next += 1;
 	}
+	Ok(())
 }
 
-pub fn sub_task(subscriber: zmq::Socket, 
-	buffer: Arc<RwLock<HashMap<Vec<u8>, RecvType>>>, sender: mpsc::Sender<Vec<u64>>) -> Result<usize, ClientError> {
+// Main thread -> sub_task
+pub enum SubCmd {
+	Watch(String),				// follow this session's seq
+	ServerKey(VerifyKey),		// verify every broadcast from now on
+}
+
+pub fn sub_task(transport: Arc<dyn Transport>, subscriber: Box<dyn Incoming>,
+	buffer: Arc<RwLock<HashMap<Vec<u8>, RecvType>>>, sender: mpsc::Sender<(String, usize, Vec<u64>)>,
+	commands: mpsc::Receiver<SubCmd>) -> Result<usize, ClientError> {
     /*
 		Subscriber thread
-		Keep recieving from socket
-		Consume msg emmited previously, add to buffer if it's new
+		Nothing is verified before the server key is known (handshake),
+		broadcasts are held until then and checked once it's there,
+		so a forged one can't take a topic's place in the buffer
+		Broadcasts failing the server key are dropped before anything else
+		Every broadcast is published once with a per-session seq,
+		for the sessions we watch (the one joined in handshake):
+			seq jumps ahead		fetch the gap from catch-up first
+			nothing for a while	ask catch-up for the next seq,
+								covers the last message of a state being lost
     */
+    let mut req = transport.requester().map_err(|_| ClientError::TransportFail(0))?;
+    let mut next = HashMap::<String, u64>::new();
+    let mut key = None;
+    let mut pending: Vec<Frames> = Vec::new();
     loop {
-        let (topic, data) = consume_broadcast(&subscriber);
-        if buffer.read().unwrap().contains_key(&topic) {
-            continue;
-        }
-        if topic == b"EC" || topic == b"AG" {
-        	match data {
-        		// m = [[dorpouts], [degree test], [Input Bit test], ....]
-        		RecvType::matrix(ref m) => sender.send(read_le_u64(m[0].clone())),
-        		_ => return Err(ClientError::UnexpectedRecv(data)),
-        	};
-        }
-        match buffer.write() {
-            Ok(mut guard) => guard.insert(topic, data),
-            Err(_) => return Err(ClientError::MutexLockFail(0)),
-        };
+    	while let Ok(cmd) = commands.try_recv() {
+    		match cmd {
+    			SubCmd::Watch(session) => { next.entry(session).or_insert(0); },
+    			SubCmd::ServerKey(k) => {
+    				key = Some(k);
+    				for frames in pending.drain(..).collect::<Vec<_>>() {
+    					on_broadcast(&*transport, &mut req, &key, &buffer, &sender, &mut next, frames)?;
+    				}
+    			},
+    		}
+    	}
+    	let frames = match subscriber.recv(Some(Duration::from_millis(CATCHUP_IDLE_MS))) {
+    		Ok(Some(f)) => f,
+    		Ok(None) => {
+    			if key.is_some() {
+		    		for (session, n) in next.iter_mut() {
+		    			catch_up(&*transport, &mut req, &key, &buffer, &sender, session, n, None)?;
+		    		}
+    			}
+	    		continue;
+    		},
+    		Err(_) => panic!("Failed to recieve braoadcast."),
+    	};
+    	if key.is_none() {
+    		if pending.len() < MAX_PENDING {
+    			pending.push(frames);
+    		}
+    		continue;
+    	}
+    	on_broadcast(&*transport, &mut req, &key, &buffer, &sender, &mut next, frames)?;
     }
-    Ok(0)
+}
+
+fn on_broadcast(transport: &dyn Transport, req: &mut Box<dyn Connection>, key: &Option<VerifyKey>,
+	buffer: &Arc<RwLock<HashMap<Vec<u8>, RecvType>>>, sender: &mpsc::Sender<(String, usize, Vec<u64>)>,
+	next: &mut HashMap<String, u64>, frames: Frames) -> Result<(), ClientError> {
+	// One live broadcast, verified, in seq order for watched sessions
+	if !verify_broadcast(key, &frames) {
+		warn!("dropping broadcast with a bad signature");
+		return Ok(());
+	}
+	let (topic, seq, data) = match split_broadcast(frames) {
+		Some(b) => b,
+		None => return Ok(()),
+	};
+	let session = match split_topic(&topic) {
+		Some((session, _, _)) => session.to_string(),
+		None => return Ok(()),
+	};
+	match next.get_mut(&session) {
+		Some(n) => {
+			if seq < *n {
+				return Ok(());
+			}
+			catch_up(transport, req, key, buffer, sender, &session, n, Some(seq))?;
+			if *n < seq {
+				// gap not filled yet, the idle catch-up fetches it in order
+				return Ok(());
+			}
+			deliver(buffer, sender, topic, data)?;
+			*n = seq + 1;
+		},
+		None => deliver(buffer, sender, topic, data)?,
+	}
+	Ok(())
 }
 
 
@@ -138,16 +280,17 @@ pub fn sub_task(subscriber: zmq::Socket,
 	Vec<Vec<u8>>, &Vec<Vec<u8>>, [u8] and &[u8] on heap,
 	Vec<String>, &Vec<String>, Vec<str>, &Vec<str>
 */
-pub fn send_vecs<I, T>(socket: &Socket, data: I) -> Result<&str, &str>
-where 
+pub fn send_vecs<'a, I, T>(socket: &'a dyn Outgoing, session: &str, round: usize, data: I) -> Result<&'a str, &'a str>
+where
     I: IntoIterator<Item = T>,
-    T: Into<Message>,
+    T: AsRef<[u8]>,
 {
-
-    let result = socket.send_multipart(data, 0);
-    match result {
-        Ok(T) => Ok("Sent vector successfully."),
-        Err(Error) => Err("Failed sending vector."),
+    // every message starts with the session and round it belongs to
+    let mut frames = vec![session.as_bytes().to_vec(), (round as u64).to_le_bytes().to_vec()];
+    frames.extend(data.into_iter().map(|d| d.as_ref().to_vec()));
+    match socket.send(frames) {
+        Ok(_) => Ok("Sent vector successfully."),
+        Err(_) => Err("Failed sending vector."),
     }
 }
 
@@ -155,14 +298,9 @@ where
 	<Vec<u8>, &Vec<u8>, u8 and &u8 on heap,
 	String, &String, str, &str
 */    
-pub fn send<T>(socket: &Socket, data: T) -> Result<&str, &str>
+pub fn send<'a, T>(socket: &'a dyn Outgoing, session: &str, round: usize, data: T) -> Result<&'a str, &'a str>
 where
-    T: Into<Message>,
+    T: AsRef<[u8]>,
 {
-    let result = socket.send(data, 0);
-    match result {
-        Ok(T) => Ok("Sent data successfully."),
-        Err(Error) => Err("Failed sending data."),
-    }
+    send_vecs(socket, session, round, vec![data])
 }
-
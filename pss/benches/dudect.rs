@@ -0,0 +1,141 @@
+use pss::*;
+
+use std::hint::black_box;
+use std::process;
+use std::time::Instant;
+use rand::{thread_rng, Rng};
+
+/* dudect-style timing leakage check
+	For each target, time many calls on two classes of secret inputs:
+		class 0: one fixed secret
+		class 1: fresh random secrets
+	interleaved at random, then run Welch's t-test on the two timing sets,
+	also on the sets cropped at a few percentiles to cut scheduler noise.
+	|t| > SUSPECT is worth a rerun, |t| > THRESHOLD means timing
+	depends on the secret (same cut-offs as the dudect paper).
+
+	The ct_* primitives are always checked,
+	FieldOps / ModPow / share / reconstruct only run in constant time with
+		cargo bench --bench dudect --features constant-time
+*/
+
+const P: u64 = 3073700804129980417;
+const SUSPECT: f64 = 4.5;
+const THRESHOLD: f64 = 10.0;
+const CROPS: [f64; 4] = [1.0, 0.9, 0.75, 0.5];
+
+fn measure<I, F>(samples: usize, inner: usize, fixed: I, random: &mut dyn FnMut() -> I, f: F) -> (Vec<f64>, Vec<f64>)
+where I: Clone, F: Fn(&I)
+{
+	let mut rng = thread_rng();
+	let mut times = (Vec::new(), Vec::new());
+	for _ in 0..samples {
+		let class = rng.gen_range(0, 2);
+		let input = if class == 0 { fixed.clone() } else { random() };
+		let start = Instant::now();
+		for _ in 0..inner {
+			f(black_box(&input));
+		}
+		let t = start.elapsed().as_nanos() as f64;
+		if class == 0 { times.0.push(t) } else { times.1.push(t) }
+	}
+	times
+}
+
+fn welch_t(a: &[f64], b: &[f64]) -> f64 {
+	let mean = |v: &[f64]| v.iter().sum::<f64>() / v.len() as f64;
+	let var = |v: &[f64], m: f64| v.iter().map(|x| (x - m) * (x - m)).sum::<f64>() / (v.len() as f64 - 1.0);
+	let (ma, mb) = (mean(a), mean(b));
+	let (va, vb) = (var(a, ma), var(b, mb));
+	(ma - mb) / (va / a.len() as f64 + vb / b.len() as f64).sqrt()
+}
+
+fn crop(v: &[f64], percentile: f64) -> Vec<f64> {
+	let mut sorted = v.to_vec();
+	sorted.sort_by(|x, y| x.partial_cmp(y).unwrap());
+	let cut = sorted[((sorted.len() - 1) as f64 * percentile) as usize];
+	v.iter().cloned().filter(|x| *x <= cut).collect()
+}
+
+fn report(name: &str, times: (Vec<f64>, Vec<f64>)) -> bool {
+	let mut worst = 0f64;
+	for p in CROPS.iter() {
+		let t = welch_t(&crop(&times.0, *p), &crop(&times.1, *p));
+		if t.abs() > worst.abs() {
+			worst = t;
+		}
+	}
+	let leaks = worst.abs() > THRESHOLD;
+	let verdict = if leaks {
+		"LEAKS"
+	} else if worst.abs() > SUSPECT {
+		"suspect"
+	} else {
+		"ok"
+	};
+	println!("{:<16} max |t| = {:>8.2}  {}", name, worst.abs(), verdict);
+	leaks
+}
+
+fn main() {
+	let mut rng = thread_rng();
+	let mut leaks = false;
+	let y = rng.gen_range(0, P);
+
+	leaks |= report("ct_sub_mod", measure(100_000, 16, 0u64,
+		&mut || rng.gen_range(0, P),
+		|x| { black_box(x.ct_sub_mod(y, P)); })
+	);
+
+	let mut rng = thread_rng();
+	leaks |= report("ct_mul_mod", measure(50_000, 8, 0u128,
+		&mut || rng.gen_range(0, P) as u128,
+		|x| { black_box(x.ct_mul_mod(y as u128, P as u128)); })
+	);
+
+	let mut rng = thread_rng();
+	leaks |= report("ct_pow_mod", measure(5_000, 1, 0u128,
+		&mut || rng.gen_range(0, P) as u128,
+		|e| { black_box((y as u128).ct_pow_mod(*e, P as u128)); })
+	);
+
+	let mut rng = thread_rng();
+	leaks |= report("mul_mod", measure(50_000, 8, 0u128,
+		&mut || rng.gen_range(0, P) as u128,
+		|x| { black_box(x.mul_mod(y as u128, P as u128)); })
+	);
+
+	let mut rng = thread_rng();
+	leaks |= report("modpow", measure(5_000, 1, 0u128,
+		&mut || rng.gen_range(0, P) as u128,
+		|e| { black_box((y as u128).modpow(*e, P as u128)); })
+	);
+
+	/* Whole sharing paths on small parameters
+		d2 = 8, d3 = 27, 4 secrets per block
+	*/
+	let p = P as u128;
+	let r2 = (437380319823159113u128 * 437380319823159113u128) % p;
+	let r3 = 1697820560572790570u128;
+	let pss = PackedSecretSharing::new(p, r2, r3, 8, 27, 32, 4, 26);
+	let mut points = Vec::new();
+	for i in 1..27 {
+		points.push(r3.modpow(i as u128, p) as u64);
+	}
+
+	let mut rng = thread_rng();
+	leaks |= report("share", measure(2_000, 1, vec![0u64; 32],
+		&mut || (0..32).map(|_| rng.gen_range(0, P)).collect::<Vec<u64>>(),
+		|s| { black_box(pss.clone().share(s)); })
+	);
+
+	let mut rng = thread_rng();
+	leaks |= report("reconstruct2", measure(1_000, 1, vec![vec![0u64; 8]; 26],
+		&mut || (0..26).map(|_| (0..8).map(|_| rng.gen_range(0, P)).collect()).collect::<Vec<Vec<u64>>>(),
+		|s| { black_box(pss.reconstruct2(s, &points)); })
+	);
+
+	if leaks {
+		process::exit(1);
+	}
+}
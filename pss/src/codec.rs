@@ -0,0 +1,194 @@
+use alloc::vec::Vec;
+
+/* Wire encoding for shares
+	Every share is a field element smaller than the prime,
+	so instead of 8 little-endian bytes per element
+	we pack each one at the exact bit width of the prime.
+
+	Encoded format:
+	   [tag, count (LEB128), payload...]
+
+	tag PACKED: payload is count * bits, LSB first, last byte zero-padded
+	tag VARINT: payload is count LEB128 integers,
+				cheaper for small values such as aggregate results
+*/
+
+const PACKED: u8 = 0;
+const VARINT: u8 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CodecError {
+	Truncated(usize),
+	UnknownTag(u8),
+	OutOfRange(usize),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ShareCodec {
+	prime: u64,
+	bits: u32,
+}
+
+impl ShareCodec {
+
+	pub fn new(prime: u64) -> ShareCodec {
+		assert!(prime > 1);
+		ShareCodec {
+			prime,
+			bits: 64 - (prime - 1).leading_zeros(),
+		}
+	}
+
+	pub fn bits(&self) -> u32 {
+		self.bits
+	}
+
+	// Number of bytes taken by n packed elements, header excluded, None on overflow
+	pub fn packed_len(&self, n: usize) -> Option<usize> {
+		let bits = n.checked_mul(self.bits as usize)?;
+		Some(bits / 8 + (bits % 8 != 0) as usize)
+	}
+
+	// Elements at or past the prime are reduced first, the same field element either way
+	pub fn encode(&self, elems: &[u64]) -> Vec<u8> {
+		let mut out = Vec::with_capacity(1 + 10 + self.packed_len(elems.len()).unwrap_or(0));
+		out.push(PACKED);
+		write_varint(&mut out, elems.len() as u64);
+
+		let mut acc = 0u128;
+		let mut accBits = 0u32;
+		for e in elems {
+			acc |= ((*e % self.prime) as u128) << accBits;
+			accBits += self.bits;
+			while accBits >= 8 {
+				out.push(acc as u8);
+				acc >>= 8;
+				accBits -= 8;
+			}
+		}
+		if accBits > 0 {
+			out.push(acc as u8);
+		}
+		out
+	}
+
+	pub fn encode_varint(&self, elems: &[u64]) -> Vec<u8> {
+		let mut out = Vec::with_capacity(1 + 10 + elems.len());
+		out.push(VARINT);
+		write_varint(&mut out, elems.len() as u64);
+		for e in elems {
+			write_varint(&mut out, *e % self.prime);
+		}
+		out
+	}
+
+	// Pick whichever of the two encodings is shorter
+	pub fn encode_compact(&self, elems: &[u64]) -> Vec<u8> {
+		let mut varintLen = 0;
+		for e in elems {
+			varintLen += varint_len(*e % self.prime);
+		}
+		let shorter = match self.packed_len(elems.len()) {
+			Some(p) => varintLen < p,
+			None => true,
+		};
+		if shorter {
+			self.encode_varint(elems)
+		} else {
+			self.encode(elems)
+		}
+	}
+
+	pub fn decode(&self, bytes: &[u8]) -> Result<Vec<u64>, CodecError> {
+		if bytes.is_empty() {
+			return Err(CodecError::Truncated(0));
+		}
+		let mut pos = 1;
+		let n = read_varint(bytes, &mut pos)?;
+		/* The count is untrusted, it can't be more than
+		what the remaining bytes hold: bits each packed, a byte each as varint
+		check before allocating anything for it
+		*/
+		let left = bytes.len() - pos;
+		let fits = match bytes[0] {
+			PACKED => (left as u64) * 8 / self.bits as u64,
+			VARINT => left as u64,
+			t => return Err(CodecError::UnknownTag(t)),
+		};
+		if n > fits {
+			return Err(CodecError::Truncated(bytes.len()));
+		}
+		let n = n as usize;
+		let mut res = Vec::with_capacity(n);
+		match bytes[0] {
+			PACKED => {
+				let mask = self.mask();
+				let mut acc = 0u128;
+				let mut accBits = 0u32;
+				for _ in 0..n {
+					while accBits < self.bits {
+						acc |= (bytes[pos] as u128) << accBits;
+						pos += 1;
+						accBits += 8;
+					}
+					res.push((acc as u64) & mask);
+					acc >>= self.bits;
+					accBits -= self.bits;
+				}
+			},
+			VARINT => {
+				for _ in 0..n {
+					res.push(read_varint(bytes, &mut pos)?);
+				}
+			},
+			t => return Err(CodecError::UnknownTag(t)),
+		}
+		/* Anything at or above the prime
+		is not a field element, reject rather than reduce
+		*/
+		for (i, e) in res.iter().enumerate() {
+			if *e >= self.prime {
+				return Err(CodecError::OutOfRange(i));
+			}
+		}
+		Ok(res)
+	}
+
+	fn mask(&self) -> u64 {
+		if self.bits == 64 { u64::MAX } else { (1u64 << self.bits) - 1 }
+	}
+}
+
+fn varint_len(mut v: u64) -> usize {
+	let mut len = 1;
+	while v >= 0x80 {
+		v >>= 7;
+		len += 1;
+	}
+	len
+}
+
+fn write_varint(out: &mut Vec<u8>, mut v: u64) {
+	while v >= 0x80 {
+		out.push((v as u8) | 0x80);
+		v >>= 7;
+	}
+	out.push(v as u8);
+}
+
+fn read_varint(bytes: &[u8], pos: &mut usize) -> Result<u64, CodecError> {
+	let mut v = 0u64;
+	let mut shift = 0;
+	loop {
+		if *pos >= bytes.len() || shift > 63 {
+			return Err(CodecError::Truncated(*pos));
+		}
+		let b = bytes[*pos];
+		*pos += 1;
+		v |= ((b & 0x7f) as u64) << shift;
+		if b & 0x80 == 0 {
+			return Ok(v);
+		}
+		shift += 7;
+	}
+}
@@ -0,0 +1,96 @@
+use core::hint::black_box;
+
+/* Constant-time field arithmetic
+	Ops whose running time does not depend on the operands,
+	only on the bit length of the modulus, which is public.
+	No % on secrets, no branch on comparisons of secrets:
+	comparisons become all-ones / all-zeros masks from the borrow bit.
+
+	Requirements on the inputs:
+		p < 2^(BITS-1), so a + b never overflows
+		a, b < p, except for ct_reduce which takes any value
+*/
+pub trait ConstantTime: Sized + Copy {
+	// all ones if self < b, zero otherwise
+	fn ct_lt_mask(self, b: Self) -> Self;
+	// mask all ones picks a, all zeros picks b
+	fn ct_select(mask: Self, a: Self, b: Self) -> Self;
+	fn ct_reduce(self, p: Self) -> Self;
+	fn ct_add_mod(self, b: Self, p: Self) -> Self;
+	fn ct_sub_mod(self, b: Self, p: Self) -> Self;
+	fn ct_mul_mod(self, b: Self, p: Self) -> Self;
+	fn ct_pow_mod(self, exponent: Self, p: Self) -> Self;
+}
+
+macro_rules! constant_time {
+	($numType: ty) => (
+		impl ConstantTime for $numType {
+
+			#[inline]
+			fn ct_lt_mask(self, b: $numType) -> $numType {
+				let (_, borrow) = self.overflowing_sub(b);
+				black_box((0 as $numType).wrapping_sub(borrow as $numType))
+			}
+
+			#[inline]
+			fn ct_select(mask: $numType, a: $numType, b: $numType) -> $numType {
+				(a & mask) | (b & !mask)
+			}
+
+			fn ct_reduce(self, p: $numType) -> $numType {
+				// shift-subtract over every bit of the type
+				let bits = (0 as $numType).count_zeros();
+				let mut r = 0 as $numType;
+				for i in (0..bits).rev() {
+					r = (r << 1) | ((self >> i) & 1);
+					let s = r.wrapping_sub(p);
+					r = Self::ct_select(r.ct_lt_mask(p), r, s);
+				}
+				r
+			}
+
+			#[inline]
+			fn ct_add_mod(self, b: $numType, p: $numType) -> $numType {
+				let s = self + b;
+				Self::ct_select(s.ct_lt_mask(p), s, s.wrapping_sub(p))
+			}
+
+			#[inline]
+			fn ct_sub_mod(self, b: $numType, p: $numType) -> $numType {
+				let d = self.wrapping_sub(b);
+				d.wrapping_add(p & self.ct_lt_mask(b))
+			}
+
+			fn ct_mul_mod(self, b: $numType, p: $numType) -> $numType {
+				// double-and-add over the bits of self, always adds
+				let bits = (0 as $numType).count_zeros() - p.leading_zeros();
+				let mut r = 0 as $numType;
+				for i in (0..bits).rev() {
+					r = r.ct_add_mod(r, p);
+					let bit = (self >> i) & 1;
+					r = r.ct_add_mod(b & (0 as $numType).wrapping_sub(bit), p);
+				}
+				r
+			}
+
+			fn ct_pow_mod(self, exponent: $numType, p: $numType) -> $numType {
+				// square-and-multiply that always multiplies,
+				// exponent bits only pick which product is kept
+				let bits = (0 as $numType).count_zeros();
+				let mut base = self;
+				let mut res = (1 as $numType).ct_reduce(p);
+				for i in 0..bits {
+					let prod = res.ct_mul_mod(base, p);
+					let bit = (exponent >> i) & 1;
+					res = Self::ct_select((0 as $numType).wrapping_sub(bit), prod, res);
+					base = base.ct_mul_mod(base, p);
+				}
+				res
+			}
+		}
+	)
+}
+
+constant_time!(u128);
+constant_time!(u64);
+constant_time!(u32);
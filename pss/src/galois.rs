@@ -0,0 +1,333 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use rand::Rng;
+#[cfg(feature = "std")]
+use rand::thread_rng;
+
+use crate::PackedSharing;
+
+/* Packed secret sharing over the Galois ring GR(2^64, d)
+	GR(2^64, d) = Z_2^64[x] / f(x), f monic of degree d and irreducible mod 2.
+	Coefficients are plain wrapping u64, so sums of shares wrap mod 2^64
+	natively and u64 inputs are shared as they are, no field conversion.
+
+	There are no roots of unity to evaluate at, instead points come from
+	the exceptional set { sum b_i x^i : b_i in {0, 1} } of size 2^d:
+	the difference of two distinct points is nonzero mod 2, hence a unit,
+	which is all Lagrange interpolation needs.
+	Point k is the element whose coefficients are the bits of k:
+		points 0 .. degree2 - 1		secret slots then randomness slots
+		point  degree2 + j			share of party j
+	Secrets sit in the constant coefficient.
+
+	Implements PackedSharing<u64> like PackedSecretSharing:
+	share returns for each party B blocks of d coefficients, flattened,
+	reconstruct takes the share_point of each party that answered.
+*/
+
+// Exponents of the lower terms of f, indexed by d
+const MODULI: [&[u32]; 17] = [
+	&[], &[],
+	&[1, 0], &[1, 0], &[1, 0], &[2, 0], &[1, 0], &[1, 0],
+	&[4, 3, 1, 0], &[4, 0], &[3, 0], &[2, 0], &[6, 4, 1, 0],
+	&[4, 3, 1, 0], &[10, 6, 1, 0], &[1, 0], &[12, 3, 1, 0],
+];
+
+pub const MAX_RING_DEGREE: usize = 16;
+
+#[derive(Clone, Debug)]
+pub struct GaloisRing {
+	d: usize,
+	// low coefficients of f, x^d = -(f[0] + f[1] x + ... )
+	f: Vec<u64>,
+	// f mod 2 as a bitmask, x^d included
+	f2: u64,
+}
+
+impl GaloisRing {
+
+	pub fn new(d: usize) -> GaloisRing {
+		assert!((2..=MAX_RING_DEGREE).contains(&d));
+		let mut f = vec![0u64; d];
+		let mut f2 = 1u64 << d;
+		for e in MODULI[d] {
+			f[*e as usize] = 1;
+			f2 |= 1 << e;
+		}
+		GaloisRing { d, f, f2 }
+	}
+
+	pub fn degree(&self) -> usize {
+		self.d
+	}
+
+	// Element of the exceptional set with index k < 2^d
+	pub fn point(&self, k: u64) -> Vec<u64> {
+		assert!(k < (1u64 << self.d));
+		(0..self.d).map(|i| (k >> i) & 1).collect()
+	}
+
+	pub fn constant(&self, c: u64) -> Vec<u64> {
+		let mut a = vec![0u64; self.d];
+		a[0] = c;
+		a
+	}
+
+	pub fn add(&self, a: &[u64], b: &[u64]) -> Vec<u64> {
+		a.iter().zip(b).map(|(x, y)| x.wrapping_add(*y)).collect()
+	}
+
+	pub fn sub(&self, a: &[u64], b: &[u64]) -> Vec<u64> {
+		a.iter().zip(b).map(|(x, y)| x.wrapping_sub(*y)).collect()
+	}
+
+	pub fn mul(&self, a: &[u64], b: &[u64]) -> Vec<u64> {
+		let d = self.d;
+		let mut prod = vec![0u64; 2 * d - 1];
+		for i in 0..d {
+			for j in 0..d {
+				prod[i + j] = prod[i + j].wrapping_add(a[i].wrapping_mul(b[j]));
+			}
+		}
+		// x^k = x^(k-d) * x^d = -x^(k-d) * (f[0] + f[1] x + ...)
+		for k in (d..2 * d - 1).rev() {
+			let c = prod[k];
+			for i in 0..d {
+				prod[k - d + i] = prod[k - d + i].wrapping_sub(c.wrapping_mul(self.f[i]));
+			}
+		}
+		prod.truncate(d);
+		prod
+	}
+
+	pub fn is_unit(&self, a: &[u64]) -> bool {
+		a.iter().any(|c| c & 1 == 1)
+	}
+
+	pub fn inverse(&self, a: &[u64]) -> Vec<u64> {
+		/* Invert mod 2 in GF(2^d), then Newton-lift:
+			x <- x (2 - a x)
+		   doubles the number of correct low bits,
+		   6 rounds take 1 bit to 64
+		*/
+		assert!(self.is_unit(a), "not a unit");
+		let a2 = a.iter().enumerate().fold(0u64, |acc, (i, c)| acc | (c & 1) << i);
+		// a^(2^d - 2) = a^-1 in GF(2^d)
+		let mut inv2 = 1u64;
+		let mut base = a2;
+		let mut e = (1u64 << self.d) - 2;
+		while e > 0 {
+			if e & 1 == 1 {
+				inv2 = self.gf2_mul(inv2, base);
+			}
+			base = self.gf2_mul(base, base);
+			e >>= 1;
+		}
+		let mut x = self.point(inv2);
+		let two = self.constant(2);
+		for _ in 0..6 {
+			let ax = self.mul(a, &x);
+			x = self.mul(&x, &self.sub(&two, &ax));
+		}
+		x
+	}
+
+	fn gf2_mul(&self, mut a: u64, mut b: u64) -> u64 {
+		let mut r = 0u64;
+		while b > 0 {
+			if b & 1 == 1 {
+				r ^= a;
+			}
+			b >>= 1;
+			a <<= 1;
+			if a & (1 << self.d) != 0 {
+				a ^= self.f2;
+			}
+		}
+		r
+	}
+
+	pub fn lagrange_weights(&self, points: &[Vec<u64>], targets: &[Vec<u64>]) -> Vec<Vec<Vec<u64>>> {
+		/* weights[t][i] = l_i(targets[t])
+			l_i(x) = prod_{j != i} (x - x_j) / (x_i - x_j)
+		   numerators from prefix and suffix products,
+		   targets may coincide with points
+		*/
+		let n = points.len();
+		let one = self.constant(1);
+		let mut denominators = Vec::new();
+		for i in 0..n {
+			let mut den = one.clone();
+			for j in 0..n {
+				if i != j {
+					den = self.mul(&den, &self.sub(&points[i], &points[j]));
+				}
+			}
+			denominators.push(self.inverse(&den));
+		}
+
+		let mut weights = Vec::new();
+		for t in targets {
+			let diffs: Vec<Vec<u64>> = points.iter().map(|p| self.sub(t, p)).collect();
+			let mut prefix = vec![one.clone()];
+			for i in 0..n {
+				let next = self.mul(&prefix[i], &diffs[i]);
+				prefix.push(next);
+			}
+			let mut suffix = one.clone();
+			let mut row = vec![Vec::new(); n];
+			for i in (0..n).rev() {
+				row[i] = self.mul(&self.mul(&prefix[i], &suffix), &denominators[i]);
+				suffix = self.mul(&suffix, &diffs[i]);
+			}
+			weights.push(row);
+		}
+		weights
+	}
+}
+
+#[derive(Clone, Debug)]
+pub struct RingPackedSecretSharing {
+
+	ring: GaloisRing,
+	// degree of the sharing poly
+	degree2: usize,
+	// shareWeights[j][i] = l_i(point of party j)
+	shareWeights: Vec<Vec<Vec<u64>>>,
+
+	V: usize,
+	L: usize,
+	N: usize,
+}
+
+impl RingPackedSecretSharing {
+
+	pub fn new(degree2: usize, total_len: usize, packing_len: usize, num_shares: usize) -> RingPackedSecretSharing {
+		assert_eq!(total_len % packing_len, 0);
+		assert!(packing_len <= degree2);
+		assert!(degree2 <= num_shares);
+		/* Smallest d whose exceptional set
+		   fits degree2 slots and num_shares parties
+		*/
+		let mut d = 2;
+		while (1usize << d) < degree2 + num_shares {
+			d += 1;
+		}
+		assert!(d <= MAX_RING_DEGREE);
+		let ring = GaloisRing::new(d);
+
+		let slots: Vec<Vec<u64>> = (0..degree2).map(|k| ring.point(k as u64)).collect();
+		let parties: Vec<Vec<u64>> = (0..num_shares).map(|j| ring.point((degree2 + j) as u64)).collect();
+		let shareWeights = ring.lagrange_weights(&slots, &parties);
+
+		RingPackedSecretSharing {
+			ring,
+			degree2,
+			shareWeights,
+			V: total_len,
+			L: packing_len,
+			N: num_shares,
+		}
+	}
+
+	pub fn ring(&self) -> &GaloisRing {
+		&self.ring
+	}
+
+	// Evaluation point of party j, to pass back into reconstruct
+	pub fn share_point(&self, party: usize) -> u64 {
+		(self.degree2 + party) as u64
+	}
+
+	#[cfg(feature = "std")]
+	pub fn share(&mut self, secrets: &[u64]) -> Vec<Vec<u64>> {
+		self.share_with_rng(secrets, &mut thread_rng())
+	}
+
+	pub fn share_with_rng<R: Rng>(&mut self, secrets: &[u64], rng: &mut R) -> Vec<Vec<u64>> {
+		/* Input Format
+		   [x0, ..., xv]
+		*/
+		assert!(secrets.len() == self.V);
+		let d = self.ring.degree();
+		let B = secrets.len() / self.L;
+		let mut ret = vec![vec![0u64; B * d]; self.N];
+		for b in 0..B {
+			/* Slot values: L secrets as constants,
+			   then uniformly random ring elements
+			*/
+			let mut slots = Vec::with_capacity(self.degree2);
+			for j in 0..self.L {
+				slots.push(self.ring.constant(secrets[b * self.L + j]));
+			}
+			for _ in self.L..self.degree2 {
+				slots.push((0..d).map(|_| rng.gen::<u64>()).collect::<Vec<u64>>());
+			}
+			for (share, row) in ret.iter_mut().zip(self.shareWeights.iter()) {
+				let mut s = vec![0u64; d];
+				for (w, v) in row.iter().zip(slots.iter()) {
+					s = self.ring.add(&s, &self.ring.mul(w, v));
+				}
+				share[b * d..(b + 1) * d].copy_from_slice(&s);
+			}
+		}
+		/* Return Format:
+		   [[s00, s01, ..., s0b],	//shares of party 0, d u64s each
+		    ...
+		    [sm0, sm1, ..., smb]]	//shares of party m
+		*/
+		ret
+	}
+
+	pub fn reconstruct(&self, shares: &[Vec<u64>], shares_point: &[u64]) -> Vec<u64> {
+		/* Input Format:
+		   [[s00, s01, ..., s0b],	//shares of party 0
+		    ...
+		    [sm0, sm1, ..., smb]]	//shares of party m
+		   shares_point[i] = share_point of the party that sent shares[i]
+
+		Any degree2 of them determine the poly
+		*/
+		let d = self.ring.degree();
+		let B = self.V / self.L;
+		let M = shares_point.len();
+		assert!(shares.len() == M);
+		assert!(M >= self.degree2);
+
+		let points: Vec<Vec<u64>> = shares_point[..self.degree2].iter()
+			.map(|p| self.ring.point(*p)).collect();
+		let targets: Vec<Vec<u64>> = (0..self.L).map(|k| self.ring.point(k as u64)).collect();
+		let weights = self.ring.lagrange_weights(&points, &targets);
+
+		let mut ret = Vec::with_capacity(self.V);
+		for b in 0..B {
+			for row in weights.iter() {
+				let mut s = vec![0u64; d];
+				for (w, share) in row.iter().zip(shares.iter()) {
+					s = self.ring.add(&s, &self.ring.mul(w, &share[b * d..(b + 1) * d]));
+				}
+				ret.push(s[0]);
+			}
+		}
+		/* Output Format
+		   [s0, ..., sv]
+		*/
+		ret
+	}
+}
+
+impl PackedSharing<u64> for RingPackedSecretSharing {
+
+	fn share_with_rng<R: Rng>(&mut self, secrets: &[u64], rng: &mut R) -> Vec<Vec<u64>> {
+		RingPackedSecretSharing::share_with_rng(self, secrets, rng)
+	}
+
+	fn reconstruct(&self, shares: &[Vec<u64>], shares_point: &[u64]) -> Vec<u64> {
+		RingPackedSecretSharing::reconstruct(self, shares, shares_point)
+	}
+
+	fn share_point(&self, party: usize) -> u64 {
+		RingPackedSecretSharing::share_point(self, party)
+	}
+}
@@ -1,18 +1,59 @@
 #![allow(non_snake_case)]
+#![cfg_attr(not(feature = "std"), no_std)]
 
+/* Without the std feature only core + alloc are used:
+	share_with_rng takes the RNG explicitly,
+	reconstruct runs on the calling thread.
+*/
+extern crate alloc;
 
-use num::traits::Unsigned;
-use std::convert::*;
-use std::thread;
+use alloc::vec;
+use alloc::vec::Vec;
+use num_traits::Unsigned;
+use core::convert::*;
 use core::fmt::Debug;
+#[cfg(feature = "std")]
+use std::thread;
 
-use rand::{thread_rng, Rng};
+use rand::Rng;
+#[cfg(feature = "std")]
+use rand::thread_rng;
 use rand::distributions::uniform::SampleUniform;
 use num_traits::{One, Zero};
+use tracing::debug_span;
 
 mod ntt;
 pub mod util;
+pub mod codec;
+pub mod seed;
+pub mod ct;
+pub mod galois;
 pub use util::*;
+pub use codec::*;
+pub use seed::*;
+pub use ct::*;
+pub use galois::*;
+
+/* The sharing API, over a prime field or over a Galois ring
+	share returns one row of shares per party,
+	reconstruct takes the rows that answered and share_point
+	of each party that sent one. Callers generic over it
+	pick the scheme by which one they construct.
+*/
+pub trait PackedSharing<U> {
+
+	fn share_with_rng<R: Rng>(&mut self, secrets: &[U], rng: &mut R) -> Vec<Vec<U>>;
+
+	#[cfg(feature = "std")]
+	fn share(&mut self, secrets: &[U]) -> Vec<Vec<U>> {
+		self.share_with_rng(secrets, &mut thread_rng())
+	}
+
+	fn reconstruct(&self, shares: &[Vec<U>], shares_point: &[U]) -> Vec<U>;
+
+	// Evaluation point of party j
+	fn share_point(&self, party: usize) -> U;
+}
 
 #[derive(Clone, Debug)]
 pub struct PackedSecretSharing<T> {
@@ -32,7 +73,7 @@ pub struct PackedSecretSharing<T> {
 }
 
 impl<T: 'static> PackedSecretSharing<T>
-where T: ModPow + Unsigned + Copy + Debug + From<u64> + SampleUniform + PartialOrd + Send,
+where T: ModPow + FieldOps + Unsigned + Copy + Debug + From<u64> + SampleUniform + PartialOrd + Send,
 {
 
 	pub fn new(prime: T, root2: T, root3:T, 
@@ -68,13 +109,23 @@ where T: ModPow + Unsigned + Copy + Debug + From<u64> + SampleUniform + PartialO
 		}
 	}
 
+	#[cfg(feature = "std")]
 	pub fn share<U>(&mut self, secrets: &[U]) -> Vec<Vec<U>>
+	where U: TryFrom<T> + Into<T> + Copy + HasMax + SampleUniform + Unsigned + Debug,
+		  <U as TryFrom<T>>::Error: Debug
+	{
+		self.share_with_rng(secrets, &mut thread_rng())
+	}
+
+	pub fn share_with_rng<U, R: Rng>(&mut self, secrets: &[U], rng: &mut R) -> Vec<Vec<U>>
 	where U: TryFrom<T> + Into<T> + Copy + HasMax + SampleUniform + Unsigned + Debug,
 		  <U as TryFrom<T>>::Error: Debug
 	{	
 		/* Input Format
 		   [x0, ..., xv]
+		Spans only carry sizes, never secrets or shares
 		*/
+		let _span = debug_span!("share", V = self.V, L = self.L, N = self.N).entered();
 		assert!(secrets.len() == self.V);	
 		let L2 = self.degree2;
 		let L3 = self.degree3;
@@ -84,16 +135,17 @@ where T: ModPow + Unsigned + Copy + Debug + From<u64> + SampleUniform + PartialO
 		T has to be a larger integer type than U to prevent overflow
 		*/
 		let mut secret_blocks: Vec<Vec<T>> = vec![Vec::new(); B];
-		let mut rng = thread_rng();
 		for i in 0..B {
 			for j in 0..self.L {
-				secret_blocks[i].push(secrets[i*self.L + j].into());
+				let s: T = secrets[i*self.L + j].into();
+				secret_blocks[i].push(s.reduce(self.prime));
 			}
 			/* Pack randomness for unused transform points
 			randomness is no greater than max of U to prevent overflow
 			*/
 			for _ in self.L..L2 {
-				secret_blocks[i].push(rng.gen_range(zero, &U::max()).into());
+				let r: T = rng.gen_range(zero, &U::max()).into();
+				secret_blocks[i].push(r.reduce(self.prime));
 			}
 		}
 		let mut ret: Vec<Vec<U>> = vec![vec![U::zero(); B]; self.N];
@@ -120,7 +172,109 @@ where T: ModPow + Unsigned + Copy + Debug + From<u64> + SampleUniform + PartialO
 		ret
 	}
 
+	/* Seeded sharing
+		The sharing poly is fixed by degree2 points, L of them are the secrets.
+		Instead of filling the other degree2 - L with randomness,
+		pin the shares of up to degree2 - L parties to expand_seed(seed)
+		and interpolate everyone else's share from there.
+		Seeded parties then only need their seed, not their shares.
+	*/
+	#[cfg(feature = "std")]
+	pub fn share_seeded<U>(&mut self, secrets: &[U], seeds: &[(usize, [u8; SEED_LEN])]) -> Vec<Vec<U>>
+	where U: TryFrom<T> + Into<T> + Copy + HasMax + SampleUniform + Unsigned + Debug,
+		  <U as TryFrom<T>>::Error: Debug,
+		  T: TryInto<u64>,
+		  <T as TryInto<u64>>::Error: Debug
+	{
+		self.share_seeded_with_rng(secrets, seeds, &mut thread_rng())
+	}
+
+	pub fn share_seeded_with_rng<U, R: Rng>(&mut self, secrets: &[U],
+		seeds: &[(usize, [u8; SEED_LEN])], rng: &mut R) -> Vec<Vec<U>>
+	where U: TryFrom<T> + Into<T> + Copy + HasMax + SampleUniform + Unsigned + Debug,
+		  <U as TryFrom<T>>::Error: Debug,
+		  T: TryInto<u64>,
+		  <T as TryInto<u64>>::Error: Debug
+	{
+		/* Input Format
+		   secrets: [x0, ..., xv]
+		   seeds: [(party index, seed), ...]
+		*/
+		let _span = debug_span!("share_seeded", V = self.V, L = self.L, N = self.N, seeds = seeds.len()).entered();
+		assert!(secrets.len() == self.V);
+		let free = self.degree2 - self.L;
+		assert!(seeds.len() <= free);
+		let B = secrets.len() / self.L;
+		let prime: u64 = self.prime.try_into().unwrap();
+		let zero = U::zero();
+
+		/* Interpolate through
+		   [secret slots, leftover random slots, seeded parties' points]
+		   and evaluate at the points of everyone else
+		*/
+		let pad = free - seeds.len();
+		let mut points: Vec<T> = self.rootTable2[0..self.L + pad].to_vec();
+		let mut seeded = vec![false; self.N];
+		for (j, _) in seeds {
+			assert!(*j < self.N && !seeded[*j]);
+			seeded[*j] = true;
+			points.push(self.rootTable3[j + 1]);
+		}
+		let mut targets = Vec::new();
+		let mut targetPoints = Vec::new();
+		for (j, s) in seeded.iter().enumerate() {
+			if !s {
+				targets.push(j);
+				targetPoints.push(self.rootTable3[j + 1]);
+			}
+		}
+		let weights = ntt::lagrange_weights(&points, &targetPoints, self.prime);
+
+		let mut ret: Vec<Vec<U>> = vec![vec![U::zero(); B]; self.N];
+		let mut expanded = Vec::new();
+		for (j, seed) in seeds {
+			let e = expand_seed(seed, prime, B);
+			for i in 0..B {
+				ret[*j][i] = U::try_from(T::from(e[i])).unwrap();
+			}
+			expanded.push(e);
+		}
+		for i in 0..B {
+			let mut values: Vec<T> = Vec::with_capacity(self.degree2);
+			for j in 0..self.L {
+				let s: T = secrets[i*self.L + j].into();
+				values.push(s.reduce(self.prime));
+			}
+			for _ in 0..pad {
+				let r: T = rng.gen_range(zero, U::max()).into();
+				values.push(r.reduce(self.prime));
+			}
+			for e in &expanded {
+				values.push(e[i].into());
+			}
+			for (t, j) in targets.iter().enumerate() {
+				let mut s = T::zero();
+				for (w, v) in weights[t].iter().zip(values.iter()) {
+					s = s.add_mod(w.mul_mod(*v, self.prime), self.prime);
+				}
+				ret[*j][i] = U::try_from(s).unwrap();
+			}
+		}
+		/* Return Format: same as share
+		   rows of seeded parties equal expand_seed(seed, prime, B)
+		*/
+		ret
+	}
+
+	#[cfg(not(feature = "std"))]
+	pub fn reconstruct<U>(&self, shares: &[Vec<U>], shares_point: &[U]) -> Vec<U>
+	where  U: TryFrom<T> + Into<T> + Copy + HasMax + SampleUniform + Unsigned,
+	   	   <U as TryFrom<T>>::Error: Debug
+	{
+		self.reconstruct2(shares, shares_point)
+	}
 
+	#[cfg(feature = "std")]
 	pub fn reconstruct<U>(&self, shares: &[Vec<U>], shares_point: &[U]) -> Vec<U> 
 	where  U: TryFrom<T> + Into<T> + Copy + HasMax + SampleUniform + Unsigned,
 	   	   <U as TryFrom<T>>::Error: Debug
@@ -136,6 +290,7 @@ where T: ModPow + Unsigned + Copy + Debug + From<u64> + SampleUniform + PartialO
 		*/
 		let B = self.V / self.L;
 		let M = shares_point.len();
+		let _span = debug_span!("reconstruct", V = self.V, L = self.L, M = M).entered();
 		assert!(shares.len() == shares_point.len());
 		assert!(M >= self.degree2);
 		assert!(M <= self.degree3);
@@ -150,7 +305,8 @@ where T: ModPow + Unsigned + Copy + Debug + From<u64> + SampleUniform + PartialO
 		let mut converted_shares = vec![vec![T::zero(); M]; B];
 		for i in 0..M {
 			for (j, s) in shares[i].iter().enumerate() {
-				converted_shares[j][i] = (*s).into();
+				let s: T = (*s).into();
+				converted_shares[j][i] = s.reduce(self.prime);
 			}
 		}
 		assert!(converted_ponts.len() == converted_shares[0].len());
@@ -198,6 +354,7 @@ where T: ModPow + Unsigned + Copy + Debug + From<u64> + SampleUniform + PartialO
 		*/
 		let B = self.V / self.L;
 		let M = shares_point.len();
+		let _span = debug_span!("reconstruct2", V = self.V, L = self.L, M = M).entered();
 		assert!(shares.len() == shares_point.len());
 		assert!(M >= self.degree2);
 		assert!(M <= self.degree3);
@@ -212,7 +369,8 @@ where T: ModPow + Unsigned + Copy + Debug + From<u64> + SampleUniform + PartialO
 		let mut converted_shares = vec![vec![T::zero(); M]; B];
 		for i in 0..M {
 			for (j, s) in shares[i].iter().enumerate() {
-				converted_shares[j][i] = (*s).into();
+				let s: T = (*s).into();
+				converted_shares[j][i] = s.reduce(self.prime);
 			}
 		}
 		assert!(converted_ponts.len() == converted_shares[0].len());
@@ -236,3 +394,113 @@ where T: ModPow + Unsigned + Copy + Debug + From<u64> + SampleUniform + PartialO
 		ret		
 	}
 }
+
+impl<T: 'static, U> PackedSharing<U> for PackedSecretSharing<T>
+where T: ModPow + FieldOps + Unsigned + Copy + Debug + From<u64> + SampleUniform + PartialOrd + Send,
+	  U: TryFrom<T> + Into<T> + Copy + HasMax + SampleUniform + Unsigned + Debug,
+	  <U as TryFrom<T>>::Error: Debug
+{
+	fn share_with_rng<R: Rng>(&mut self, secrets: &[U], rng: &mut R) -> Vec<Vec<U>> {
+		PackedSecretSharing::share_with_rng(self, secrets, rng)
+	}
+
+	fn reconstruct(&self, shares: &[Vec<U>], shares_point: &[U]) -> Vec<U> {
+		PackedSecretSharing::reconstruct(self, shares, shares_point)
+	}
+
+	// root3^(j+1), party j's share sits there
+	fn share_point(&self, party: usize) -> U {
+		self.root3.modpow(((party + 1) as u64).into(), self.prime).try_into().unwrap()
+	}
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+	use super::*;
+
+	/*
+		Seed-compressed sharing against plain sharing,
+		same parameters as tests/galois.rs
+	*/
+	const P: u128 = 3073700804129980417;
+	const R3: u128 = 1697820560572790570;
+	const D2: usize = 8;
+	const D3: usize = 27;
+	const V: usize = 32;
+	const L: usize = 4;
+	const N: usize = D3 - 1;
+
+	fn pss() -> PackedSecretSharing<u128> {
+		let R2 = (437380319823159113 * 437380319823159113) % P;
+		PackedSecretSharing::new(P, R2, R3, D2, D3, V, L, N)
+	}
+
+	fn secrets() -> Vec<u64> {
+		let mut rng = thread_rng();
+		(0..V).map(|_| rng.gen_range(0, P as u64)).collect()
+	}
+
+	// D2 - L parties spread over the share points, each with its own seed
+	fn seeds() -> Vec<(usize, [u8; SEED_LEN])> {
+		(0..D2 - L).map(|k| (5*k + 2, [k as u8 + 1; SEED_LEN])).collect()
+	}
+
+	fn reconstruct_from(pss: &PackedSecretSharing<u128>, shares: &[Vec<u64>], parties: &[usize]) -> Vec<u64> {
+		let rows: Vec<Vec<u64>> = parties.iter().map(|&j| shares[j].clone()).collect();
+		let points: Vec<u64> = parties.iter().map(|&j| PackedSharing::share_point(pss, j)).collect();
+		pss.reconstruct(&rows, &points)
+	}
+
+	#[test]
+	fn share_round_trip() {
+		let mut pss = pss();
+		let secrets = secrets();
+		let shares = pss.share(&secrets);
+		let parties: Vec<usize> = (0..N).collect();
+		assert_eq!(reconstruct_from(&pss, &shares, &parties), secrets);
+	}
+
+	#[test]
+	fn seeded_round_trip() {
+		let mut pss = pss();
+		let secrets = secrets();
+		let shares = pss.share_seeded(&secrets, &seeds());
+		assert_eq!(shares.len(), N);
+		let parties: Vec<usize> = (0..N).collect();
+		assert_eq!(reconstruct_from(&pss, &shares, &parties), secrets);
+	}
+
+	#[test]
+	fn seeded_rows_are_expansions() {
+		// what a seeded party expands is the row the dealer would have sent
+		let mut pss = pss();
+		let secrets = secrets();
+		let seeds = seeds();
+		let shares = pss.share_seeded(&secrets, &seeds);
+		for (j, seed) in seeds.iter() {
+			assert_eq!(shares[*j], expand_seed(seed, P as u64, V / L));
+		}
+	}
+
+	#[test]
+	fn seeded_rows_on_the_polynomial() {
+		// seeded parties plus just enough explicit ones still reconstruct
+		let mut pss = pss();
+		let secrets = secrets();
+		let seeds = seeds();
+		let shares = pss.share_seeded(&secrets, &seeds);
+		let seeded: Vec<usize> = seeds.iter().map(|(j, _)| *j).collect();
+		let mut parties = seeded.clone();
+		parties.extend((0..N).filter(|j| !seeded.contains(j)).take(D2 - seeded.len()));
+		assert_eq!(reconstruct_from(&pss, &shares, &parties), secrets);
+	}
+
+	#[test]
+	fn no_seeds_is_plain_sharing() {
+		let mut pss = pss();
+		let secrets = secrets();
+		let shares = pss.share_seeded(&secrets, &[]);
+		let parties: Vec<usize> = (0..N).collect();
+		assert_eq!(reconstruct_from(&pss, &shares, &parties), secrets);
+	}
+}
@@ -1,5 +1,7 @@
 use core::fmt::Debug;
-use std::cmp::PartialOrd;
+use core::cmp::PartialOrd;
+use alloc::vec;
+use alloc::vec::Vec;
 use num_traits::*;
 use num_traits::{One, Zero};
 
@@ -9,7 +11,7 @@ use crate::util::*;
 //out-of-place transform
 //input reference and perform in-place DFT on copy of input
 pub fn transform2<T>(mut a: Vec<T>, P: T, rootTable: &Vec<T>) -> Vec<T> 
-where T: Unsigned + Copy + Debug + From<u64> + PartialOrd
+where T: Unsigned + Copy + Debug + From<u64> + PartialOrd + FieldOps
 {
 	bit_reverse2(&mut a);
 	DFT_radix2(&mut a, P, rootTable);
@@ -17,7 +19,7 @@ where T: Unsigned + Copy + Debug + From<u64> + PartialOrd
 }
 
 pub fn transform3<T>(mut a: Vec<T>, P: T, rootTable: &Vec<T>) -> Vec<T> 
-where T: Unsigned + Copy + Debug + From<u64> + PartialOrd
+where T: Unsigned + Copy + Debug + From<u64> + PartialOrd + FieldOps
 {
 	bit_reverse3(&mut a);
 	DFT_radix3(&mut a, P, rootTable);
@@ -26,7 +28,7 @@ where T: Unsigned + Copy + Debug + From<u64> + PartialOrd
 
 
 pub fn inverse2<T: ModPow>(mut b: Vec<T>, P: T, rootTable: &Vec<T>) -> Vec<T> 
-where T: Unsigned + Copy + Debug + From<u64> + PartialOrd
+where T: Unsigned + Copy + Debug + From<u64> + PartialOrd + FieldOps
 {
 
 
@@ -48,14 +50,14 @@ where T: Unsigned + Copy + Debug + From<u64> + PartialOrd
 	let L_into: T = (L as u64).into();
 	let L_inverse: T = L_into.modpow(P - 2.into(), P);
 	for i in 0..L {
-		b[i] = b[i] * L_inverse % P;
+		b[i] = b[i].mul_mod(L_inverse, P);
 	}
 
 	b
 }
 
 pub fn inverse3<T: ModPow>(mut b: Vec<T>, P: T, rootTable: &Vec<T>) -> Vec<T> 
-where T: Unsigned + Copy + Debug + From<u64> + PartialOrd
+where T: Unsigned + Copy + Debug + From<u64> + PartialOrd + FieldOps
 {
 
 	let L = b.len();
@@ -76,7 +78,7 @@ where T: Unsigned + Copy + Debug + From<u64> + PartialOrd
 	let L_into: T = (L as u64).into();
 	let L_inverse = L_into.modpow(P - 2.into(), P);
 	for i in 0..L {
-		b[i] = b[i] * L_inverse % P;
+		b[i] = b[i].mul_mod(L_inverse, P);
 	}
 
 	b
@@ -84,10 +86,10 @@ where T: Unsigned + Copy + Debug + From<u64> + PartialOrd
 
 //in-place, use mutable reference
 pub fn DFT_radix2<T>(a: &mut Vec<T>, P: T, rootTable: &Vec<T>)
-where T: Unsigned + Copy + Debug + From<u64> + PartialOrd
+where T: Unsigned + Copy + Debug + From<u64> + PartialOrd + FieldOps
 {
 	let L = a.len();
-	let L_bitNum = (L as f64).log2().trunc() as usize;
+	let L_bitNum = L.trailing_zeros() as usize;
 
     //Cooley-Tukey DFT
 	for s in 1..(L_bitNum + 1) {
@@ -96,14 +98,10 @@ where T: Unsigned + Copy + Debug + From<u64> + PartialOrd
 		while i < L {
 			let mut j = 0;
 			while j < m/2 {
-				let t = rootTable[j*(L/m as usize)] * a[i + j + m/2 ] % P;
-				let u = a[i + j] % P;
-				a[i + j] = (u + t) % P;
-				if u <= t {
-					a[i + j + m/2] = ((P + u) - t) % P;
-				} else {
-					a[i + j + m/2] = (u - t) % P;
-				}
+				let t = rootTable[j*(L/m as usize)].mul_mod(a[i + j + m/2], P);
+				let u = a[i + j];
+				a[i + j] = u.add_mod(t, P);
+				a[i + j + m/2] = u.sub_mod(t, P);
 				j+= 1;
 			}
 			i += m;
@@ -112,7 +110,7 @@ where T: Unsigned + Copy + Debug + From<u64> + PartialOrd
 }
 
 pub fn DFT_radix3<T>(a: &mut Vec<T>, P: T, rootTable: &Vec<T>) 
-where T: Unsigned + Copy + Debug + From<u64> + PartialOrd
+where T: Unsigned + Copy + Debug + From<u64> + PartialOrd + FieldOps
 {	
 	let L = a.len();
 	let w = rootTable[L/3];
@@ -126,11 +124,11 @@ where T: Unsigned + Copy + Debug + From<u64> + PartialOrd
 			let mut pair = j;
 			while pair < L {
 				let (x, y, z) = (a[pair],
-								a[pair + i] * rootTable[j * stride] % P,
-								a[pair + 2 * i] * rootTable[2 * j * stride] %P);
-				a[pair] 	  	= (x + y + z) % P;
-				a[pair + i]   	= (x % P + w * y % P + w_sqr * z % P) % P;
-                a[pair + 2 * i] = (x % P + w_sqr * y % P + w * z % P) % P;
+								a[pair + i].mul_mod(rootTable[j * stride], P),
+								a[pair + 2 * i].mul_mod(rootTable[2 * j * stride], P));
+				a[pair] 	  	= x.add_mod(y, P).add_mod(z, P);
+				a[pair + i]   	= x.add_mod(w.mul_mod(y, P), P).add_mod(w_sqr.mul_mod(z, P), P);
+                a[pair + 2 * i] = x.add_mod(w_sqr.mul_mod(y, P), P).add_mod(w.mul_mod(z, P), P);
 				
 				pair += jump;
 			}
@@ -194,7 +192,7 @@ pub fn trigits_len(n: usize) -> usize {
 
 
 pub fn lagrange_interpolation<T: ModPow>(points: &Vec<T>, values: &Vec<T>, roots: &Vec<T>, P: T) -> Vec<T> 
-where T: Unsigned + Copy + Debug + From<u64> + PartialOrd
+where T: Unsigned + Copy + Debug + From<u64> + PartialOrd + FieldOps
 {
 	assert!(points.len() == values.len());
 	let L = points.len();
@@ -204,12 +202,7 @@ where T: Unsigned + Copy + Debug + From<u64> + PartialOrd
 		let mut d = T::one();
 		for j in 0..L {
 			if i != j {
-				if points[i] >= points[j]{
-					d = d * (points[i] - points[j]);
-				} else {
-					d = d * ((points[i] + P - points[j]) % P);
-				}
-				d = d % P;
+				d = d.mul_mod(points[i].sub_mod(points[j], P), P);
 			}
 		}
 		d = d.modpow(P - 2.into(), P);
@@ -223,23 +216,52 @@ where T: Unsigned + Copy + Debug + From<u64> + PartialOrd
 			let mut li = T::one();
 			for j in 0..L {
 				if i != j {
-					if *r >= points[j] {
-						li = li * (*r - points[j]);
-					} else {
-						li = li * ((*r + P - points[j]) % P);
-					}
-					li = li % P;
+					li = li.mul_mod(r.sub_mod(points[j], P), P);
 				}
 			}
-			li = li * denominators[i] % P;
-			eval = eval + (li * values[i] % P);
+			li = li.mul_mod(denominators[i], P);
+			eval = eval.add_mod(li.mul_mod(values[i], P), P);
 		}
-		evals.push(eval % P);
+		evals.push(eval);
 	}
 	
 	evals
 }
 
+pub fn lagrange_weights<T>(points: &[T], targets: &[T], P: T) -> Vec<Vec<T>>
+where T: ModPow + Unsigned + Copy + Debug + From<u64> + PartialOrd + FieldOps
+{
+	/* weights[t][i] = l_i(targets[t])
+	   l_i(x) = prod_{j != i} (x - x_j) / (x_i - x_j)
+	   so evaluating the interpolated poly at targets[t]
+	   is a dot product of weights[t] with the values.
+	   targets must not overlap points.
+	*/
+	let L = points.len();
+	let mut denominators: Vec<T> = Vec::new();
+	for i in 0..L {
+		let mut d = T::one();
+		for j in 0..L {
+			if i != j {
+				d = d.mul_mod(points[i].sub_mod(points[j], P), P);
+			}
+		}
+		denominators.push(d.modpow(P - 2.into(), P));
+	}
 
-
-
+	let mut weights = Vec::new();
+	for r in targets {
+		let mut full = T::one();
+		for p in points {
+			full = full.mul_mod(r.sub_mod(*p, P), P);
+		}
+		let mut row = Vec::new();
+		for i in 0..L {
+			let diff = r.sub_mod(points[i], P);
+			assert!(diff != T::zero());
+			row.push(full.mul_mod(diff.modpow(P - 2.into(), P), P).mul_mod(denominators[i], P));
+		}
+		weights.push(row);
+	}
+	weights
+}
@@ -0,0 +1,21 @@
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use alloc::vec::Vec;
+
+/* PRG-derived shares
+	A share vector the receiver regenerates from a short seed,
+	so the sender ships SEED_LEN bytes instead of the whole vector.
+	Elements are drawn uniformly from [0, prime) by ChaCha20,
+	sender and receiver must agree on prime and len.
+*/
+
+pub const SEED_LEN: usize = 32;
+
+pub fn expand_seed(seed: &[u8; SEED_LEN], prime: u64, len: usize) -> Vec<u64> {
+	let mut rng = ChaCha20Rng::from_seed(*seed);
+	let mut res = Vec::with_capacity(len);
+	for _ in 0..len {
+		res.push(rng.gen_range(0, prime));
+	}
+	res
+}
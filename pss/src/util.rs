@@ -1,3 +1,5 @@
+#[cfg(feature = "constant-time")]
+use crate::ct::ConstantTime;
 
 pub trait ModPow {
     fn modpow(&self, exponent: Self, modulus: Self) -> Self;
@@ -28,37 +30,133 @@ macro_rules! modpow {
 }
 
 
+#[cfg(feature = "constant-time")]
+macro_rules! modpow_ct {
+    ($numType: ty) => (
+        fn modpow(&self, exponent: Self, modulus: Self) -> Self {
+                assert!(modulus != (0 as $numType), "divide by zero!");
+                self.ct_reduce(modulus).ct_pow_mod(exponent, modulus)
+        }
+    )
+}
+
+#[cfg(not(feature = "constant-time"))]
 impl ModPow for u128 {
     modpow!(u128);
 }
 
+#[cfg(not(feature = "constant-time"))]
 impl ModPow for u64 {
     modpow!(u64);
 }
 
+#[cfg(not(feature = "constant-time"))]
 impl ModPow for u32 {
     modpow!(u32);
 }
 
+#[cfg(feature = "constant-time")]
+impl ModPow for u128 {
+    modpow_ct!(u128);
+}
+
+#[cfg(feature = "constant-time")]
+impl ModPow for u64 {
+    modpow_ct!(u64);
+}
+
+#[cfg(feature = "constant-time")]
+impl ModPow for u32 {
+    modpow_ct!(u32);
+}
+
+/* Field arithmetic on secret data (shares, inputs, randomness)
+    Plain % and comparisons by default,
+    with the constant-time feature they go through ConstantTime.
+    Operands are expected to be reduced already, except for reduce.
+*/
+pub trait FieldOps: Sized + Copy {
+    fn reduce(self, p: Self) -> Self;
+    fn add_mod(self, b: Self, p: Self) -> Self;
+    fn sub_mod(self, b: Self, p: Self) -> Self;
+    fn mul_mod(self, b: Self, p: Self) -> Self;
+}
+
+#[cfg(not(feature = "constant-time"))]
+macro_rules! field_ops {
+    ($numType: ty) => (
+        impl FieldOps for $numType {
+            #[inline]
+            fn reduce(self, p: $numType) -> $numType {
+                self % p
+            }
+            #[inline]
+            fn add_mod(self, b: $numType, p: $numType) -> $numType {
+                (self + b) % p
+            }
+            #[inline]
+            fn sub_mod(self, b: $numType, p: $numType) -> $numType {
+                if self >= b {
+                    self - b
+                } else {
+                    (self + p) - b
+                }
+            }
+            #[inline]
+            fn mul_mod(self, b: $numType, p: $numType) -> $numType {
+                self * b % p
+            }
+        }
+    )
+}
+
+#[cfg(feature = "constant-time")]
+macro_rules! field_ops {
+    ($numType: ty) => (
+        impl FieldOps for $numType {
+            #[inline]
+            fn reduce(self, p: $numType) -> $numType {
+                self.ct_reduce(p)
+            }
+            #[inline]
+            fn add_mod(self, b: $numType, p: $numType) -> $numType {
+                self.ct_add_mod(b, p)
+            }
+            #[inline]
+            fn sub_mod(self, b: $numType, p: $numType) -> $numType {
+                self.ct_sub_mod(b, p)
+            }
+            #[inline]
+            fn mul_mod(self, b: $numType, p: $numType) -> $numType {
+                self.ct_mul_mod(b, p)
+            }
+        }
+    )
+}
+
+field_ops!(u128);
+field_ops!(u64);
+field_ops!(u32);
+
 pub trait HasMax {
     fn max() -> Self;
 }
 
 impl HasMax for u32 {
     fn max() -> u32 {
-        std::u32::MAX
+        u32::MAX
     }
 }
 
 impl HasMax for u64 {
     fn max() -> u64 {
-        std::u64::MAX
+        u64::MAX
     }
 }
 
 impl HasMax for u128 {
     fn max() -> u128 {
-        std::u128::MAX
+        u128::MAX
     }
 }
 
@@ -77,7 +175,7 @@ impl HasMax for u128 {
 
 // impl HasMax for &u32 {
 //     fn max_def() -> u32 {
-//         std::u32::MAX
+//         u32::MAX
 //     }
 // }
 
@@ -0,0 +1,106 @@
+use pss::*;
+
+/*
+	ShareCodec round trips and untrusted input
+	Malformed bytes must come back as a CodecError, never a panic or a huge allocation
+*/
+
+const P: u64 = 3073700804129980417;
+
+#[test]
+fn packed_round_trip() {
+	let codec = ShareCodec::new(P);
+	let elems = vec![0, 1, 2, P - 1, P / 2, 12345678901234];
+	let bytes = codec.encode(&elems);
+	assert_eq!(bytes.len(), 2 + codec.packed_len(elems.len()).unwrap());
+	assert_eq!(codec.decode(&bytes).unwrap(), elems);
+}
+
+#[test]
+fn varint_round_trip() {
+	let codec = ShareCodec::new(P);
+	let elems = vec![0, 1, 127, 128, 300, P - 1];
+	assert_eq!(codec.decode(&codec.encode_varint(&elems)).unwrap(), elems);
+}
+
+#[test]
+fn compact_picks_shorter() {
+	let codec = ShareCodec::new(P);
+	let small = vec![3u64; 100];
+	let large = vec![P - 1; 100];
+	assert!(codec.encode_compact(&small).len() < codec.encode(&small).len());
+	assert_eq!(codec.encode_compact(&large), codec.encode(&large));
+	assert_eq!(codec.decode(&codec.encode_compact(&small)).unwrap(), small);
+	assert_eq!(codec.decode(&codec.encode_compact(&large)).unwrap(), large);
+}
+
+#[test]
+fn empty_round_trip() {
+	let codec = ShareCodec::new(P);
+	assert_eq!(codec.decode(&codec.encode(&[])).unwrap(), Vec::<u64>::new());
+	assert_eq!(codec.decode(&codec.encode_compact(&[])).unwrap(), Vec::<u64>::new());
+}
+
+#[test]
+fn small_prime_round_trip() {
+	let codec = ShareCodec::new(17);
+	assert_eq!(codec.bits(), 5);
+	let elems: Vec<u64> = (0..17).collect();
+	assert_eq!(codec.decode(&codec.encode(&elems)).unwrap(), elems);
+}
+
+#[test]
+fn packed_len_overflow() {
+	let codec = ShareCodec::new(P);
+	assert_eq!(codec.packed_len(usize::MAX), None);
+	assert_eq!(codec.packed_len(0), Some(0));
+	assert_eq!(codec.packed_len(1), Some(8));
+}
+
+#[test]
+fn huge_count() {
+	let codec = ShareCodec::new(P);
+	let mut bytes = vec![0u8];
+	bytes.extend(&[0xff; 8]);
+	bytes.push(0x7f);
+	assert!(matches!(codec.decode(&bytes), Err(CodecError::Truncated(_))));
+	let bytes = [1u8, 0xff, 0xff, 0xff, 0xff, 0x0f];
+	assert!(matches!(codec.decode(&bytes), Err(CodecError::Truncated(_))));
+}
+
+#[test]
+fn truncated() {
+	let codec = ShareCodec::new(P);
+	let bytes = codec.encode(&[1, 2, 3]);
+	assert!(codec.decode(&[]).is_err());
+	assert!(codec.decode(&bytes[..bytes.len() - 1]).is_err());
+	let bytes = codec.encode_varint(&[1000, 2000]);
+	assert!(codec.decode(&bytes[..bytes.len() - 1]).is_err());
+	// count's varint runs off the end
+	assert!(codec.decode(&[0, 0x80]).is_err());
+}
+
+#[test]
+fn encode_reduces() {
+	// past the prime is the same field element, not a masked different one
+	let codec = ShareCodec::new(P);
+	let elems = vec![P, P + 5, u64::MAX, 7];
+	let reduced = vec![0, 5, u64::MAX % P, 7];
+	assert_eq!(codec.decode(&codec.encode(&elems)).unwrap(), reduced);
+	assert_eq!(codec.decode(&codec.encode_varint(&elems)).unwrap(), reduced);
+	assert_eq!(codec.decode(&codec.encode_compact(&elems)).unwrap(), reduced);
+	assert_eq!(codec.encode(&elems), codec.encode(&reduced));
+}
+
+#[test]
+fn unknown_tag() {
+	let codec = ShareCodec::new(P);
+	assert_eq!(codec.decode(&[7, 0]), Err(CodecError::UnknownTag(7)));
+}
+
+#[test]
+fn out_of_range() {
+	let codec = ShareCodec::new(P);
+	let bytes = ShareCodec::new(u64::MAX).encode_varint(&[5, P]);
+	assert_eq!(codec.decode(&bytes), Err(CodecError::OutOfRange(1)));
+}
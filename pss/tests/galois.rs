@@ -0,0 +1,107 @@
+#![allow(non_snake_case)]
+
+use pss::*;
+use rand::{thread_rng, Rng};
+
+/*
+	Field and ring sharing through the PackedSharing trait,
+	ring sums wrapping mod 2^64 and GaloisRing inverses
+*/
+
+const P: u128 = 3073700804129980417;
+const R3: u128 = 1697820560572790570;
+const D2: usize = 8;
+const D3: usize = 27;
+const V: usize = 32;
+const L: usize = 4;
+const N: usize = D3 - 1;
+
+fn field() -> PackedSecretSharing<u128> {
+	let R2 = (437380319823159113 * 437380319823159113) % P;
+	PackedSecretSharing::new(P, R2, R3, D2, D3, V, L, N)
+}
+
+fn ring() -> RingPackedSecretSharing {
+	RingPackedSecretSharing::new(D2, V, L, N)
+}
+
+// Share, then reconstruct from the given parties through the trait only
+fn round_trip<S: PackedSharing<u64>>(pss: &mut S, secrets: &[u64], parties: &[usize]) -> Vec<u64> {
+	let shares = pss.share(secrets);
+	assert_eq!(shares.len(), N);
+	let rows: Vec<Vec<u64>> = parties.iter().map(|&j| shares[j].clone()).collect();
+	let points: Vec<u64> = parties.iter().map(|&j| pss.share_point(j)).collect();
+	pss.reconstruct(&rows, &points)
+}
+
+#[test]
+fn field_round_trip() {
+	let mut rng = thread_rng();
+	let secrets: Vec<u64> = (0..V).map(|_| rng.gen_range(0, P as u64)).collect();
+	let parties: Vec<usize> = (0..N).collect();
+	assert_eq!(round_trip(&mut field(), &secrets, &parties), secrets);
+}
+
+#[test]
+fn ring_round_trip() {
+	let mut rng = thread_rng();
+	let secrets: Vec<u64> = (0..V).map(|_| rng.gen::<u64>()).collect();
+	let parties: Vec<usize> = (0..N).collect();
+	assert_eq!(round_trip(&mut ring(), &secrets, &parties), secrets);
+}
+
+#[test]
+fn ring_any_degree2_parties() {
+	let mut rng = thread_rng();
+	let secrets: Vec<u64> = (0..V).map(|_| rng.gen::<u64>()).collect();
+	let parties: Vec<usize> = (0..D2).map(|k| 3 * k % N).collect();
+	assert_eq!(round_trip(&mut ring(), &secrets, &parties), secrets);
+}
+
+#[test]
+fn ring_sums_wrap() {
+	let mut pss = ring();
+	let a: Vec<u64> = (0..V).map(|i| u64::MAX - i as u64).collect();
+	let b: Vec<u64> = (0..V).map(|i| 2 * i as u64 + 5).collect();
+	let sa = PackedSharing::share(&mut pss, &a);
+	let sb = PackedSharing::share(&mut pss, &b);
+	let sum: Vec<Vec<u64>> = sa.iter().zip(sb.iter())
+		.map(|(x, y)| x.iter().zip(y).map(|(s, t)| s.wrapping_add(*t)).collect())
+		.collect();
+	let points: Vec<u64> = (0..N).map(|j| pss.share_point(j)).collect();
+	let expected: Vec<u64> = a.iter().zip(b.iter()).map(|(x, y)| x.wrapping_add(*y)).collect();
+	assert_eq!(PackedSharing::reconstruct(&pss, &sum, &points), expected);
+}
+
+#[test]
+fn ring_inverse() {
+	let mut rng = thread_rng();
+	for d in 2..=MAX_RING_DEGREE {
+		let ring = GaloisRing::new(d);
+		let one = ring.constant(1);
+		for _ in 0..20 {
+			let mut a: Vec<u64> = (0..d).map(|_| rng.gen::<u64>()).collect();
+			a[0] |= 1;
+			assert!(ring.is_unit(&a));
+			assert_eq!(ring.mul(&a, &ring.inverse(&a)), one, "d = {}", d);
+		}
+	}
+}
+
+#[test]
+fn ring_point_differences_are_units() {
+	let ring = GaloisRing::new(5);
+	for i in 0..32 {
+		for j in 0..32 {
+			let diff = ring.sub(&ring.point(i), &ring.point(j));
+			assert_eq!(ring.is_unit(&diff), i != j);
+		}
+	}
+}
+
+#[test]
+fn ring_not_a_unit() {
+	let ring = GaloisRing::new(4);
+	assert!(!ring.is_unit(&[2, 4, 0, 6]));
+	assert!(std::panic::catch_unwind(|| ring.inverse(&[2, 4, 0, 6])).is_err());
+}
@@ -1,72 +1,60 @@
 #! /bin/bash
 
 # Not supplying IP and ports
-# Default value is localhost 8888 and 9999
+# Default value is localhost 8888 and 9999 (catch-up 9998)
 
 # Malicious:
-#	./runall.sh [client number] [vector length] [input bit limit] [dropouts] 
+#	./runall.sh [client number] [vector length] [input bit limit] [dropouts]
 #				[session time] [IS session time] [corrupted parties] [malicious flag]
 #				[ip] [msg port] [broadcast port2]
 
 # Semi-Honest:
-#	./runall.sh [client number] [vector length] [dropouts] 
+#	./runall.sh [client number] [vector length] [dropouts]
 #				[session time] [IS session time] [malicious flag]
 #				[ip] [msg port] [broadcast port2]
 
-if [[ $# == 8 ]]
-# malicious without port
-	then
-		cd server
-		cargo run $1 $2 $3 $4 $5 $6 $7 $8 &
+# Both binaries also take a config file, see `--help` and `--print-config`
+# For a seeded run in one process with scripted dropouts, see simulation/
 
-		cd ..
-		cd client
-		for (( counter=0; counter< $1; counter++ ))
-			do
-			sleep 0.01 && (target/debug/client "Client $counter" $2 $8 $3 & echo "Client $counter")
-		done
-fi
-if [[ $# == 6 ]]
-# semi-honest without port
+if [[ $# == 8 || $# == 11 ]]
+# malicious
 	then
+		ENDPOINT=""
+		if [[ $# == 11 ]]
+			then
+				ENDPOINT="--ip $9 --msg-port ${10} --broadcast-port ${11}"
+		fi
 		cd server
-		cargo run $1 $2 $3 $4 $5 $6 &
+		cargo run -- --clients $1 --vector-len $2 --input-bit-limit $3 --dropouts $4 \
+			--session-time $5 --is-session-time $6 --corruption $7 --malicious $ENDPOINT &
 
 		cd ..
 		cd client
 		for (( counter=0; counter< $1; counter++ ))
 			do
-			sleep 0.01 && (target/debug/client "Client $counter" $2 $6 & echo "Client $counter")
+			sleep 0.01 && (target/debug/client --id "Client $counter" --vector-len $2 \
+				--malicious --input-bit-limit $3 $ENDPOINT & echo "Client $counter")
 		done
 fi
 
-# Supplying IP and ports
-# $9 = IP, $10 = port1 for messeging, $11 = port2 for broadcasting 
-if [[ $# == 11 ]]
-# malicious with port
-	then
-		cd server
-		cargo run $1 $2 $3 $4 $5 $6 $7 $8 $9 ${10} ${11} &
-
-		cd ..
-		cd client
-		for (( counter=0; counter< $1; counter++ ))
-			do
-			sleep 0.01 && (target/debug/client "Client $counter" $2 $8 $3 $9 ${10} ${11} & echo "Client $counter")
-		done
-fi
-if [[ $# == 9 ]]
-# semi-honest with port
+if [[ $# == 6 || $# == 9 ]]
+# semi-honest
 	then
+		ENDPOINT=""
+		if [[ $# == 9 ]]
+			then
+				ENDPOINT="--ip $7 --msg-port $8 --broadcast-port $9"
+		fi
 		cd server
-		cargo run $1 $2 $3 $4 $5 $6 $7 $8 $9 &
+		cargo run -- --clients $1 --vector-len $2 --dropouts $3 \
+			--session-time $4 --is-session-time $5 $ENDPOINT &
 
 		cd ..
 		cd client
 		for (( counter=0; counter< $1; counter++ ))
 			do
-			sleep 0.01 && (target/debug/client "Client $counter" $2 $6 $7 $8 $9 & echo "Client $counter")
+			sleep 0.01 && (target/debug/client --id "Client $counter" --vector-len $2 $ENDPOINT & echo "Client $counter")
 		done
 fi
 # ./runall.sh 20 64 3 0 20000 20000 0 true
-#END
\ No newline at end of file
+#END
@@ -0,0 +1,368 @@
+use std::collections::BTreeMap;
+
+use serde::{Serialize, Deserialize};
+use tracing::debug;
+use pss::ShareCodec;
+
+use crate::param::Param;
+use crate::state::State;
+use crate::transcript::{Record, Entry, GENESIS};
+use crate::worker::read_le_u64;
+use crate::tests::{find_cheaters, check_aggregates};
+
+/*
+	Offline replay of a transcript
+	Checks the hash chain, then for every round
+		EC		reruns the tests on the recorded corrections,
+				blamed clients must be the ones excluded on BL
+		sum		reconstructs from the recorded aggregated shares,
+				leaving out dropouts' and the ones off the polynomial,
+				must be the sum published on RS
+	Only accepted messages count, as for the server. Dropouts are
+	clients without accepted IS shares, excluded ones and replayers.
+	Each mismatch is a Divergence, an empty list means the transcript holds up.
+*/
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Divergence {
+	BrokenChain { index: u64 },
+	NoSetup { round: usize },
+	UnknownClient { round: usize, client: String },
+	Malformed { index: u64 },
+	Exclusions { round: usize, published: Vec<usize>, recomputed: Vec<usize> },
+	Sum { round: usize, published: Vec<u64>, recomputed: Vec<u64> },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoundAudit {
+	pub round: usize,
+	pub excluded: Option<Vec<usize>>,		// recomputed, None in semi-honest sessions
+	pub sum: Option<Vec<u64>>,				// recomputed, None if too few aggregated shares
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditReport {
+	pub records: usize,
+	pub rounds: Vec<RoundAudit>,
+	pub divergences: Vec<Divergence>,
+}
+
+impl AuditReport {
+	pub fn passed(&self) -> bool {
+		self.divergences.is_empty()
+	}
+}
+
+struct Setup {
+	maxClients: usize,
+	vectorSize: usize,
+	malicious: bool,
+	param: Param,
+	clients: Vec<Vec<u8>>,
+}
+
+// What one round left in the transcript
+#[derive(Default)]
+struct Round {
+	corrections: Vec<(usize, Vec<Vec<u8>>)>,	// (client index, frames)
+	aggregates: Vec<(usize, Vec<u8>)>,			// (client index, encoded shares)
+	shared: Vec<usize>,							// clients with accepted IS shares
+	seen: Vec<(usize, Vec<u8>)>,				// (client index, message digest)
+	replayed: Vec<usize>,						// clients that sent a message again
+	replayedIS: Vec<usize>,						// the ones that did in IS, out before EC
+	exclusions: Option<Vec<usize>>,				// from BL
+	sum: Option<Vec<u64>>,						// from RS, empty if the server couldn't reconstruct
+}
+
+pub fn replay(records: &[Record]) -> AuditReport {
+	let mut divergences = Vec::new();
+	/*
+		Chain first, everything after a break is suspect
+		but still replayed
+	*/
+	let mut last = GENESIS.to_string();
+	for (i, r) in records.iter().enumerate() {
+		if r.index != i as u64 || !r.verify(&last) {
+			divergences.push(Divergence::BrokenChain { index: i as u64 });
+		}
+		last = r.hash.clone();
+	}
+	/*
+		Sort messages and decisions by round
+		Client indices come from the latest Setup
+	*/
+	let mut setup: Option<Setup> = None;
+	let mut rounds: BTreeMap<usize, Round> = BTreeMap::new();
+	for r in records {
+		match &r.entry {
+			Entry::Setup { maxClients, vectorSize, malicious, param, clients } => {
+				let clients: Option<Vec<Vec<u8>>> = clients.iter().map(|c| hex::decode(c).ok()).collect();
+				match clients {
+					Some(c) => setup = Some(Setup {
+						maxClients: *maxClients,
+						vectorSize: *vectorSize,
+						malicious: *malicious,
+						param: param.clone(),
+						clients: c,
+					}),
+					None => divergences.push(Divergence::Malformed { index: r.index }),
+				}
+			},
+			Entry::Broadcast { topic, frames, .. } => {
+				let (name, round) = match split_topic(topic) {
+					Some(t) => t,
+					None => continue,
+				};
+				let frames = match unhex(frames) {
+					Some(f) => f,
+					None => {
+						divergences.push(Divergence::Malformed { index: r.index });
+						continue
+					},
+				};
+				// [signature, round, payload, ...], BL's payload is u64 indices
+				if frames.len() > 2 && name == "BL" && frames[2].len() % 8 != 0 {
+					divergences.push(Divergence::Malformed { index: r.index });
+					continue
+				}
+				match (name, frames.len()) {
+					("BL", 5) => {
+						let excluded = read_le_u64(&frames[2]).iter().map(|i| *i as usize).collect();
+						rounds.entry(round).or_default().exclusions = Some(excluded);
+					},
+					("RS", 4) => match setup.as_ref().map(|s| ShareCodec::new(s.param.P).decode(&frames[2])) {
+						Some(Ok(sum)) => rounds.entry(round).or_default().sum = Some(sum),
+						_ => divergences.push(Divergence::Malformed { index: r.index }),
+					},
+					_ => (),
+				}
+			},
+			Entry::Message { client, round, state, frames, accepted } => {
+				if !(*state == State::InputSharing || *state == State::ErrorCorrection || *state == State::Aggregation) {
+					continue;
+				}
+				let s = match &setup {
+					Some(s) => s,
+					None => {
+						divergences.push(Divergence::NoSetup { round: *round });
+						continue
+					},
+				};
+				let idx = match hex::decode(client).ok().and_then(|c| s.clients.iter().position(|x| *x == c)) {
+					Some(i) => i,
+					None => {
+						divergences.push(Divergence::UnknownClient { round: *round, client: client.clone() });
+						continue
+					},
+				};
+				let mut frames = match unhex(frames) {
+					Some(f) => f,
+					None => {
+						divergences.push(Divergence::Malformed { index: r.index });
+						continue
+					},
+				};
+				let entry = rounds.entry(*round).or_default();
+				/* the second copy of a message is a replay, excluded if before AG
+				   copies handled at once may be recorded in either order
+				*/
+				let digest = crate::message_digest(&frames);
+				if *state != State::Aggregation {
+					if entry.seen.contains(&(idx, digest.clone())) {
+						if !entry.replayed.contains(&idx) {
+							entry.replayed.push(idx);
+						}
+						if *state == State::InputSharing && !entry.replayedIS.contains(&idx) {
+							entry.replayedIS.push(idx);
+						}
+					} else {
+						entry.seen.push((idx, digest));
+					}
+				}
+				if !accepted {
+					continue;
+				}
+				match state {
+					State::InputSharing => entry.shared.push(idx),
+					State::ErrorCorrection if frames.iter().all(|f| f.len() % 8 == 0) =>
+						entry.corrections.push((idx, frames)),
+					State::ErrorCorrection => divergences.push(Divergence::Malformed { index: r.index }),
+					// [shares, Sig(session | round | shares)]
+					_ if frames.len() == 2 => entry.aggregates.push((idx, frames.swap_remove(0))),
+					_ => divergences.push(Divergence::Malformed { index: r.index }),
+				}
+			},
+		}
+	}
+	/*
+		Recompute each round's decisions from its messages
+	*/
+	let mut audits = Vec::new();
+	let s = match setup {
+		Some(s) => s,
+		None => return AuditReport { records: records.len(), rounds: audits, divergences: divergences },
+	};
+	let M = s.maxClients;
+	for (round, r) in rounds.iter() {
+		debug!(round, corrections = r.corrections.len(), aggregates = r.aggregates.len(), "replaying round");
+		let excluded = match s.malicious {
+			true => {
+				let mut corrections = vec![vec![Vec::new(); M]; M];
+				for (idx, frames) in r.corrections.iter() {
+					if frames.len() != M || *idx >= M {
+						continue;
+					}
+					for i in 0..M {
+						corrections[i][*idx] = read_le_u64(&frames[i]);
+					}
+				}
+				// IS dropouts aren't tested again, as for the server
+				let candidates: Vec<usize> = (0..M)
+					.filter(|i| r.shared.contains(i) && !r.replayedIS.contains(i))
+					.filter(|&i| corrections[i].iter().any(|c| c.len() != 0))
+					.collect();
+				let excluded: Vec<usize> = find_cheaters(&corrections, &candidates, &s.param, &s.clients)
+					.iter().map(|b| b.index).collect();
+				if let Some(published) = &r.exclusions {
+					let mut published = published.clone();
+					published.sort();
+					if published != excluded {
+						divergences.push(Divergence::Exclusions {
+							round: *round, published: published, recomputed: excluded.clone(),
+						});
+					}
+				}
+				Some(excluded)
+			},
+			false => None,
+		};
+		// aggregated shares of dropouts are ignored, as by the server
+		let dropped = |i: usize| !r.shared.contains(&i) || r.replayed.contains(&i)
+			|| excluded.as_ref().map_or(false, |e| e.contains(&i));
+		let codec = ShareCodec::new(s.param.P);
+		let mut shares = vec![Vec::new(); s.clients.len()];
+		for (idx, encoded) in r.aggregates.iter() {
+			if dropped(*idx) {
+				continue;
+			}
+			if let Ok(decoded) = codec.decode(encoded) {
+				shares[*idx] = decoded;
+			}
+		}
+		// malicious sessions leave out aggregated shares off the polynomial
+		let rejected = match s.malicious {
+			true => check_aggregates(&shares, &s.param, s.vectorSize),
+			false => Some(Vec::new()),
+		};
+		for &i in rejected.iter().flatten() {
+			shares[i] = Vec::new();
+		}
+		let responders = shares.iter().filter(|x| !x.is_empty()).count();
+		let sum = match rejected.is_some() && responders >= s.param.useD2 && responders > 0 {
+			true => Some(crate::reconstruct_sum(&shares, &s.param, s.vectorSize)),
+			false => None,
+		};
+		if let Some(published) = &r.sum {
+			let recomputed = sum.clone().unwrap_or_default();
+			if *published != recomputed {
+				divergences.push(Divergence::Sum {
+					round: *round, published: published.clone(), recomputed: recomputed,
+				});
+			}
+		}
+		audits.push(RoundAudit {
+			round: *round,
+			excluded: excluded,
+			sum: sum,
+		});
+	}
+	AuditReport {
+		records: records.len(),
+		rounds: audits,
+		divergences: divergences,
+	}
+}
+
+// "<session>/<BASE>-<round>" -> (BASE, round)
+fn split_topic(topic: &str) -> Option<(&str, usize)> {
+	let (_, rest) = topic.split_once('/')?;
+	let (name, round) = rest.rsplit_once('-')?;
+	Some((name, round.parse().ok()?))
+}
+
+fn unhex(frames: &[String]) -> Option<Vec<Vec<u8>>> {
+	frames.iter().map(|f| hex::decode(f).ok()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::transcript::hex_frames;
+
+	fn chain(entries: Vec<Entry>) -> Vec<Record> {
+		let mut last = GENESIS.to_string();
+		let mut records = Vec::new();
+		for (i, entry) in entries.into_iter().enumerate() {
+			let hash = Record::hash(&last, i as u64, &entry).unwrap();
+			records.push(Record { index: i as u64, prev: last, hash: hash.clone(), entry: entry });
+			last = hash;
+		}
+		records
+	}
+
+	fn message(client: &[u8], state: State, frames: &[Vec<u8>]) -> Entry {
+		Entry::Message {
+			client: hex::encode(client), round: 0, state: state,
+			frames: hex_frames(frames), accepted: true,
+		}
+	}
+
+	fn records() -> Vec<Record> {
+		let param = Param::new(3073700804129980417, 1414118249734601779, 20, 308414859194273485, 15);
+		chain(vec![
+			Entry::Setup {
+				maxClients: 2, vectorSize: 1, malicious: false, param: param,
+				clients: vec![hex::encode(b"c0"), hex::encode(b"c1")],
+			},
+			message(b"c0", State::InputSharing, &[vec![1u8; 8]]),
+			message(b"c1", State::InputSharing, &[vec![2u8; 8]]),
+		])
+	}
+
+	#[test]
+	fn untouched_chain_passes() {
+		let report = replay(&records());
+		assert!(report.passed(), "{:?}", report.divergences);
+		assert_eq!(report.records, 3);
+	}
+
+	#[test]
+	fn tampered_entry_breaks_the_chain() {
+		let mut records = records();
+		if let Entry::Message { frames, .. } = &mut records[1].entry {
+			frames[0] = hex::encode([9u8; 8]);
+		}
+		let report = replay(&records);
+		assert_eq!(report.divergences, vec![Divergence::BrokenChain { index: 1 }]);
+	}
+
+	#[test]
+	fn rehashed_record_breaks_the_next_link() {
+		// a forger fixing up the edited record's own hash still breaks its successor
+		let mut records = records();
+		if let Entry::Message { accepted, .. } = &mut records[1].entry {
+			*accepted = false;
+		}
+		records[1].hash = Record::hash(&records[1].prev, 1, &records[1].entry).unwrap();
+		let report = replay(&records);
+		assert_eq!(report.divergences, vec![Divergence::BrokenChain { index: 2 }]);
+	}
+
+	#[test]
+	fn dropped_record_breaks_the_chain() {
+		let mut records = records();
+		records.remove(1);
+		let report = replay(&records);
+		assert!(report.divergences.contains(&Divergence::BrokenChain { index: 1 }));
+	}
+}
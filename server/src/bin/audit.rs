@@ -0,0 +1,65 @@
+#![allow(non_snake_case)]
+
+use std::path::PathBuf;
+use std::process;
+
+use structopt::StructOpt;
+use server::transcript;
+use server::audit;
+
+/*
+    Replay a session transcript offline,
+    recompute the EC exclusions and the aggregate
+    and report where they diverge from what the server published.
+
+    Exit code: 0 consistent, 1 divergence, 2 unreadable transcript
+*/
+#[derive(Debug, StructOpt)]
+#[structopt(name = "audit", about = "Replay a server transcript and check its decisions")]
+struct AuditOpt {
+    /// Transcript written by the server with --transcript
+    #[structopt(parse(from_os_str))]
+    transcript: PathBuf,
+    /// Print the full report as JSON
+    #[structopt(long)]
+    json: bool,
+}
+
+fn main() {
+
+    let opt = AuditOpt::from_args();
+    let records = match transcript::read(&opt.transcript) {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("{}: {:?}", opt.transcript.display(), e);
+            process::exit(2);
+        },
+    };
+    let report = audit::replay(&records);
+
+    if opt.json {
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+    } else {
+        println!("{} records, {} rounds", report.records, report.rounds.len());
+        for r in report.rounds.iter() {
+            let excluded = match &r.excluded {
+                Some(e) => format!("{:?}", e),
+                None => "-".to_string(),
+            };
+            let sum = match &r.sum {
+                Some(s) => format!("{} values", s.len()),
+                None => "not reconstructable".to_string(),
+            };
+            println!("round {}: excluded {}, sum {}", r.round, excluded, sum);
+        }
+        for d in report.divergences.iter() {
+            println!("DIVERGENCE {:?}", d);
+        }
+        if report.passed() {
+            println!("OK");
+        }
+    }
+    if !report.passed() {
+        process::exit(1);
+    }
+}
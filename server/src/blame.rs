@@ -0,0 +1,200 @@
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Serialize, Deserialize};
+
+use crate::worker::ServerError;
+
+/*
+	Who failed error correction and why
+	One BlameReport per round, kept by the Session (Session::blame_reports),
+	published on "<session>/BL-<round>" and written to the blame file as JSON
+	Client indices are positions in the client list,
+	the same ones used in the dropouts arrays
+	A client is blamed as a dealer for its own shares
+	or as a holder for the corrections it sent about others'
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EcTest {
+	BitAndQuadratic,		// Input Bit, Quadratic, L2-norm bit: secrets are 0
+	Bounds,					// Input Bound, L2-norm sum, L2-norm bound: secrets sum to 0
+	Degree,					// shares aren't on one polynomial of degree D2
+	Responses,				// as a holder, corrections off for more than T dealers
+}
+
+/*
+	Outcome of one client's EC reconstruction,
+	the evidence behind a pass or fail
+*/
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EcCheck {
+	pub holders: Vec<usize>,	// clients whose corrections were reconstructed from
+	#[serde(default)]
+	pub off: Vec<usize>,		// holders whose corrections are off the decoded polynomials
+	#[serde(default)]
+	pub undecodable: bool,		// more than T holders off, tests couldn't be reconstructed
+	pub nonzero: Vec<usize>,	// positions in the zero section (L..2L) that aren't 0
+	pub sum: u64,				// total of the sum section (2L..3L), 0 when honest
+}
+
+impl EcCheck {
+	pub fn passed(&self) -> bool {
+		!self.undecodable && self.nonzero.is_empty() && self.sum == 0
+	}
+
+	// Passed and every holder agreed
+	pub fn clean(&self) -> bool {
+		self.passed() && self.off.is_empty()
+	}
+
+	pub fn failed_tests(&self) -> Vec<EcTest> {
+		let mut failed = Vec::new();
+		if self.undecodable {
+			failed.push(EcTest::Degree);
+		}
+		if !self.nonzero.is_empty() {
+			failed.push(EcTest::BitAndQuadratic);
+		}
+		if self.sum != 0 {
+			failed.push(EcTest::Bounds);
+		}
+		failed
+	}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Blame {
+	pub index: usize,			// in the client list
+	pub client: String,			// client ID
+	pub failed: Vec<EcTest>,
+	pub evidence: EcCheck,		// its own shares' check
+	#[serde(default)]
+	pub dealers: Vec<usize>,	// Responses: dealers its corrections were off for
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlameReport {
+	pub session: String,
+	pub round: usize,
+	pub checked: usize,			// clients that sent corrections
+	pub timestamp: u64,			// unix seconds
+	pub excluded: Vec<Blame>,
+}
+
+impl BlameReport {
+	pub fn new(session: &str, round: usize, checked: usize, excluded: Vec<Blame>) -> BlameReport {
+		let timestamp = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map(|d| d.as_secs())
+			.unwrap_or(0);
+		BlameReport {
+			session: session.to_string(),
+			round: round,
+			checked: checked,
+			timestamp: timestamp,
+			excluded: excluded,
+		}
+	}
+
+	pub fn indices(&self) -> Vec<usize> {
+		self.excluded.iter().map(|b| b.index).collect()
+	}
+
+	// What the server signs: session, round, then the excluded indices as u64 le
+	pub fn signed_bytes(session: &str, round: usize, indices: &[usize]) -> Vec<u8> {
+		let mut bytes = session.as_bytes().to_vec();
+		bytes.extend(&(round as u64).to_le_bytes());
+		for i in indices {
+			bytes.extend(&(*i as u64).to_le_bytes());
+		}
+		bytes
+	}
+}
+
+pub fn write_reports(path: &Path, reports: &[BlameReport]) -> Result<(), ServerError> {
+/*
+	Rewrite the whole file with every round so far,
+	same tmp-then-rename as the output file
+*/
+	let text = serde_json::to_string_pretty(reports).map_err(|_| ServerError::BlameFail(1))?;
+	let tmp = path.with_extension("tmp");
+	let mut file = fs::File::create(&tmp).map_err(|_| ServerError::BlameFail(2))?;
+	file.write_all(text.as_bytes()).map_err(|_| ServerError::BlameFail(2))?;
+	fs::rename(&tmp, path).map_err(|_| ServerError::BlameFail(2))?;
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn report() -> BlameReport {
+		let dealer = Blame {
+			index: 3,
+			client: "c3".to_string(),
+			failed: vec![EcTest::BitAndQuadratic],
+			evidence: EcCheck { holders: vec![0, 1, 2], off: vec![], undecodable: false, nonzero: vec![2], sum: 0 },
+			dealers: vec![],
+		};
+		let liar = Blame {
+			index: 5,
+			client: "c5".to_string(),
+			failed: vec![EcTest::Responses],
+			evidence: EcCheck::default(),
+			dealers: vec![0, 1],
+		};
+		BlameReport::new("bl", 4, 6, vec![dealer, liar])
+	}
+
+	#[test]
+	fn signed_bytes_layout() {
+		let r = report();
+		let bytes = BlameReport::signed_bytes(&r.session, r.round, &r.indices());
+		let mut expected = b"bl".to_vec();
+		expected.extend(&4u64.to_le_bytes());
+		expected.extend(&3u64.to_le_bytes());
+		expected.extend(&5u64.to_le_bytes());
+		assert_eq!(bytes, expected);
+		// nobody excluded still binds session and round
+		assert_eq!(BlameReport::signed_bytes("bl", 4, &[]), expected[..10].to_vec());
+	}
+
+	#[test]
+	fn check_verdicts() {
+		let clean = EcCheck { holders: vec![0, 1], ..EcCheck::default() };
+		assert!(clean.passed() && clean.clean());
+		assert!(clean.failed_tests().is_empty());
+
+		let corrected = EcCheck { off: vec![1], ..clean.clone() };
+		assert!(corrected.passed() && !corrected.clean());
+
+		let bad = EcCheck { undecodable: true, nonzero: vec![2], sum: 1, ..clean };
+		assert!(!bad.passed());
+		assert_eq!(bad.failed_tests(), vec![EcTest::Degree, EcTest::BitAndQuadratic, EcTest::Bounds]);
+	}
+
+	#[test]
+	fn json_round_trip() {
+		let path = std::env::temp_dir().join(format!("blame-{}.json", std::process::id()));
+		write_reports(&path, &[report()]).unwrap();
+		let read: Vec<BlameReport> = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+		fs::remove_file(&path).unwrap();
+		assert_eq!(read[0].indices(), vec![3, 5]);
+		assert_eq!(read[0].excluded[0].evidence, report().excluded[0].evidence);
+		assert_eq!(read[0].excluded[1].failed, vec![EcTest::Responses]);
+		assert_eq!(read[0].excluded[1].dealers, vec![0, 1]);
+	}
+
+	#[test]
+	fn older_reports_still_parse() {
+		// written before off, undecodable and dealers existed
+		let text = r#"{"index":1,"client":"c1","failed":["Bounds"],"evidence":{"holders":[0],"nonzero":[],"sum":7}}"#;
+		let b: Blame = serde_json::from_str(text).unwrap();
+		assert!(b.dealers.is_empty());
+		assert!(!b.evidence.undecodable && b.evidence.off.is_empty());
+		assert_eq!(b.evidence.failed_tests(), vec![EcTest::Bounds]);
+	}
+}
@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use serde::{Serialize, Deserialize};
+use p256::ecdsa::{SigningKey, VerifyKey};
+
+use crate::{Profile, Session, RoundResult};
+use crate::blame::BlameReport;
+use crate::param::Param;
+use crate::state::{State, StateCtx, Announcement};
+use crate::worker::ServerError;
+
+/*
+	Session checkpoint
+	Written after every state transition, everything a restarted
+	server needs to carry on from the state it was in:
+		client list & profiles	clients reconnect with the same socket identity
+		param					sharing params fixed in KE
+		corrections & shares	collected so far this round
+		dropouts, spBytes		state_task bookkeeping
+		announcements			broadcast log, seq numbers carry on after resume
+		signKey, results		clients already hold the server veriKey
+		blame					EC blame reports of earlier rounds
+	Messages handled after the last checkpoint are lost,
+	those clients count as dropouts of the resumed state.
+*/
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProfileRecord {
+	pub id: Vec<u8>,
+	pub veriKey: Vec<u8>,			// compressed SEC1 point
+	pub publicKey: Vec<u8>,
+	pub hasShared: bool,
+	#[serde(default)]
+	pub replayed: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+	pub session: String,
+	pub state: State,
+	pub round: usize,
+	pub clientList: Vec<Vec<u8>>,
+	pub profiles: Vec<ProfileRecord>,
+	pub param: Param,
+	pub correctionVecs: Vec<Vec<Vec<u64>>>,
+	pub shares: Vec<Vec<u64>>,
+	pub spBytes: Vec<u8>,
+	pub dropouts: Vec<usize>,
+	pub announcements: Vec<Announcement>,
+	pub signKey: Vec<u8>,
+	pub results: Vec<RoundResult>,
+	#[serde(default)]
+	pub blame: Vec<BlameReport>,
+}
+
+impl Checkpoint {
+
+	pub fn capture(session: &Session, ctx: &StateCtx, state: State) -> Result<Checkpoint, ServerError> {
+		let list = session.clientList.read().map_err(|_| ServerError::MutexLockFail(0))?;
+		let profiles = session.clientProfiles.read().map_err(|_| ServerError::MutexLockFail(0))?;
+		let mut records = Vec::new();
+		for id in list.iter() {
+			if let Some(p) = profiles.get(id) {
+				records.push(ProfileRecord {
+					id: id.clone(),
+					veriKey: VerifyKey::to_encoded_point(&p.veriKey, true).to_bytes().to_vec(),
+					publicKey: p.publicKey.clone(),
+					hasShared: p.hasShared,
+					replayed: p.replayed,
+				});
+			}
+		}
+		Ok(Checkpoint {
+			session: session.ID.clone(),
+			state: state,
+			round: session.round(),
+			clientList: list.clone(),
+			profiles: records,
+			param: session.param.read().map_err(|_| ServerError::MutexLockFail(0))?.clone(),
+			correctionVecs: session.correctionVecs.lock().map_err(|_| ServerError::MutexLockFail(0))?.clone(),
+			shares: session.shares.lock().map_err(|_| ServerError::MutexLockFail(0))?.clone(),
+			spBytes: ctx.spBytes.clone(),
+			dropouts: ctx.dropouts.clone(),
+			announcements: session.announcements.read().map_err(|_| ServerError::MutexLockFail(0))?.clone(),
+			signKey: SigningKey::to_bytes(&session.signKey).to_vec(),
+			results: session.results.read().map_err(|_| ServerError::MutexLockFail(0))?.clone(),
+			blame: session.blame.read().map_err(|_| ServerError::MutexLockFail(0))?.clone(),
+		})
+	}
+
+	pub fn load(path: &Path) -> Result<Checkpoint, ServerError> {
+		let text = fs::read_to_string(path).map_err(|_| ServerError::CheckpointFail(1))?;
+		serde_json::from_str(&text).map_err(|_| ServerError::CheckpointFail(2))
+	}
+
+	pub fn save(&self, path: &Path) -> Result<(), ServerError> {
+	/*
+		Write next to the target then rename,
+		a crash mid-write leaves the previous checkpoint intact
+	*/
+		let text = serde_json::to_string(self).map_err(|_| ServerError::CheckpointFail(3))?;
+		let tmp = path.with_extension("tmp");
+		let mut file = fs::File::create(&tmp).map_err(|_| ServerError::CheckpointFail(4))?;
+		file.write_all(text.as_bytes()).map_err(|_| ServerError::CheckpointFail(4))?;
+		file.sync_all().map_err(|_| ServerError::CheckpointFail(4))?;
+		fs::rename(&tmp, path).map_err(|_| ServerError::CheckpointFail(4))?;
+		Ok(())
+	}
+
+	pub fn restore(&self, session: &mut Session) -> Result<(), ServerError> {
+	/*
+		Put the saved state back into a freshly built session,
+		ctx fields are picked up by state_task
+	*/
+		if self.session != session.ID {
+			return Err(ServerError::CheckpointFail(5));
+		}
+		let mut profiles = HashMap::new();
+		for r in self.profiles.iter() {
+			let veriKey = VerifyKey::new(&r.veriKey).map_err(|_| ServerError::CheckpointFail(5))?;
+			profiles.insert(r.id.clone(), Profile {
+				veriKey: veriKey,
+				publicKey: r.publicKey.clone(),
+				hasShared: r.hasShared,
+				replayed: r.replayed,
+			});
+		}
+		session.signKey = SigningKey::new(&self.signKey).map_err(|_| ServerError::CheckpointFail(5))?;
+		*session.announcements.write().map_err(|_| ServerError::MutexLockFail(0))? = self.announcements.clone();
+		*session.results.write().map_err(|_| ServerError::MutexLockFail(0))? = self.results.clone();
+		*session.blame.write().map_err(|_| ServerError::MutexLockFail(0))? = self.blame.clone();
+		*session.STATE.write().map_err(|_| ServerError::MutexLockFail(0))? = self.state;
+		*session.ROUND.write().map_err(|_| ServerError::MutexLockFail(0))? = self.round;
+		*session.clientList.write().map_err(|_| ServerError::MutexLockFail(0))? = self.clientList.clone();
+		// past key exchange the session runs with the clients that made it
+		if !matches!(self.state, State::Handshake | State::KeyExchange) {
+			*session.MAX.write().map_err(|_| ServerError::MutexLockFail(0))? = self.clientList.len();
+		}
+		*session.clientProfiles.write().map_err(|_| ServerError::MutexLockFail(0))? = profiles;
+		*session.param.write().map_err(|_| ServerError::MutexLockFail(0))? = self.param.clone();
+		*session.correctionVecs.lock().map_err(|_| ServerError::MutexLockFail(0))? = self.correctionVecs.clone();
+		*session.shares.lock().map_err(|_| ServerError::MutexLockFail(0))? = self.shares.clone();
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn param() -> Param {
+		Param::new(3073700804129980417, 1414118249734601779, 20, 308414859194273485, 15)
+	}
+
+	fn checkpoint() -> Checkpoint {
+		let client = SigningKey::new(&[7u8; 32]).unwrap();
+		Checkpoint {
+			session: "ckpt".to_string(),
+			state: State::InputSharing,
+			round: 2,
+			clientList: vec![b"c0".to_vec(), b"c1".to_vec()],
+			profiles: vec![ProfileRecord {
+				id: b"c0".to_vec(),
+				veriKey: VerifyKey::to_encoded_point(&VerifyKey::from(&client), true).to_bytes().to_vec(),
+				publicKey: vec![4u8; 65],
+				hasShared: true,
+				replayed: false,
+			}],
+			param: param(),
+			correctionVecs: vec![vec![vec![1, 2, 3]]],
+			shares: vec![vec![5, 6], vec![]],
+			spBytes: vec![9, 9],
+			dropouts: vec![1],
+			announcements: vec![("EC".to_string(), vec![vec![1u8]])],
+			signKey: vec![3u8; 32],
+			results: vec![RoundResult::new("ckpt", 1, 2, &[], vec![10, 20])],
+			blame: Vec::new(),
+		}
+	}
+
+	#[test]
+	fn save_load_round_trip() {
+		let path = std::env::temp_dir().join(format!("ckpt-{}.json", std::process::id()));
+		let saved = checkpoint();
+		saved.save(&path).unwrap();
+		assert!(!path.with_extension("tmp").exists());
+		let loaded = Checkpoint::load(&path).unwrap();
+		fs::remove_file(&path).unwrap();
+
+		assert_eq!(loaded.session, saved.session);
+		assert_eq!(loaded.state, saved.state);
+		assert_eq!(loaded.round, saved.round);
+		assert_eq!(loaded.clientList, saved.clientList);
+		assert_eq!(loaded.profiles[0].veriKey, saved.profiles[0].veriKey);
+		assert_eq!(loaded.correctionVecs, saved.correctionVecs);
+		assert_eq!(loaded.shares, saved.shares);
+		assert_eq!(loaded.dropouts, saved.dropouts);
+		assert_eq!(loaded.announcements, saved.announcements);
+		assert_eq!(loaded.results[0].sum, saved.results[0].sum);
+	}
+
+	#[test]
+	fn load_rejects_garbage() {
+		let path = std::env::temp_dir().join(format!("ckpt-bad-{}.json", std::process::id()));
+		fs::write(&path, "{\"session\":").unwrap();
+		assert!(matches!(Checkpoint::load(&path), Err(ServerError::CheckpointFail(2))));
+		fs::remove_file(&path).unwrap();
+		assert!(matches!(Checkpoint::load(&path), Err(ServerError::CheckpointFail(1))));
+	}
+
+	#[test]
+	fn restore_into_fresh_session() {
+		let saved = checkpoint();
+		let mut session = Session::new_semi_honest("ckpt", 4, 2, 1, 10, 10, false, param());
+		saved.restore(&mut session).unwrap();
+		assert_eq!(session.state(), State::InputSharing);
+		assert_eq!(session.round(), 2);
+		assert_eq!(*session.clientList.read().unwrap(), saved.clientList);
+		// past key exchange MAX shrinks to the clients that made it
+		assert_eq!(*session.MAX.read().unwrap(), 2);
+		assert!(session.clientProfiles.read().unwrap()[&b"c0".to_vec()].hasShared);
+		assert_eq!(*session.shares.lock().unwrap(), saved.shares);
+
+		let mut other = Session::new_semi_honest("other", 4, 2, 1, 10, 10, false, param());
+		assert!(matches!(saved.restore(&mut other), Err(ServerError::CheckpointFail(5))));
+	}
+}
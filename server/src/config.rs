@@ -0,0 +1,495 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use serde::{Serialize, Deserialize};
+use structopt::StructOpt;
+use rand_core::OsRng;
+use p256::ecdsa::{SigningKey, VerifyKey};
+
+use crate::Session;
+use crate::param::Param;
+use crate::logging::LogFormat;
+
+/*
+	Server configuration
+	Values come from an optional TOML / JSON file (picked by extension),
+	named flags override the file, then everything is validated
+	before a Session is built from it.
+*/
+
+#[derive(Debug)]
+pub enum ConfigError {
+	Read(String),
+	Parse(String),
+	Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			ConfigError::Read(s) => write!(f, "cannot read config: {}", s),
+			ConfigError::Parse(s) => write!(f, "cannot parse config: {}", s),
+			ConfigError::Invalid(s) => write!(f, "invalid config: {}", s),
+		}
+	}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FieldConfig {
+	pub prime: u64,
+	pub root2: u64,					// 2^root2_degree-th root of unity
+	pub root2_degree: usize,
+	pub root3: u64,					// 3^root3_degree-th root of unity
+	pub root3_degree: usize,
+}
+
+impl Default for FieldConfig {
+	fn default() -> FieldConfig {
+		FieldConfig {
+			prime: 3073700804129980417,		// 62 bits
+			root2: 1414118249734601779,
+			root2_degree: 20,
+			root3: 308414859194273485,
+			root3_degree: 15,
+		}
+	}
+}
+
+impl FieldConfig {
+	pub fn param(&self) -> Param {
+		Param::new(self.prime, self.root2, self.root2_degree, self.root3, self.root3_degree)
+	}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+	pub session: String,
+	pub clients: usize,						// max clients
+	pub vector_len: usize,
+	pub dropouts: usize,
+	pub malicious: bool,
+	pub corruption: Option<usize>,			// malicious only
+	pub input_bit_limit: Option<usize>,		// malicious only
+	pub session_time: usize,				// ms per state
+	pub is_session_time: usize,				// ms for input sharing
+	pub rounds: usize,
+	pub min_contributors: usize,			// K, abort rather than reveal a sum of fewer inputs
+	pub ip: Option<String>,					// None binds every interface
+	pub msg_port: usize,
+	pub broadcast_port: usize,
+	pub catchup_port: usize,				// REQ/REP for missed broadcasts
+	pub metrics_port: Option<usize>,		// HTTP /metrics on localhost, None turns it off
+	pub workers: usize,
+	pub log_level: String,					// tracing filter, RUST_LOG overrides
+	pub log_format: LogFormat,				// text or json
+	pub checkpoint: Option<PathBuf>,		// state file, resumed from if present
+	pub output: Option<PathBuf>,			// per-round results, .csv or JSON
+	pub blame: Option<PathBuf>,				// per-round EC blame reports, JSON
+	pub transcript: Option<PathBuf>,		// hash-chained protocol log, replayed by audit
+	pub enrollment: Option<PathBuf>,		// client ID -> hex veriKey, None admits anyone
+	pub key_file: Option<PathBuf>,			// hex signKey, created if missing, None uses a key per session
+	pub field: FieldConfig,
+}
+
+impl Default for ServerConfig {
+	fn default() -> ServerConfig {
+		ServerConfig {
+			session: crate::DEFAULT_SESSION.to_string(),
+			clients: 0,
+			vector_len: 0,
+			dropouts: 0,
+			malicious: false,
+			corruption: None,
+			input_bit_limit: None,
+			session_time: 20000,
+			is_session_time: 20000,
+			rounds: 1,
+			min_contributors: 2,
+			ip: None,
+			msg_port: 8888,
+			broadcast_port: 9999,
+			catchup_port: 9998,
+			metrics_port: None,
+			workers: 10,
+			log_level: "info".to_string(),
+			log_format: LogFormat::Text,
+			checkpoint: None,
+			output: None,
+			blame: None,
+			transcript: None,
+			enrollment: None,
+			key_file: None,
+			field: FieldConfig::default(),
+		}
+	}
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "server", about = "Secure aggregation server")]
+pub struct ServerOpt {
+	/// TOML or JSON config file, flags override it
+	#[structopt(short, long, parse(from_os_str))]
+	pub config: Option<PathBuf>,
+	/// Print the effective config and exit
+	#[structopt(long)]
+	pub print_config: bool,
+	/// Print the hex veriKey of --key-file (creating it if missing) and exit
+	#[structopt(long)]
+	pub print_verify_key: bool,
+
+	#[structopt(long)]
+	pub session: Option<String>,
+	/// Maximum number of clients
+	#[structopt(long)]
+	pub clients: Option<usize>,
+	#[structopt(long)]
+	pub vector_len: Option<usize>,
+	/// Expected client dropouts
+	#[structopt(long)]
+	pub dropouts: Option<usize>,
+	/// Run the malicious protocol (needs --corruption and --input-bit-limit)
+	#[structopt(long)]
+	pub malicious: bool,
+	/// Corrupted clients tolerated
+	#[structopt(long)]
+	pub corruption: Option<usize>,
+	/// Bits per input element
+	#[structopt(long)]
+	pub input_bit_limit: Option<usize>,
+	/// Time allowed for each state, ms
+	#[structopt(long)]
+	pub session_time: Option<usize>,
+	/// Time allowed for input sharing, ms
+	#[structopt(long)]
+	pub is_session_time: Option<usize>,
+	#[structopt(long)]
+	pub rounds: Option<usize>,
+	/// Fewest inputs a revealed sum may contain
+	#[structopt(long)]
+	pub min_contributors: Option<usize>,
+	#[structopt(long)]
+	pub ip: Option<String>,
+	#[structopt(long)]
+	pub msg_port: Option<usize>,
+	#[structopt(long)]
+	pub broadcast_port: Option<usize>,
+	/// Port serving broadcasts a subscriber missed
+	#[structopt(long)]
+	pub catchup_port: Option<usize>,
+	/// Serve Prometheus metrics on http://127.0.0.1:<port>/metrics
+	#[structopt(long)]
+	pub metrics_port: Option<usize>,
+	#[structopt(long)]
+	pub workers: Option<usize>,
+	/// Log filter: error, warn, info, debug or trace (shares and keys only at trace)
+	#[structopt(long)]
+	pub log_level: Option<String>,
+	/// Log output: text or json
+	#[structopt(long)]
+	pub log_format: Option<LogFormat>,
+	/// Checkpoint file, written at every state change and resumed from on restart
+	#[structopt(long, parse(from_os_str))]
+	pub checkpoint: Option<PathBuf>,
+	/// Write every round's aggregate here, CSV if it ends in .csv, JSON otherwise
+	#[structopt(long, parse(from_os_str))]
+	pub output: Option<PathBuf>,
+	/// Write every round's EC blame report here as JSON: who was excluded and why
+	#[structopt(long, parse(from_os_str))]
+	pub blame: Option<PathBuf>,
+	/// Record every broadcast and client message here, hash-chained, for the audit tool
+	#[structopt(long, parse(from_os_str))]
+	pub transcript: Option<PathBuf>,
+	/// TOML or JSON map of client ID to hex veriKey, only these clients may join
+	#[structopt(long, parse(from_os_str))]
+	pub enrollment: Option<PathBuf>,
+	/// Long-term server signing key, hex, generated on first use
+	#[structopt(long, parse(from_os_str))]
+	pub key_file: Option<PathBuf>,
+	#[structopt(long)]
+	pub prime: Option<u64>,
+	#[structopt(long)]
+	pub root2: Option<u64>,
+	#[structopt(long)]
+	pub root2_degree: Option<usize>,
+	#[structopt(long)]
+	pub root3: Option<u64>,
+	#[structopt(long)]
+	pub root3_degree: Option<usize>,
+}
+
+impl ServerConfig {
+
+	pub fn load(path: &Path) -> Result<ServerConfig, ConfigError> {
+		let text = match fs::read_to_string(path) {
+			Ok(t) => t,
+			Err(e) => return Err(ConfigError::Read(format!("{}: {}", path.display(), e))),
+		};
+		match path.extension().and_then(|e| e.to_str()) {
+			Some("json") => serde_json::from_str(&text)
+				.map_err(|e| ConfigError::Parse(format!("{}: {}", path.display(), e))),
+			_ => toml::from_str(&text)
+				.map_err(|e| ConfigError::Parse(format!("{}: {}", path.display(), e))),
+		}
+	}
+
+	pub fn from_opt(opt: &ServerOpt) -> Result<ServerConfig, ConfigError> {
+	/*
+		File (or defaults) first, then flags on top
+		Not validated yet so --print-config can show a broken one
+	*/
+		let mut cfg = match &opt.config {
+			Some(path) => ServerConfig::load(path)?,
+			None => ServerConfig::default(),
+		};
+		if let Some(v) = &opt.session { cfg.session = v.clone(); }
+		if let Some(v) = opt.clients { cfg.clients = v; }
+		if let Some(v) = opt.vector_len { cfg.vector_len = v; }
+		if let Some(v) = opt.dropouts { cfg.dropouts = v; }
+		if opt.malicious { cfg.malicious = true; }
+		if let Some(v) = opt.corruption { cfg.corruption = Some(v); }
+		if let Some(v) = opt.input_bit_limit { cfg.input_bit_limit = Some(v); }
+		if let Some(v) = opt.session_time { cfg.session_time = v; }
+		if let Some(v) = opt.is_session_time { cfg.is_session_time = v; }
+		if let Some(v) = opt.rounds { cfg.rounds = v; }
+		if let Some(v) = opt.min_contributors { cfg.min_contributors = v; }
+		if let Some(v) = &opt.ip { cfg.ip = Some(v.clone()); }
+		if let Some(v) = opt.msg_port { cfg.msg_port = v; }
+		if let Some(v) = opt.broadcast_port { cfg.broadcast_port = v; }
+		if let Some(v) = opt.catchup_port { cfg.catchup_port = v; }
+		if let Some(v) = opt.metrics_port { cfg.metrics_port = Some(v); }
+		if let Some(v) = opt.workers { cfg.workers = v; }
+		if let Some(v) = &opt.log_level { cfg.log_level = v.clone(); }
+		if let Some(v) = opt.log_format { cfg.log_format = v; }
+		if let Some(v) = &opt.checkpoint { cfg.checkpoint = Some(v.clone()); }
+		if let Some(v) = &opt.output { cfg.output = Some(v.clone()); }
+		if let Some(v) = &opt.blame { cfg.blame = Some(v.clone()); }
+		if let Some(v) = &opt.transcript { cfg.transcript = Some(v.clone()); }
+		if let Some(v) = &opt.enrollment { cfg.enrollment = Some(v.clone()); }
+		if let Some(v) = &opt.key_file { cfg.key_file = Some(v.clone()); }
+		if let Some(v) = opt.prime { cfg.field.prime = v; }
+		if let Some(v) = opt.root2 { cfg.field.root2 = v; }
+		if let Some(v) = opt.root2_degree { cfg.field.root2_degree = v; }
+		if let Some(v) = opt.root3 { cfg.field.root3 = v; }
+		if let Some(v) = opt.root3_degree { cfg.field.root3_degree = v; }
+		Ok(cfg)
+	}
+
+	pub fn validate(&self) -> Result<(), ConfigError> {
+		let invalid = |s: &str| Err(ConfigError::Invalid(s.to_string()));
+		if self.session.is_empty() || self.session.contains('/') {
+			return invalid("session must be non-empty and contain no '/'");
+		}
+		if self.clients == 0 {
+			return invalid("clients must be at least 1");
+		}
+		if self.vector_len == 0 {
+			return invalid("vector_len must be at least 1");
+		}
+		if is_prime(self.vector_len) {
+			return invalid("vector_len must not be prime, it is split into packed blocks");
+		}
+		if self.dropouts >= self.clients {
+			return invalid("dropouts must be less than clients");
+		}
+		if self.malicious {
+			match (self.corruption, self.input_bit_limit) {
+				(Some(t), Some(s)) => {
+					if self.dropouts + t >= self.clients {
+						return invalid("dropouts + corruption must be less than clients");
+					}
+					if s == 0 || s > 32 {
+						return invalid("input_bit_limit must be in 1..=32");
+					}
+				},
+				_ => return invalid("malicious mode needs corruption and input_bit_limit"),
+			}
+		}
+		if self.session_time == 0 || self.is_session_time == 0 {
+			return invalid("session_time and is_session_time must be positive");
+		}
+		if self.rounds == 0 {
+			return invalid("rounds must be at least 1");
+		}
+		if self.min_contributors == 0 || self.min_contributors > self.clients {
+			return invalid("min_contributors must be in 1..=clients");
+		}
+		for port in [self.msg_port, self.broadcast_port, self.catchup_port].iter() {
+			if *port == 0 || *port > 65535 {
+				return invalid("ports must be in 1..=65535");
+			}
+		}
+		if self.msg_port == self.broadcast_port
+			|| self.msg_port == self.catchup_port
+			|| self.broadcast_port == self.catchup_port {
+			return invalid("msg_port, broadcast_port and catchup_port must differ");
+		}
+		if let Some(port) = self.metrics_port {
+			if port == 0 || port > 65535 {
+				return invalid("ports must be in 1..=65535");
+			}
+			if [self.msg_port, self.broadcast_port, self.catchup_port].contains(&port) {
+				return invalid("metrics_port must differ from the other ports");
+			}
+		}
+		if self.workers == 0 {
+			return invalid("workers must be at least 1");
+		}
+		let f = &self.field;
+		if f.prime < 3 || f.prime >> 63 != 0 {
+			return invalid("prime must be an odd prime below 2^63");
+		}
+		if f.root2 == 0 || f.root2 >= f.prime || f.root3 == 0 || f.root3 >= f.prime {
+			return invalid("roots must be nonzero field elements");
+		}
+		if f.root2_degree == 0 || f.root3_degree == 0 {
+			return invalid("root degrees must be positive");
+		}
+		Ok(())
+	}
+
+	pub fn load_enrollment(&self) -> Result<Option<HashMap<Vec<u8>, Vec<u8>>>, ConfigError> {
+	/*
+		"Client 0" = "02ab..." per line (or a JSON object)
+		Keys are hex SEC1 points, stored compressed
+	*/
+		let path = match &self.enrollment {
+			Some(p) => p,
+			None => return Ok(None),
+		};
+		let text = match fs::read_to_string(path) {
+			Ok(t) => t,
+			Err(e) => return Err(ConfigError::Read(format!("{}: {}", path.display(), e))),
+		};
+		let entries: HashMap<String, String> = match path.extension().and_then(|e| e.to_str()) {
+			Some("json") => serde_json::from_str(&text)
+				.map_err(|e| ConfigError::Parse(format!("{}: {}", path.display(), e)))?,
+			_ => toml::from_str(&text)
+				.map_err(|e| ConfigError::Parse(format!("{}: {}", path.display(), e)))?,
+		};
+		let mut enrollment = HashMap::new();
+		for (id, key) in entries {
+			let vk = hex::decode(key.trim()).ok()
+				.and_then(|b| VerifyKey::new(&b).ok())
+				.ok_or_else(|| ConfigError::Invalid(format!("enrollment key of {} is not a hex P-256 point", id)))?;
+			enrollment.insert(id.into_bytes(), VerifyKey::to_encoded_point(&vk, true).to_bytes().to_vec());
+		}
+		Ok(Some(enrollment))
+	}
+
+	pub fn load_or_create_key(&self) -> Result<Option<Vec<u8>>, ConfigError> {
+	/*
+		Read the signKey from key_file,
+		generate and save one (owner-only on unix) on first run
+	*/
+		let path = match &self.key_file {
+			Some(p) => p,
+			None => return Ok(None),
+		};
+		if path.exists() {
+			let text = fs::read_to_string(path)
+				.map_err(|e| ConfigError::Read(format!("{}: {}", path.display(), e)))?;
+			let key = hex::decode(text.trim())
+				.map_err(|e| ConfigError::Parse(format!("{}: {}", path.display(), e)))?;
+			if SigningKey::new(&key).is_err() {
+				return Err(ConfigError::Invalid(format!("{} is not a P-256 signing key", path.display())));
+			}
+			return Ok(Some(key));
+		}
+		let key = SigningKey::to_bytes(&SigningKey::random(&mut OsRng)).to_vec();
+		let mut options = fs::OpenOptions::new();
+		options.write(true).create_new(true);
+		#[cfg(unix)]
+		{
+			use std::os::unix::fs::OpenOptionsExt;
+			options.mode(0o600);
+		}
+		let mut file = options.open(path)
+			.map_err(|e| ConfigError::Read(format!("{}: {}", path.display(), e)))?;
+		file.write_all(hex::encode(&key).as_bytes())
+			.map_err(|e| ConfigError::Read(format!("{}: {}", path.display(), e)))?;
+		Ok(Some(key))
+	}
+
+	pub fn to_toml(&self) -> String {
+		toml::to_string_pretty(self).unwrap()
+	}
+
+	pub fn build_session(&self) -> Result<Session, ConfigError> {
+	/*
+		Loads the enrollment and key files,
+		then resumes from the checkpoint if there is one
+	*/
+		let enrollment = self.load_enrollment()?;
+		let signKey = self.load_or_create_key()?;
+		let param = self.field.param();
+		let session = match self.malicious {
+			true => Session::new_malicious(
+				&self.session,
+				self.clients,
+				self.vector_len,
+				self.input_bit_limit.unwrap(),
+				self.dropouts,
+				self.session_time,
+				self.is_session_time,
+				self.corruption.unwrap(),
+				true,
+				param
+			),
+			false => Session::new_semi_honest(
+				&self.session,
+				self.clients,
+				self.vector_len,
+				self.dropouts,
+				self.session_time,
+				self.is_session_time,
+				false,
+				param
+			),
+		};
+		let mut session = session
+			.with_rounds(self.rounds)
+			.with_min_contributors(self.min_contributors);
+		if let Some(path) = &self.output {
+			session = session.with_output(path);
+		}
+		if let Some(path) = &self.blame {
+			session = session.with_blame(path);
+		}
+		if let Some(path) = &self.transcript {
+			session = session.with_transcript(path)
+				.map_err(|e| ConfigError::Invalid(format!("transcript {}: {:?}", path.display(), e)))?;
+		}
+		if let Some(e) = enrollment {
+			session = session.with_enrollment(e);
+		}
+		if let Some(path) = &self.checkpoint {
+			session = session.with_checkpoint(path)
+				.map_err(|e| ConfigError::Invalid(format!("checkpoint {}: {:?}", path.display(), e)))?;
+		}
+		// after the checkpoint, the configured key wins over a saved one
+		if let Some(k) = signKey {
+			session = session.with_sign_key(&k)
+				.map_err(|e| ConfigError::Invalid(format!("key_file: {:?}", e)))?;
+		}
+		Ok(session)
+	}
+}
+
+fn is_prime(n: usize) -> bool {
+	if n < 2 {
+		return false;
+	}
+	let mut i = 2;
+	while i * i <= n {
+		if n % i == 0 {
+			return false;
+		}
+		i += 1;
+	}
+	true
+}
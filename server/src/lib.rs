@@ -7,9 +7,17 @@
 use std::collections::HashMap;
 use std::thread;
 use std::sync::*;
-use std::time::Instant;
+use std::time::{Duration, Instant};
+use std::path::{Path, PathBuf};
+use std::convert::TryInto;
+use std::str;
+use std::panic::{self, AssertUnwindSafe};
 
 use rand_core::{RngCore, OsRng};
+use tracing::{trace, debug, info, warn, error, info_span, Instrument};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender, UnboundedReceiver};
+use transport::Transport;
+use sha2::{Sha256, Digest};
 
 use signature::Signature as _;
 use p256::{
@@ -25,13 +33,28 @@ use pss::ModPow;
 
 mod sockets;
 pub mod worker;
+pub mod state;
+pub mod config;
 pub mod param;
+pub mod checkpoint;
+pub mod output;
+pub mod blame;
+pub mod transcript;
+pub mod audit;
+pub mod metrics;
+pub mod logging;
 mod tests;
 use sockets::*;
 use param::*;
 use worker::*;
 use worker::ServerError;
 use worker::WorkerError;
+pub use state::*;
+use checkpoint::Checkpoint;
+use blame::{Blame, BlameReport};
+use transcript::{Transcript, Entry, hex_frames};
+pub use output::RoundResult;
+use metrics::{Metrics, Labels};
 use tests::*;
 
 
@@ -40,10 +63,29 @@ pub struct Profile {
 	veriKey: VerifyKey,
 	publicKey:  Vec<u8>,
 	hasShared: bool,
+	replayed: bool,			// sent a message twice this round, excluded
 }
 
+pub const DEFAULT_SESSION: &str = "default";
+
+/*
+	Registry of aggregation sessions
+	Clients name a session in every message (handshake included),
+	each session runs its own params, client list and state machine,
+	one ROUTER port and one PUB port are shared by all of them
+*/
 pub struct Server {
-	STATE: RwLock<usize>,	//readWrite Lock
+	sessions: RwLock<HashMap<String, Arc<Session>>>,
+	inboxSender: UnboundedSender<Vec<Vec<u8>>>,				// server_task -> dispatch_task
+	inboxReciever: Mutex<Option<UnboundedReceiver<Vec<Vec<u8>>>>>,
+}
+
+pub struct Session {
+	ID: String,
+	STATE: RwLock<State>,	//readWrite Lock
+	machine: StateMachine,								// transitions, hooks and handlers per State
+	ROUND: RwLock<usize>,								// Current aggregation round
+	rounds: usize,										// Rounds to run before shutting down
 	MAX: RwLock<usize>,
 	V: usize,											// Vector size
 	S: Option<usize>,									// Input Bit Limit (only used in ML)
@@ -57,19 +99,233 @@ pub struct Server {
 	clientProfiles: RwLock<HashMap<Vec<u8>, Profile>>,	// key = ID, value = Profile
 	correctionVecs: Mutex<Vec<Vec<Vec<u64>>>>,
 	shares: Mutex<Vec<Vec<u64>>>,
+	digests: Mutex<HashMap<Vec<u8>, Vec<Vec<u8>>>>,		// client ID -> SHA-256 of its messages this round
+	eventSender: UnboundedSender<Event>,				// handlers, timers and admin commands
+	eventReciever: Mutex<Option<UnboundedReceiver<Event>>>,	// taken by state_task
+	checkpointPath: Option<PathBuf>,					// saved after every transition
+	signKey: SigningKey,								// server identity, signs every broadcast
+	results: RwLock<Vec<RoundResult>>,					// one per finished round
+	outputPath: Option<PathBuf>,						// results written here, .csv or JSON
+	blame: RwLock<Vec<BlameReport>>,					// one per round that ran EC
+	blamePath: Option<PathBuf>,							// blame reports written here as JSON
+	transcript: Option<Transcript>,						// hash-chained log of broadcasts and client messages
+	announcements: RwLock<Vec<Announcement>>,			// every broadcast, index = seq, served by catchup_task
+	enrollment: Option<HashMap<Vec<u8>, Vec<u8>>>,		// client ID -> compressed veriKey, None admits anyone
+	minContributors: usize,								// K, no sum over fewer inputs is revealed
+	resume: Mutex<Option<Checkpoint>>,					// loaded checkpoint, consumed by state_task
+	metrics: Metrics,									// served by Server::metrics_task
 }
 
 
 impl Server {
 
-	pub fn new_malicious(maxClients: usize,
+	pub fn new() -> Server {
+		let (tx, rx) = unbounded_channel();
+		Server {
+			sessions: RwLock::new(HashMap::new()),
+			inboxSender: tx,
+			inboxReciever: Mutex::new(Some(rx)),
+		}
+	}
+
+	pub fn add_session(&self, session: Session) -> Result<Arc<Session>, ServerError> {
+	/*
+		Session IDs prefix PUB topics as "<session>/<topic>-<round>"
+		so they can't contain '/'
+	*/
+		if session.ID.is_empty() || session.ID.contains('/') {
+			return Err(ServerError::InvalidSession(0));
+		}
+		let mut guard = match self.sessions.write() {
+			Ok(guard) => guard,
+			Err(_) => return Err(ServerError::MutexLockFail(0)),
+		};
+		if guard.contains_key(&session.ID) {
+			return Err(ServerError::SessionExisted(0));
+		}
+		let session = Arc::new(session);
+		guard.insert(session.ID.clone(), session.clone());
+		info!(session = %session.ID, "session registered");
+		Ok(session)
+	}
+
+	pub fn session(&self, ID: &str) -> Option<Arc<Session>> {
+		self.sessions.read().unwrap().get(ID).cloned()
+	}
+
+	pub fn session_ids(&self) -> Vec<String> {
+		self.sessions.read().unwrap().keys().cloned().collect()
+	}
+
+	pub fn remove_session(&self, ID: &str) -> Option<Arc<Session>> {
+		self.sessions.write().unwrap().remove(ID)
+	}
+
+	pub fn server_task(&self, transport: &dyn Transport) -> Result<usize, ServerError>  {
+	/*
+		Transport I/O only, blocks while idle
+		client messages are handed to dispatch_task,
+		the transport routes worker replies back by identity
+	*/
+		let listener = transport.listen().map_err(|_| ServerError::TransportFail(0))?;
+		loop {
+			match listener.recv(None) {
+				Ok(Some(frames)) => {
+					if self.inboxSender.send(frames).is_err() {
+						return Err(ServerError::ThreadSenderFail(0))
+					}
+				},
+				Ok(None) => (),
+				Err(_) => return Err(ServerError::TransportFail(0)),
+			}
+		}
+	}
+
+	pub fn publisher_task(&self, transport: &dyn Transport) -> Result<usize, ServerError> {
+	/*
+		Sessions publish on their own publisher,
+		the transport carries all of them to the subscribers
+	*/
+		transport.relay().map_err(|_| ServerError::TransportFail(1))?;
+		return Ok(0)
+	}
+
+	pub fn catchup_task(&self, transport: &dyn Transport) -> Result<usize, ServerError> {
+	/*
+		Request: [session, seq]
+		Reply: [topic, seq, frames...] or one empty frame if
+		there's no such session or seq isn't published yet
+	*/
+		let responder = transport.responder().map_err(|_| ServerError::TransportFail(2))?;
+		loop {
+			let request = match responder.recv(None) {
+				Ok(Some(r)) => r,
+				Ok(None) => continue,
+				Err(_) => return Err(ServerError::FailPublish(0)),
+			};
+			let found = match (request.get(0), request.get(1)) {
+				(Some(id), Some(seq)) if seq.len() == 8 => {
+					let seq = u64::from_le_bytes(seq[..].try_into().unwrap()) as usize;
+					str::from_utf8(id).ok()
+						.and_then(|id| self.session(id))
+						.and_then(|s| s.announcement(seq).map(|a| (seq, a)))
+				},
+				_ => None,
+			};
+			let reply = match found {
+				Some((seq, (topic, frames))) => {
+					let mut reply = vec![topic.into_bytes(), (seq as u64).to_le_bytes().to_vec()];
+					reply.extend(frames);
+					reply
+				},
+				None => vec![Vec::new()],
+			};
+			responder.send(reply);
+		}
+	}
+
+	pub fn metrics_task(&self, ip: Option<&str>, port: usize) -> Result<usize, ServerError> {
+	/*
+		HTTP endpoint for Prometheus,
+		every registered session's metrics
+	*/
+		metrics::serve(ip, port, || {
+			let sessions: Vec<Arc<Session>> = self.sessions.read().unwrap().values().cloned().collect();
+			let registries: Vec<&Metrics> = sessions.iter().map(|s| &s.metrics).collect();
+			metrics::render(&registries)
+		})
+	}
+
+	pub async fn dispatch_task(self: Arc<Self>,
+		transport: Arc<dyn Transport>, concurrency: usize) -> Result<usize, ServerError> {
+	/*
+		One task per client message,
+		at most `concurrency` handlers run at once, each with its own Worker
+		Handlers block (locks, crypto), so they go to the blocking pool
+	*/
+		let mut inbox = match self.inboxReciever.lock() {
+			Ok(mut guard) => match guard.take() {
+				Some(rx) => rx,
+				None => return Err(ServerError::UnknownState(0)),
+			},
+			Err(_) => return Err(ServerError::MutexLockFail(0)),
+		};
+		let (poolTx, mut poolRx) = unbounded_channel();
+		for i in 0..concurrency {
+			let reply = transport.replier().map_err(|_| ServerError::TransportFail(0))?;
+			poolTx.send(Worker::new(&format!("Worker{}", i), reply));
+		}
+		while let Some(frames) = inbox.recv().await {
+			let worker = match poolRx.recv().await {
+				Some(w) => w,
+				None => return Err(ServerError::ThreadSenderFail(0)),
+			};
+			let server = self.clone();
+			let poolTx = poolTx.clone();
+			tokio::task::spawn_blocking(move || {
+				// a panicking handler still hands its worker back
+				if panic::catch_unwind(AssertUnwindSafe(|| server.route(&worker, frames))).is_err() {
+					error!(worker = %worker.ID, "handler panicked, message dropped");
+				}
+				poolTx.send(worker);
+			});
+		}
+		return Ok(0)
+	}
+
+	fn route(&self, worker: &Worker, frames: Vec<Vec<u8>>) {
+	/*
+		Message: [ID, session, round, payload...]
+		Route to the session named in it
+	*/
+		let mut frames = frames.into_iter();
+		let clientID = take_id(&mut frames);
+		let sessionID = match take_session(&mut frames) {
+			Some(s) => s,
+			None => {
+				send(&worker.reply, "Error: Message must start with the session ID.", &clientID);
+				return
+			},
+		};
+		let round = match take_round(&mut frames) {
+			Some(r) => r,
+			None => {
+				send(&worker.reply, "Error: Message must start with the round number.", &clientID);
+				return
+			},
+		};
+		let msg = recv(frames);
+		let session = match self.session(&sessionID) {
+			Some(s) => s,
+			None => {
+				send(&worker.reply, "Error: Unknown session.", &clientID);
+				return
+			},
+		};
+		let span = info_span!("client",
+			session = %sessionID, round = round, client = %String::from_utf8_lossy(&clientID));
+		let _entered = span.enter();
+		if let Err(e) = session.handle(&worker, clientID, round, msg) {
+			warn!(error = ?e, "message rejected");
+		}
+	}
+}
+
+impl Session {
+
+	pub fn new_malicious(ID: &str, maxClients: usize,
 		vectorSize: usize, inputBitLimit: usize,
 		dropouts: usize, sessionTime: usize, ISsessTime: usize,
-		corruption: usize, malicious: bool, mut param: Param) -> Server {
-		println!("maxClients {:?} vectorSize {} dropouts {} sessionTime {} ISsessTime {} corruption {} malicious {}",
-			maxClients, vectorSize, dropouts, sessionTime, ISsessTime, corruption, malicious);
-		Server {
-			STATE: RwLock::new(1usize),
+		corruption: usize, malicious: bool, mut param: Param) -> Session {
+		info!(session = ID, maxClients, vectorSize, inputBitLimit, dropouts, sessionTime, ISsessTime, corruption, malicious,
+			"session created");
+		let (tx, rx) = unbounded_channel();
+		Session {
+			ID: ID.to_string(),
+			STATE: RwLock::new(State::Handshake),
+			machine: Session::state_machine(),
+			ROUND: RwLock::new(0usize),
+			rounds: 1,
 			MAX: RwLock::new(maxClients),
 			V: vectorSize,
 			S: Some(inputBitLimit),
@@ -83,17 +339,37 @@ impl Server {
 			clientProfiles: RwLock::new(HashMap::<Vec<u8>, Profile>::new()),
 			correctionVecs: Mutex::new(Vec::new()),
 			shares: Mutex::new(Vec::new()),
+			digests: Mutex::new(HashMap::new()),
+			eventSender: tx,
+			eventReciever: Mutex::new(Some(rx)),
+			checkpointPath: None,
+			resume: Mutex::new(None),
+			signKey: SigningKey::random(&mut OsRng),
+			results: RwLock::new(Vec::new()),
+			outputPath: None,
+			blame: RwLock::new(Vec::new()),
+			blamePath: None,
+			transcript: None,
+			announcements: RwLock::new(Vec::new()),
+			enrollment: None,
+			minContributors: 2,
+			metrics: Metrics::new(),
 		}
 	}
 
-	pub fn new_semi_honest(maxClients: usize,
+	pub fn new_semi_honest(ID: &str, maxClients: usize,
 		vectorSize: usize,
 		dropouts: usize, sessionTime: usize, ISsessTime: usize,
-		malicious: bool, mut param: Param) -> Server {
-		println!("maxClients {:?} vectorSize {} dropouts {} sessionTime {} ISsessTime {} malicious {}",
-			maxClients, vectorSize, dropouts, sessionTime, ISsessTime, malicious);
-		Server {
-			STATE: RwLock::new(1usize),
+		malicious: bool, mut param: Param) -> Session {
+		info!(session = ID, maxClients, vectorSize, dropouts, sessionTime, ISsessTime, malicious,
+			"session created");
+		let (tx, rx) = unbounded_channel();
+		Session {
+			ID: ID.to_string(),
+			STATE: RwLock::new(State::Handshake),
+			machine: Session::state_machine(),
+			ROUND: RwLock::new(0usize),
+			rounds: 1,
 			MAX: RwLock::new(maxClients),
 			V: vectorSize,
 			S: None,
@@ -107,334 +383,852 @@ impl Server {
 			clientProfiles: RwLock::new(HashMap::<Vec<u8>, Profile>::new()),
 			correctionVecs: Mutex::new(Vec::new()),
 			shares: Mutex::new(Vec::new()),
+			digests: Mutex::new(HashMap::new()),
+			eventSender: tx,
+			eventReciever: Mutex::new(Some(rx)),
+			checkpointPath: None,
+			resume: Mutex::new(None),
+			signKey: SigningKey::random(&mut OsRng),
+			results: RwLock::new(Vec::new()),
+			outputPath: None,
+			blame: RwLock::new(Vec::new()),
+			blamePath: None,
+			transcript: None,
+			announcements: RwLock::new(Vec::new()),
+			enrollment: None,
+			minContributors: 2,
+			metrics: Metrics::new(),
 		}
 	}
 
-	pub fn server_task(&self,
-		context: zmq::Context, ip: Option<&str>, port1: usize) -> Result<usize, ServerError>  {
-
-		let frontend = context.socket(zmq::ROUTER).unwrap();
-    	let backend = context.socket(zmq::DEALER).unwrap();
-
-    	match ip {
-    		Some(address) => {
-    			println!("Reciever connecting to tcp://{}:{}", address, port1);
-				assert!(frontend
-					.bind(&format!("tcp://{}:{:?}", address, port1))
-					.is_ok());
-    		},
-    		None => {
-				println!("Reciever going default tcp://*:{}", port1);
-				assert!(frontend
-					.bind(&format!("tcp://*:{:?}", port1))
-					.is_ok());
-    		},
-    	}
-		assert!(backend
-			.bind("inproc://backend")
-			.is_ok());
-
-		zmq::proxy(&frontend, &backend);
-		return Ok(0)
+	pub fn with_machine(mut self, machine: StateMachine) -> Session {
+	/*
+		Swap in a custom state machine,
+		e.g. Session::state_machine() with extra phases registered
+	*/
+		self.machine = machine;
+		*self.STATE.write().unwrap() = self.machine.initial();
+		self
+	}
+
+	pub fn with_rounds(mut self, rounds: usize) -> Session {
+	/*
+		Loop back to input sharing after reconstruction
+		until this many rounds are aggregated,
+		clients and their keys are kept across rounds
+	*/
+		assert!(rounds > 0);
+		self.rounds = rounds;
+		self
+	}
+
+	pub fn with_checkpoint(mut self, path: &Path) -> Result<Session, ServerError> {
+	/*
+		Save state to path at every transition,
+		if the file already exists resume from it
+	*/
+		if path.exists() {
+			let checkpoint = Checkpoint::load(path)?;
+			checkpoint.restore(&mut self)?;
+			info!(session = %self.ID, path = %path.display(), state = ?checkpoint.state, round = checkpoint.round,
+				"resuming from checkpoint");
+			self.resume = Mutex::new(Some(checkpoint));
+		}
+		self.checkpointPath = Some(path.to_path_buf());
+		Ok(self)
+	}
+
+	pub fn with_enrollment(mut self, enrollment: HashMap<Vec<u8>, Vec<u8>>) -> Session {
+	/*
+		Only admit these client IDs, each with its registered veriKey
+		Keys are compressed SEC1 points
+	*/
+		self.enrollment = Some(enrollment);
+		self
+	}
+
+	pub fn with_sign_key(mut self, signKey: &[u8]) -> Result<Session, ServerError> {
+	/*
+		Long-term server key instead of a random one per session,
+		clients can then pin its veriKey
+	*/
+		self.signKey = SigningKey::new(signKey).map_err(|_| ServerError::InvalidKey(0))?;
+		Ok(self)
+	}
+
+	pub fn with_min_contributors(mut self, k: usize) -> Session {
+	/*
+		Abort instead of revealing a sum over fewer than k inputs
+	*/
+		assert!(k > 0);
+		self.minContributors = k;
+		self
+	}
+
+	pub fn with_output(mut self, path: &Path) -> Session {
+		self.outputPath = Some(path.to_path_buf());
+		self
+	}
+
+	pub fn with_blame(mut self, path: &Path) -> Session {
+	/*
+		Keep every round's EC blame report in this file,
+		for auditing who was excluded and why
+	*/
+		self.blamePath = Some(path.to_path_buf());
+		self
+	}
+
+	pub fn with_transcript(mut self, path: &Path) -> Result<Session, ServerError> {
+	/*
+		Record every broadcast and client message to path,
+		an existing transcript is verified and appended to
+	*/
+		self.transcript = Some(Transcript::open(path)?);
+		Ok(self)
+	}
+
+	fn record(&self, entry: Entry) {
+		if let Some(t) = &self.transcript {
+			if let Err(e) = t.append(entry) {
+				error!(error = ?e, "transcript write failed");
+			}
+		}
+	}
+
+	fn save_checkpoint(&self, ctx: &StateCtx, state: State) -> Result<(), ServerError> {
+		match &self.checkpointPath {
+			Some(path) => Checkpoint::capture(self, ctx, state)?.save(path),
+			None => Ok(()),
+		}
+	}
+
+	pub fn id(&self) -> &str {
+		&self.ID
+	}
+
+	fn notify(&self, state: State) {
+		self.eventSender.send(Event::Handled(state));
 	}
 
+	pub fn command(&self, command: AdminCommand) -> Result<(), ServerError> {
+	/*
+		Operator control, handled by state_task
+		between client events
+	*/
+		self.eventSender.send(Event::Admin(command)).map_err(|_| ServerError::ThreadSenderFail(0))
+	}
 
+	fn topic(&self, topic: &str, round: usize) -> String {
+		session_topic(&self.ID, topic, round)
+	}
 
-	pub fn state_task(&self,
-		context: zmq::Context, ip: Option<&str>, port2: usize, threadReciever: mpsc::Receiver<usize>) -> Result<usize, ServerError>
+	pub async fn state_task(&self, transport: &dyn Transport) -> Result<usize, ServerError> {
+		let span = info_span!("session", session = %self.ID);
+		self.run_states(transport).instrument(span).await
+	}
+
+	async fn run_states(&self, transport: &dyn Transport) -> Result<usize, ServerError>
 	{
+		/* Publish through the transport, relayed by publisher_task
+		   topics scoped as "<session>/<topic>-<round>"
+		*/
+		let mut eventReciever = match self.eventReciever.lock() {
+			Ok(mut guard) => match guard.take() {
+				Some(rx) => rx,
+				None => return Err(ServerError::UnknownState(0)),
+			},
+			Err(_) => return Err(ServerError::MutexLockFail(0)),
+		};
+		let publisher = transport.publisher().map_err(|_| ServerError::TransportFail(1))?;
 
-		let publisher = context.socket(zmq::PUB).unwrap();
-        publisher.set_sndhwm(1_100_000).expect("failed setting hwm");
-    	match ip {
-    		Some(address) => {
-				println!("Publisher connecting to tcp://{}:{:?}", address, port2);
-				assert!(publisher
-					.bind(&format!("tcp://{}:{:?}", address, port2))
-					.is_ok());
-    		},
-    		None => {
-				println!("Publisher going default tcp://*:{:?}", port2);
-				assert!(publisher
-					.bind(&format!("tcp://*:{:?}", port2))
-					.is_ok());
-    		},
-    	}
-		let timesUp = Arc::new(RwLock::new(false));
-		let tu = timesUp.clone();
-	    let (timerTx, timerRx) = mpsc::channel();
-	    let timer = thread::spawn(move || {
-	        match timer_task(timerRx, tu) {
-	            Ok(_) => (),
-	            Err(e) => println!("{:?}", e),
-	        };
-	    });
-
-		timerTx.send(self.sessTime);
-
-		let mut recvCnt = 0;
-		let mut finalResult;
-		let mut dropouts = Vec::new();
+		let mut ctx = StateCtx {
+			publisher: publisher,
+			recvCnt: 0,
+			spBytes: Vec::new(),
+			dropouts: Vec::new(),
+			result: None,
+			log: &self.announcements,
+			signKey: &self.signKey,
+			transcript: self.transcript.as_ref(),
+		};
+		/* Resuming from a checkpoint
+		   pick up the bookkeeping, the broadcast log is restored
+		   so clients catch up on whatever they missed
+		*/
+		let resume = self.resume.lock().map_err(|_| ServerError::MutexLockFail(0))?.take();
+		if let Some(checkpoint) = resume {
+			if checkpoint.state == State::Done {
+				info!("session already done");
+				return Ok(0)
+			}
+			ctx.spBytes = checkpoint.spBytes;
+			ctx.dropouts = checkpoint.dropouts;
+		}
+
+		let mut deadline = self.arm(self.deadline(self.state()));
 		let mut BENCH_TIMER = Instant::now();
 		loop {
-			/* when finished client num exceed MAX
-			initiate state change
+			/* Block on the next event,
+			   change state as soon as every client is in,
+			   the deadline passes or the operator says so
+			*/
+			let event = match eventReciever.recv().await {
+				Some(e) => e,
+				None => return Err(ServerError::ThreadSenderFail(0)),
+			};
+			let due = match event {
+				Event::Handled(state) => {
+					if state == self.state() {
+						ctx.recvCnt += 1;
+					}
+					ctx.recvCnt >= *self.MAX.read().unwrap()
+				},
+				Event::Deadline(at) => at == deadline,
+				Event::Admin(AdminCommand::Advance) => true,
+				Event::Admin(AdminCommand::Extend(d)) => {
+					deadline = self.arm(deadline + d);
+					info!(extend_ms = d.as_millis() as u64, "deadline extended");
+					false
+				},
+				Event::Admin(AdminCommand::Abort) => {
+					let mut stateGuard = self.STATE.write().unwrap();
+					return self.stop(&mut ctx, &mut *stateGuard, Abort::Operator);
+				},
+			};
+			if !due {
+				continue;
+			}
+
+			let mut stateGuard = self.STATE.write().unwrap();
+			info!(state = ?*stateGuard, round = self.round(), elapse_ms = BENCH_TIMER.elapsed().as_millis() as u64,
+				"state finished");
+			self.metrics.set(metrics::STATE_SECONDS,
+				&self.labels(Some(self.round()), Some(*stateGuard)), BENCH_TIMER.elapsed().as_secs_f64());
+			BENCH_TIMER = Instant::now();
+
+			let transition = info_span!("transition", from = ?*stateGuard, round = self.round());
+			let advanced = transition.in_scope(|| self.machine.advance(self, &mut ctx, *stateGuard));
+			*stateGuard = match advanced {
+				Ok(next) => next,
+				Err(ServerError::Aborted(reason)) => return self.stop(&mut ctx, &mut *stateGuard, reason),
+				Err(e) => return Err(e),
+			};
+			info!(state = ?*stateGuard, round = self.round(), "state changed");
+			if let Err(e) = self.save_checkpoint(&ctx, *stateGuard) {
+				error!(error = ?e, "checkpoint failed");
+			}
+			if *stateGuard == State::Done {
+				info!("session done");
+				break;
+			}
+			ctx.recvCnt = 0;
+			deadline = self.arm(self.deadline(*stateGuard));
+		}
+		return Ok(0)
+	}
+
+	fn stop(&self, ctx: &mut StateCtx, state: &mut State, reason: Abort) -> Result<usize, ServerError> {
+	/*
+		Broadcast why, end in Done
+		and keep that in the checkpoint so a restart doesn't resume
+	*/
+		self.abort(ctx, &reason);
+		*state = State::Done;
+		self.save_checkpoint(ctx, State::Done);
+		Err(ServerError::Aborted(reason))
+	}
+
+	fn arm(&self, at: tokio::time::Instant) -> tokio::time::Instant {
+	/*
+		Timer task for an absolute deadline,
+		posts Event::Deadline(at) to state_task when it passes
+	*/
+		let events = self.eventSender.clone();
+		tokio::spawn(async move {
+			tokio::time::sleep_until(at).await;
+			events.send(Event::Deadline(at));
+		});
+		at
+	}
+
+	pub fn state_machine() -> StateMachine {
+	/*
+		HS -> KE -> IS -> EC -> AG -> IS (next round) ... -> Done
+		semi-honest skips EC
+	*/
+		StateMachine::new(State::Handshake)
+			.transition(State::Handshake, Guard::Always, State::KeyExchange)
+			.transition(State::KeyExchange, Guard::Always, State::InputSharing)
+			.transition(State::InputSharing, Guard::Malicious, State::ErrorCorrection)
+			.transition(State::InputSharing, Guard::SemiHonest, State::Aggregation)
+			.transition(State::ErrorCorrection, Guard::Always, State::Aggregation)
+			.transition(State::Aggregation, Guard::MoreRounds, State::InputSharing)
+			.transition(State::Aggregation, Guard::LastRound, State::Done)
+			.register(State::Handshake, StateHooks {
+				handler: Some(Session::handshake),
+				on_enter: None,
+				on_exit: Some(Session::exit_handshake),
+			})
+			.register(State::KeyExchange, StateHooks {
+				handler: Some(Session::key_exchange),
+				on_enter: None,
+				on_exit: Some(Session::exit_key_exchange),
+			})
+			.register(State::InputSharing, StateHooks {
+				handler: Some(Session::input_sharing),
+				on_enter: Some(Session::enter_input_sharing),
+				on_exit: Some(Session::exit_input_sharing),
+			})
+			.register(State::ErrorCorrection, StateHooks {
+				handler: Some(Session::error_correction),
+				on_enter: Some(Session::enter_error_correction),
+				on_exit: Some(Session::exit_error_correction),
+			})
+			.register(State::Aggregation, StateHooks {
+				handler: Some(Session::result_collection),
+				on_enter: Some(Session::enter_aggregation),
+				on_exit: Some(Session::exit_aggregation),
+			})
+	}
+
+	fn session_time(&self, state: State) -> usize {
+		match state {
+			State::InputSharing => self.ISsessTime,
+			_ => self.sessTime,
+		}
+	}
+
+	fn deadline(&self, state: State) -> tokio::time::Instant {
+		tokio::time::Instant::now() + Duration::from_millis(self.session_time(state) as u64)
+	}
+
+	fn exit_handshake(&self, ctx: &mut StateCtx, _to: State) -> Result<(), ServerError> {
+		self.check_quorum(ctx, State::Handshake)?;
+		let mut list = self.clientList.write().map_err(|_| ServerError::MutexLockFail(1))?;
+		let mut profiles = self.clientProfiles.write().map_err(|_| ServerError::MutexLockFail(1))?;
+		ctx.broadcast(
+			self.topic("HS", self.round()),
+			format_clientData(&mut *profiles, &mut *list, "veriKey")?);
+		self.metrics.set(metrics::CLIENTS_REGISTERED, &self.labels(None, None), list.len() as f64);
+		Ok(())
+	}
+
+	fn exit_key_exchange(&self, ctx: &mut StateCtx, _to: State) -> Result<(), ServerError> {
+		/* Dropouts handled in format_clientData
+		   removed from *list and *profiles if pk not found
+		   Not using recording dropouts before IS begins
+		*/
+		let mut list = self.clientList.write().map_err(|_| ServerError::MutexLockFail(2))?;
+		let mut profiles = self.clientProfiles.write().map_err(|_| ServerError::MutexLockFail(2))?;
+		let mut param = self.param.write().map_err(|_| ServerError::MutexLockFail(2))?;
+		let joined = list.len();
+		let publicKeys = format_clientData(&mut *profiles, &mut *list, "publicKey")?;
+		let M = list.len();
+		// from here on messages are sized by the clients still in
+		*self.MAX.write().map_err(|_| ServerError::MutexLockFail(2))? = M;
+		let labels = self.labels(Some(self.round()), Some(State::KeyExchange));
+		self.metrics.set(metrics::DROPOUTS, &labels, (joined - M) as f64);
+		self.metrics.set(metrics::CLIENTS_REGISTERED, &self.labels(None, None), M as f64);
+		let clients: Vec<String> = list.iter().map(hex::encode).collect();
+		drop(profiles);
+		drop(list);
+		self.check_quorum(ctx, State::KeyExchange)?;
+		let sharingParams = match self.malFg {
+			false => param.calculate_semi_honest(M, self.V, self.D),
+			true => param.calculate_malicious(M, self.V, self.D, self.T.unwrap()),
+		};
+		let sharingParams = sharingParams.ok_or(ServerError::Aborted(Abort::NoParams { clients: M }))?;
+		self.record(Entry::Setup {
+			maxClients: *self.MAX.read().map_err(|_| ServerError::MutexLockFail(2))?,
+			vectorSize: self.V,
+			malicious: self.malFg,
+			param: param.clone(),
+			clients: clients,
+		});
+		ctx.broadcast(self.topic("KE", self.round()), publicKeys);
+		debug!(malicious = self.malFg, sharingParams = ?sharingParams, L = sharingParams[5], "sharing params");
+		for sp in sharingParams {
+			ctx.spBytes.extend(sp.to_le_bytes().to_vec());
+		}
+		Ok(())
+	}
+
+	fn enter_input_sharing(&self, ctx: &mut StateCtx, from: State) -> Result<(), ServerError> {
+		let list = self.clientList.read().map_err(|_| ServerError::MutexLockFail(3))?;
+		let mut profiles = self.clientProfiles.write().map_err(|_| ServerError::MutexLockFail(3))?;
+		let mut shares = self.shares.lock().map_err(|_| ServerError::MutexLockFail(3))?;
+		if from == State::Aggregation {
+			/* Next round
+			   same clients, keys and sharing params,
+			   reset what each round collects
 			*/
-			let tu =  (*timesUp.read().unwrap()).clone();
-			let mut M = *self.MAX.read().unwrap();
-			if tu || recvCnt >= M {
-				// println!("\n timesUp {:?}", tu);
-				M = *self.MAX.write().unwrap();
-				let mut stateGuard = self.STATE.write().unwrap();
-				println!("- State {} elapse {:?}ms", *stateGuard, BENCH_TIMER.elapsed().as_millis());
-				BENCH_TIMER = Instant::now();
-				if *stateGuard == 6 {
-					println!("Server shutting down");
-					break;
+			let mut round = self.ROUND.write().map_err(|_| ServerError::MutexLockFail(3))?;
+			*round += 1;
+			info!(round = *round, "starting next round");
+			ctx.dropouts = Vec::new();
+			ctx.result = None;
+			for p in profiles.values_mut() {
+				p.hasShared = false;
+				p.replayed = false;
+			}
+			self.digests.lock().map_err(|_| ServerError::MutexLockFail(3))?.clear();
+			*self.correctionVecs.lock().map_err(|_| ServerError::MutexLockFail(3))? = Vec::new();
+		}
+		*shares = vec![Vec::new(); list.len()];
+		let spBytes = ctx.spBytes.clone();
+		ctx.broadcast(self.topic("IS", self.round()), vec![spBytes]);
+		Ok(())
+	}
+
+	fn exit_input_sharing(&self, ctx: &mut StateCtx, _to: State) -> Result<(), ServerError> {
+		/* Check dropouts from IS
+		We don't remove anyone cuz resizing array is slow
+		*/
+		let list = self.clientList.read().map_err(|_| ServerError::MutexLockFail(3))?;
+		let profiles = self.clientProfiles.read().map_err(|_| ServerError::MutexLockFail(3))?;
+		for (i, c) in list.iter().enumerate() {
+			let p = profiles.get(c).unwrap();
+			if p.replayed {
+				warn!(index = i, "client replayed a message, excluded");
+			}
+			if !p.hasShared || p.replayed {
+				ctx.dropouts.push(i);
+			}
+		}
+		info!(dropouts = ?ctx.dropouts, "IS dropouts");
+		self.metrics.set(metrics::DROPOUTS,
+			&self.labels(Some(self.round()), Some(State::InputSharing)), ctx.dropouts.len() as f64);
+		drop(profiles);
+		drop(list);
+		self.check_quorum(ctx, State::InputSharing)
+	}
+
+	fn enter_error_correction(&self, ctx: &mut StateCtx, _from: State) -> Result<(), ServerError> {
+		let param = self.param.read().map_err(|_| ServerError::MutexLockFail(4))?;
+		let M = *self.MAX.read().unwrap();
+		// msg = [[dorpouts], [degree test], [Input Bit test], ....]
+	   	let mut msg = vec![Vec::new(); 9];
+		msg[0].extend(write_usize_le_u8(ctx.dropouts.as_slice()));
+		let L = param.L;
+		let B = self.V / L;
+		let S = self.S.unwrap();
+		debug!(L, B, S, "EC blocks");
+        // maximun bits length of ySum
+		let Y = (
+			((2f32*(S as f32) + (self.V as f32).log2().ceil())/
+			(L as f32)).ceil()*
+			(L as f32)) as usize;
+		// Degree Test
+		for i in 0..(2*self.V + L + Y + L*S*B + 3*L)/L {
+			msg[1].extend(&(OsRng.next_u64() % param.P).to_le_bytes());
+		}
+		// Input Bit Test
+		for i in 0..B*S {
+			msg[2].extend(&(OsRng.next_u64() % param.P).to_le_bytes());
+		}
+		// Quadratic Test
+		for i in 0..B {
+			msg[3].extend(&(OsRng.next_u64() % param.P).to_le_bytes());
+		}
+		// Input bound test
+		for i in 0..B {
+			msg[4].extend(&(OsRng.next_u64() % param.P).to_le_bytes());
+		}
+		// L2-norm sum test
+		msg[5].extend(&(OsRng.next_u64() % param.P).to_le_bytes());
+		// L2-norm bit test
+		for i in 0..Y/L {
+			msg[6].extend(&(OsRng.next_u64() % param.P).to_le_bytes());
+		}
+		// L2-norm bound test
+		for i in 0..Y/L {
+			msg[7].extend(&(OsRng.next_u64() % param.P).to_le_bytes());
+		}
+        debug!(Y, "blocks taken by y_bits");
+		let mut twoPowers = Vec::<u64>::new();
+		let bit_num = (2f32*(S as f32) + (self.V as f32).log2().ceil()) as usize;
+		for i in 0..bit_num {
+			twoPowers.push(2u64.pow(i as u32));
+		}
+		for i in bit_num..Y {
+			twoPowers.push(0u64);
+		}
+        let mut pss = PackedSecretSharing::new(
+			param.P as u128, param.useR2 as u128, param.useR3 as u128,
+			param.useD2, param.useD3, Y, L, M
+		);
+		let twoPowers_shares = pss.share(&twoPowers);
+        for share in twoPowers_shares {
+			msg[8].extend(write_u64_le_u8(share.as_slice()));
+		}
+		/* M is updated
+		Corrections only contains the clients didn't dropout from IS
+		*/
+		debug!(sections = msg.len(), "EC params");
+		*self.correctionVecs.lock().map_err(|_| ServerError::MutexLockFail(4))? = vec![vec![Vec::new(); M]; M];
+		ctx.broadcast(self.topic("EC", self.round()), msg);
+		Ok(())
+	}
+
+	fn exit_error_correction(&self, ctx: &mut StateCtx, _to: State) -> Result<(), ServerError> {
+		/* Check dropouts from EC
+			add clients who fail tests
+		*/
+		info!(dropouts = ?ctx.dropouts, "EC dropouts");
+		let corrections = self.correctionVecs.lock().map_err(|_| ServerError::MutexLockFail(4))?;
+		let param = self.param.read().map_err(|_| ServerError::MutexLockFail(4))?;
+		let M = *self.MAX.read().unwrap();
+		/* Clients with at least one correction, but for IS dropouts:
+		   they're out already, and a replayer's row may hold corrections
+		   from whoever still got its shares
+		*/
+		let candidates: Vec<usize> = (0..M)
+			.filter(|i| !ctx.dropouts.contains(i))
+			.filter(|&i| corrections[i].iter().any(|c| c.len() != 0))
+			.collect();
+		debug!(tests = candidates.len(), "EC batch check");
+		/* Blame by position in the client list,
+		   the same index the corrections row and dropouts use
+		*/
+		let list = self.clientList.read().map_err(|_| ServerError::MutexLockFail(4))?;
+		let excluded = find_cheaters(&corrections, &candidates, &param, &list);
+		drop(list);
+		drop(param);
+		drop(corrections);
+		let round = self.round();
+		for b in excluded.iter() {
+			warn!(round, index = b.index, client = %b.client, failed = ?b.failed,
+				holders = b.evidence.holders.len(), off = ?b.evidence.off, nonzero = b.evidence.nonzero.len(),
+				"client failed EC");
+		}
+		let report = BlameReport::new(&self.ID, round, candidates.len(), excluded);
+		let indices = report.indices();
+		// a liar can be an IS dropout, it's only added once
+		for &i in indices.iter() {
+			if !ctx.dropouts.contains(&i) {
+				ctx.dropouts.push(i);
+			}
+		}
+		// replays caught after IS
+		for i in self.replayers()? {
+			if !ctx.dropouts.contains(&i) {
+				warn!(index = i, "client replayed a message, excluded");
+				ctx.dropouts.push(i);
+			}
+		}
+		self.metrics.set(metrics::EC_FAILURES, &self.labels(Some(round), None), indices.len() as f64);
+		/* Publish the exclusion list signed,
+		   msg = [round, excluded, report, Sig(session | round | excluded)]
+		*/
+		let signature: Signature = self.signKey.sign(&BlameReport::signed_bytes(&self.ID, round, &indices));
+		let excludedBytes: Vec<u8> = indices.iter().flat_map(|i| (*i as u64).to_le_bytes().to_vec()).collect();
+		let msg = vec![
+			(round as u64).to_le_bytes().to_vec(),
+			excludedBytes,
+			serde_json::to_vec(&report).map_err(|_| ServerError::BlameFail(0))?,
+			signature.as_ref().to_vec(),
+		];
+		ctx.broadcast(self.topic("BL", round), msg);
+		let mut reports = self.blame.write().map_err(|_| ServerError::MutexLockFail(4))?;
+		reports.retain(|r| r.round != round);
+		reports.push(report);
+		if let Some(path) = &self.blamePath {
+			blame::write_reports(path, &reports)?;
+		}
+		drop(reports);
+		self.check_quorum(ctx, State::ErrorCorrection)
+	}
+
+	fn enter_aggregation(&self, ctx: &mut StateCtx, _from: State) -> Result<(), ServerError> {
+		// msg = [[dropouts], [magic], [another round follows]]
+	   	let mut msg = vec![Vec::new(); 3];
+		msg[0].extend(write_usize_le_u8(ctx.dropouts.as_slice()));
+		msg[1].extend(&23423399994u64.to_le_bytes());
+		msg[2].push(self.has_next_round() as u8);
+		ctx.broadcast(self.topic("AG", self.round()), msg);
+		Ok(())
+	}
+
+	fn exit_aggregation(&self, ctx: &mut StateCtx, _to: State) -> Result<(), ServerError> {
+		/* AG doesn't need to consider dropouts
+			Dropout will results in a empty entry for the missing client
+			and the entry will be removed for reconstruction.
+			Reconstruction successful as long as dropouts under limit.
+			Aggregated shares of clients already dropped or excluded
+			are left out before the quorum counts them.
+		*/
+		{
+			let mut shares = self.shares.lock().map_err(|_| ServerError::MutexLockFail(5))?;
+			for &i in ctx.dropouts.iter() {
+				if i < shares.len() && !shares[i].is_empty() {
+					debug!(index = i, "aggregated shares of a dropout, ignored");
+					shares[i] = Vec::new();
+				}
+			}
+		}
+		self.check_quorum(ctx, State::Aggregation)?;
+		let mut shares = self.shares.lock().map_err(|_| ServerError::MutexLockFail(5))?;
+		let param = self.param.read().map_err(|_| ServerError::MutexLockFail(5))?;
+		let responders = shares.iter().filter(|s| !s.is_empty()).count();
+		self.metrics.set(metrics::DROPOUTS,
+			&self.labels(Some(self.round()), Some(State::Aggregation)), (shares.len() - responders) as f64);
+		let timer = Instant::now();
+		/* Malicious: aggregated shares off the sum's polynomial are left out,
+		   with more than T of them there's no sum
+		*/
+		let rejected = match self.malFg {
+			true => check_aggregates(&shares, &param, self.V),
+			false => Some(Vec::new()),
+		};
+		ctx.result = match &rejected {
+			Some(off) => {
+				for &i in off.iter() {
+					warn!(round = self.round(), index = i, "aggregated shares off, left out");
+					shares[i] = Vec::new();
 				}
+				self.reconstruction(&shares, &ctx.dropouts, &param, shares.len()).ok()
+			},
+			None => {
+				warn!(round = self.round(), "too many aggregated shares off, no sum");
+				None
+			},
+		};
+		self.metrics.set(metrics::RECONSTRUCTION_SECONDS,
+			&self.labels(Some(self.round()), None), timer.elapsed().as_secs_f64());
+		/* Publish the sum signed, msg = [round, Enc(sum), Sig(session | round | sum)]
+		   sum in the codec's compact form, signed as u64 little endian
+		   an empty sum means reconstruction failed
+		*/
+		let round = self.round();
+		let sum = ctx.result.clone().unwrap_or_default();
+		let signature: Signature = self.signKey.sign(&RoundResult::signed_bytes(&self.ID, round, &sum));
+		let msg = vec![
+			(round as u64).to_le_bytes().to_vec(),
+			ShareCodec::new(param.P).encode_compact(&sum),
+			signature.as_ref().to_vec(),
+		];
+		ctx.broadcast(self.topic("RS", round), msg);
+		if let Some(sum) = ctx.result.clone() {
+			let contributors = self.contributors(ctx)?;
+			let mut results = self.results.write().map_err(|_| ServerError::MutexLockFail(5))?;
+			results.push(RoundResult::new(&self.ID, round, contributors, &ctx.dropouts, sum)
+				.with_rejected(&rejected.unwrap_or_default()));
+			if let Some(path) = &self.outputPath {
+				output::write_results(path, &results)?;
+			}
+		}
+		Ok(())
+	}
 
-				let mut list = match self.clientList.write() {
-					Ok(mut guard) => guard,
-					Err(_) => return Err(ServerError::MutexLockFail(0)),
-				};
-				let mut profiles = match self.clientProfiles.write() {
-					Ok(mut guard) => guard,
-					Err(_) => return Err(ServerError::MutexLockFail(0)),
-				};
-				let mut shares = match self.shares.lock() {
-					Ok(guard) => guard,
-					Err(_) => return Err(ServerError::MutexLockFail(0)),
-				};
-				let mut corrections = match self.correctionVecs.lock() {
-					Ok(guard) => guard,
-					Err(_) => return Err(ServerError::MutexLockFail(0)),
-				};
-				let mut param = match self.param.write() {
-					Ok(guard) => guard,
-					Err(_) => return Err(ServerError::MutexLockFail(0)),
-				};
-				let res = match *stateGuard {
-					1 => {
-						if recvCnt == 0 {panic!("No one joins!");}
-						publish_vecs(
-							&publisher,
-							format_clientData(&mut *profiles, &mut *list, "veriKey").unwrap(),
-							"HS");
-						M = list.len();
-						timerTx.send(self.sessTime)
-					},
-					2 => {
-						/* Dropouts handled in format_clientData
-						   removed from *list and *profiles if pk not found
-						   Not using recording dropouts before IS begins
-						*/
-						publish_vecs(
-							&publisher,
-							format_clientData(&mut *profiles, &mut *list,
-							 "publicKey").unwrap(),
-							"KE");
-						M = list.len();
-						let sharingParams = match self.malFg {
-							false => param.calculate_semi_honest(M, self.V, self.D),
-							true => param.calculate_malicious(M, self.V, self.D, self.T.unwrap()),
-						};
-						println!("malicious: {:?}, sharingParams {:?}", self.malFg, sharingParams);
-						println!("L {:?}", sharingParams[5]);
-						let mut spBytes = Vec::new();
-						for sp in sharingParams {
-							spBytes.extend(sp.to_le_bytes().to_vec());
-						}
-						*shares = vec![Vec::new(); M];
-						publish(&publisher, spBytes, "IS");
-						timerTx.send(self.ISsessTime)
-					},
-					3 => {
-						/* Check dropouts from IS
-						We don't remove anyone cuz resizing array is slow
-						*/
-						let mut new_dropouts = Vec::new();
-						for (i, c) in list.iter().enumerate() {
-							if !profiles.get(c).unwrap().hasShared {
-								new_dropouts.extend(&(i as u64).to_le_bytes());
-								dropouts.push(i);
-							}
-						}
-					   	let mut msg = vec![Vec::new(); 9];
-					   	msg[0] = new_dropouts;
-						println!("IS dropouts {:?}", dropouts);
-						if self.malFg {
-							// msg = [[dorpouts], [degree test], [Input Bit test], ....]
-							let L = param.L;
-							let B = self.V / L;
-							let S = self.S.unwrap();
-							println!("{}, {}, {}", L, B, S);
-	                        // maximun bits length of ySum
-							let Y = (
-								((2f32*(S as f32) + (self.V as f32).log2().ceil())/
-								(L as f32)).ceil()*
-								(L as f32)) as usize;
-	                        println!("{}", Y);
-							// Degree Test
-							for i in 0..(2*self.V + L + Y + L*S*B + 3*L)/L {
-								msg[1].extend(&(OsRng.next_u64() % param.P).to_le_bytes());
-							}
-							// Input Bit Test
-							for i in 0..B*S {
-								msg[2].extend(&(OsRng.next_u64() % param.P).to_le_bytes());
-							}
-							// Quadratic Test
-							for i in 0..B {
-								msg[3].extend(&(OsRng.next_u64() % param.P).to_le_bytes());
-							}
-							// Input bound test
-							for i in 0..B {
-								msg[4].extend(&(OsRng.next_u64() % param.P).to_le_bytes());
-							}
-							// L2-norm sum test
-							msg[5].extend(&(OsRng.next_u64() % param.P).to_le_bytes());
-							// L2-norm bit test
-							for i in 0..Y/L {
-								msg[6].extend(&(OsRng.next_u64() % param.P).to_le_bytes());
-							}
-							// L2-norm bound test
-							for i in 0..Y/L {
-								msg[7].extend(&(OsRng.next_u64() % param.P).to_le_bytes());
-							}
-	                        println!("Y (#blocks taken by y_bits) {}", Y);
-							let mut twoPowers = Vec::<u64>::new();
-							let bit_num = (2f32*(S as f32) + (self.V as f32).log2().ceil()) as usize;
-							for i in 0..bit_num {
-								twoPowers.push(2u64.pow(i as u32));
-							}
-							for i in bit_num..Y {
-								twoPowers.push(0u64);
-							}
-	                        let mut pss = PackedSecretSharing::new(
-								param.P as u128, param.useR2 as u128, param.useR3 as u128,
-								param.useD2, param.useD3, Y, L, M
-							);
-							let twoPowers_shares = pss.share(&twoPowers);
-	                        for share in twoPowers_shares {
-								msg[8].extend(write_u64_le_u8(share.as_slice()));
-							}
-							/* M is updated
-							Corrections only contains the clients didn't dropout from IS
-							*/
-							println!("EC params {:?}", msg.len());
-							*corrections = vec![vec![Vec::new(); M]; M];
-							publish_vecs(&publisher, msg, "EC");
-						} else {
-							msg[1].extend(&23423399994u64.to_le_bytes());
-							publish_vecs(&publisher, msg, "AG");
-						}
-						timerTx.send(self.sessTime)
-					},
-					4 => {
-						/* Check dropouts from EC
-							msg = [clients who dropouts or fail tests]
-						*/
-						println!("EC dropouts {:?}", dropouts);
-                        let mut ThreadPool = Vec::new();
-                        for i in 0..M {
-                        	let mut j = 0;
-                        	while j < M && corrections[i][j].len() == 0 {
-                        		j+=1;
-                        	}
-                        	if j == M {continue;}
-                            let corrections_ = (corrections[i]).clone();
-							let param_ = (*param).clone();
-							let child = thread::spawn(move || {
-								test_suit(&corrections_, &param_)
-							});
-							ThreadPool.push(child);
-						}
-						println!("ThreadPool size {:?}", ThreadPool.len());
-                        let mut cnt = 0;
-						for t in ThreadPool {
-							let is_pass = t.join().unwrap();
-							if !is_pass {
-								dropouts.push(cnt);
-							}
-							cnt += 1;
-						}
-					   	let mut msg = vec![Vec::new(); 2];
-						msg[0].extend(write_usize_le_u8(dropouts.as_slice()));
-						msg[1].extend(&23423399994u64.to_le_bytes());
-						publish_vecs(&publisher, msg, "AG");
-						timerTx.send(self.sessTime)
-					},
-					5 => {
-						/* AG doesn't need to consider dropouts
-							Dropout will results in a empty entry for the missing client
-							and the entry will be removed for reconstruction.
-							Reconstruction successful as long as dropouts under limit.
-						*/
-						finalResult = self.reconstruction(&shares, &dropouts, &param, M);
-						timerTx.send(1)
-					},
-					_ => Ok(()),
-				};
-				match res {
-					Ok(_) => {
-						if !self.malFg && *stateGuard == 3 {
-							// skip state 4 in semi-honest case
-							*stateGuard += 2;
-						} else {
-							*stateGuard += 1;
-						}
-						println!("Server: STATE change from {:?} to {:?}", *stateGuard-1, *stateGuard);
-						recvCnt = 0;
-						match timesUp.write() {
-							Ok(mut guard) => *guard = false,
-							Err(_) => return Err(ServerError::MutexLockFail(0)),
-						}
-					},
-					Err(_) => return Err(ServerError::ThreadSenderFail(0)),
-				};
+	fn contributors(&self, ctx: &StateCtx) -> Result<usize, ServerError> {
+		// Joined clients minus IS dropouts and EC failures, their inputs are in the sum
+		let joined = self.clientList.read().map_err(|_| ServerError::MutexLockFail(0))?.len();
+		let mut excluded = ctx.dropouts.clone();
+		excluded.sort();
+		excluded.dedup();
+		Ok(joined.saturating_sub(excluded.len()))
+	}
+
+	fn check_quorum(&self, ctx: &StateCtx, state: State) -> Result<(), ServerError> {
+	/*
+		Run by every exit hook before anything is broadcast or revealed
+			contributors	clients whose input can still end up in the sum,
+							never fewer than minContributors
+			responders		clients holding aggregated shares,
+							reconstruction needs degree2 of them
+	*/
+		let joined = self.clientList.read().map_err(|_| ServerError::MutexLockFail(0))?.len();
+		let contributors = self.contributors(ctx)?;
+		self.metrics.set(metrics::CLIENTS_SURVIVING, &self.labels(Some(self.round()), Some(state)), contributors as f64);
+		if state == State::Handshake && joined == 0 {
+			return Err(ServerError::Aborted(Abort::NoClients));
+		}
+		if contributors < self.minContributors {
+			return Err(ServerError::Aborted(Abort::TooFewContributors {
+				state: state, have: contributors, need: self.minContributors,
+			}));
+		}
+		if state == State::Aggregation {
+			let need = self.param.read().map_err(|_| ServerError::MutexLockFail(0))?.useD2;
+			let responders = self.shares.lock().map_err(|_| ServerError::MutexLockFail(0))?
+				.iter().filter(|s| !s.is_empty()).count();
+			if responders < need {
+				return Err(ServerError::Aborted(Abort::TooFewClients {
+					state: state, have: responders, need: need,
+				}));
 			}
+		}
+		Ok(())
+	}
 
-			match threadReciever.try_recv() {
-				Ok(notification) => {
-					/* worker thread send stateNum
-					when finish processing one client
-					*/
-					let mut stateGuard = self.STATE.write().unwrap();
-					//println!("Server mpsc recieved notification {:?}, cnt {}", notification, recvCnt+1);
-					if notification == *stateGuard {
-						recvCnt += 1;
-					}
-				},
-				Err(_) => continue,
+	fn abort(&self, ctx: &mut StateCtx, reason: &Abort) {
+	/*
+		Tell clients why the session stops,
+		msg = [reason]
+	*/
+		warn!(round = self.round(), reason = %reason, "session aborted");
+		ctx.broadcast(self.topic("AB", self.round()), vec![reason.to_string().into_bytes()]);
+	}
+
+	fn labels(&self, round: Option<usize>, state: Option<State>) -> Labels {
+		let mut labels = vec![("session", self.ID.clone())];
+		if let Some(r) = round {
+			labels.push(("round", r.to_string()));
+		}
+		if let Some(s) = state {
+			labels.push(("state", format!("{:?}", s)));
+		}
+		labels
+	}
+
+	pub fn metrics(&self) -> &Metrics {
+		&self.metrics
+	}
+
+	pub fn is_malicious(&self) -> bool {
+		self.malFg
+	}
+
+	pub fn round(&self) -> usize {
+		*self.ROUND.read().unwrap()
+	}
+
+	pub fn has_next_round(&self) -> bool {
+		self.round() + 1 < self.rounds
+	}
+
+	pub fn state(&self) -> State {
+		*self.STATE.read().unwrap()
+	}
+
+	// Key clients check broadcasts against, handed out at handshake
+	pub fn veri_key(&self) -> VerifyKey {
+		VerifyKey::from(&self.signKey)
+	}
+
+	pub fn announcement(&self, seq: usize) -> Option<Announcement> {
+		self.announcements.read().unwrap().get(seq).cloned()
+	}
+
+	pub fn results(&self) -> Vec<RoundResult> {
+		self.results.read().unwrap().clone()
+	}
+
+	pub fn result(&self, round: usize) -> Option<RoundResult> {
+		self.results.read().unwrap().iter().find(|r| r.round == round).cloned()
+	}
+
+	pub fn blame_reports(&self) -> Vec<BlameReport> {
+		self.blame.read().unwrap().clone()
+	}
+
+	pub fn blame_report(&self, round: usize) -> Option<BlameReport> {
+		self.blame.read().unwrap().iter().find(|r| r.round == round).cloned()
+	}
+
+	pub fn handle(&self, worker: &Worker, clientID: Vec<u8>, round: usize, msg: RecvType) -> Result<usize, WorkerError> {
+		let state = *(self.STATE.read().unwrap());
+		let frames = msg.frames();
+		let digest = message_digest(&frames);
+		let recorded = self.transcript.as_ref().map(|_| (clientID.clone(), frames));
+		let result = if round != *self.ROUND.read().unwrap() {
+			send(&worker.reply, "Error: Wrong round.", &clientID);
+			Err(WorkerError::WrongRound(state.code()))
+		} else if self.is_replay(&clientID, &digest) {
+			send(&worker.reply, "Error: Replayed message.", &clientID);
+			Err(WorkerError::Replayed(state.code()))
+		} else {
+			match self.machine.handler(state) {
+				Some(handler) => handler(self, worker, clientID.clone(), msg),
+				None => Err(WorkerError::UnknownState(state.code())),
 			}
+		};
+		if let Some((client, frames)) = recorded {
+			self.record(Entry::Message {
+				client: hex::encode(&client),
+				round: round,
+				state: state,
+				frames: hex_frames(&frames),
+				accepted: result.is_ok(),
+			});
 		}
-		return Ok(0)
+		result
 	}
 
-	pub fn worker_task(&self, worker: Worker)-> Result<usize, ServerError> {
-		let mut EC_skip = false;
-		let mut i = 0;
-		loop {
-			let clientID = take_id(&worker.dealer);
-			let msg = recv(&worker.dealer);
-			let res = match *(self.STATE.read().unwrap()) {
-				1 => self.handshake(&worker, clientID, msg),
-				2 => self.key_exchange(&worker, clientID, msg),
-				3 => self.input_sharing(&worker, clientID, msg),
-				4 => self.error_correction(&worker, clientID, msg),
-				5 => self.result_collection(&worker, clientID, msg),
-				_ => Err(WorkerError::UnknownState(0)),
-			};
-			i += 1;
-			match res {
-				Ok(_) => continue,
-				Err(e) => println!("{:?}", e),
-			};
+	fn is_replay(&self, clientID: &Vec<u8>, digest: &Vec<u8>) -> bool {
+	/*
+		A message identical to one already received from this client this round
+		Checked and recorded under one lock before the handler runs,
+		so of two copies handled at once only the first gets through
+		Honest clients never repeat one, the client is flagged
+		and excluded when IS or EC exits
+	*/
+		let seen = match self.digests.lock() {
+			Ok(mut guard) => {
+				let d = guard.entry(clientID.clone()).or_insert_with(Vec::new);
+				match d.contains(digest) {
+					true => true,
+					false => {
+						d.push(digest.clone());
+						false
+					},
+				}
+			},
+			Err(_) => false,
+		};
+		if seen {
+			warn!(client = %String::from_utf8_lossy(clientID), "replayed message");
+			if let Ok(mut guard) = self.clientProfiles.write() {
+				if let Some(p) = guard.get_mut(clientID) {
+					p.replayed = true;
+				}
+			}
 		}
-		return Ok(0)
+		seen
+	}
+
+	fn replayers(&self) -> Result<Vec<usize>, ServerError> {
+		// Flagged by is_replay, by position in the client list
+		let list = self.clientList.read().map_err(|_| ServerError::MutexLockFail(0))?;
+		let profiles = self.clientProfiles.read().map_err(|_| ServerError::MutexLockFail(0))?;
+		Ok((0..list.len()).filter(|&i| profiles.get(&list[i]).map_or(false, |p| p.replayed)).collect())
 	}
 
 	fn handshake(&self,
 		worker: &Worker, clientID: Vec<u8>, msg: RecvType) -> Result<usize, WorkerError> {
+	/*
+		Client generates its own keys
+		msg = [Hello, veriKey]
+		Check veriKey against enrollment if there is one
+	*/
+		let veriKey = match msg {
+			RecvType::matrix(m) if m.len() == 2 => match VerifyKey::new(&m[1]) {
+				Ok(vk) => vk,
+				Err(_) => {
+					send(&worker.reply, "Error: Invalid verification key.", &clientID);
+					return Err(WorkerError::UnexpectedFormat(1));
+				},
+			},
+			_ => {
+				send(&worker.reply, "Please send with format: [Hello, veriKey]", &clientID);
+				return Err(WorkerError::UnexpectedFormat(1));
+			},
+		};
+		if let Some(enrollment) = &self.enrollment {
+			let vk = VerifyKey::to_encoded_point(&veriKey, true).to_bytes().to_vec();
+			match enrollment.get(&clientID) {
+				Some(enrolled) if *enrolled == vk => (),
+				Some(_) => {
+					send(&worker.reply, "Error: Key does not match enrollment.", &clientID);
+					return Err(WorkerError::NotEnrolled(1));
+				},
+				None => {
+					send(&worker.reply, "Error: Not enrolled.", &clientID);
+					return Err(WorkerError::NotEnrolled(1));
+				},
+			}
+		}
 	/*
 		Check client not existed & under limit
 		Record ID and release lock
@@ -442,11 +1236,11 @@ impl Server {
 		match self.clientList.write() {
 			Ok(mut guard) => {
 				if guard.contains(&clientID) {
-					send(&worker.dealer, "Error: You already exists.", &clientID);
+					send(&worker.reply, "Error: You already exists.", &clientID);
 					return Err(WorkerError::MaxClientExceed(1));
 				}
 				if guard.len() == *self.MAX.read().unwrap() {
-		            send(&worker.dealer, "Error: Reached maximun client number.", &clientID);
+		            send(&worker.reply, "Error: Reached maximun client number.", &clientID);
 					return Err(WorkerError::MaxClientExceed(1));
 				}
 				guard.push(clientID.clone());
@@ -454,27 +1248,26 @@ impl Server {
 			Err(_) => return Err(WorkerError::MutexLockFail(1)),
 		};
 	/*
-		Generate ECDSA keys and send
+		Reply with server veriKey
 		Create new profile
 		Write to mutex (don't need check_state)
 	*/
-		let signKey = SigningKey::random(&mut OsRng);
-		let veriKey = VerifyKey::from(&signKey);
-		send(&worker.dealer,
-		 	SigningKey::to_bytes(&signKey).to_vec(),
-		 	&clientID
-		 );
+		send(&worker.reply,
+			VerifyKey::to_encoded_point(&self.veri_key(), true).to_bytes().to_vec(),
+			&clientID
+		);
 
 		let newProfiel = Profile {
 			veriKey: veriKey,
 			publicKey: Vec::new(),
 			hasShared: false,
+			replayed: false,
 		};
 		match self.clientProfiles.write() {
 			Ok(mut guard) => guard.insert( clientID.clone(), newProfiel),
 			Err(guard) => return Err(WorkerError::MutexLockFail(1)),
 		};
-		worker.threadSender.send(1);
+		self.notify(State::Handshake);
 		//println!("handshaked with {:?}", std::str::from_utf8(&clientID).unwrap());
 		return Ok(1);
 	}
@@ -486,7 +1279,7 @@ impl Server {
 		Parse msg
 	*/
 		if !self.check_exist(&clientID) {
-			send(&worker.dealer,"Error: Your profile not found", &clientID);
+			send(&worker.reply,"Error: Your profile not found", &clientID);
 			return Err(WorkerError::ClientNotFound(1))
 		}
 		let publicKey;
@@ -494,15 +1287,21 @@ impl Server {
 		match msg {
 			RecvType::matrix(m) => {
 				if m.len() != 2 {
-					send(&worker.dealer,
+					send(&worker.reply,
 						"Please send with format: [publicKey, Enc(publicKey, veriKey)]", &clientID);
 					return Err(WorkerError::UnexpectedFormat(3))
 				}
 				publicKey = m[0].clone();
-				singedPublicKey = Signature::from_bytes(&m[1]).unwrap();
+				singedPublicKey = match Signature::from_bytes(&m[1]) {
+					Ok(sig) => sig,
+					Err(_) => {
+						send(&worker.reply, "Error: Invalid signature.", &clientID);
+						return Err(WorkerError::DecryptionFail(3))
+					},
+				};
 			},
 			_ => {
-				send(&worker.dealer,
+				send(&worker.reply,
 					"Please send with ormat: [publicKey, Enc(publicKey, veriKey)]", &clientID);
 				return Err(WorkerError::UnexpectedFormat(3))
 			},
@@ -513,23 +1312,32 @@ impl Server {
 		Sotre DH pk in *profiles
 	*/
 		let veriKey = match self.clientProfiles.read() {
-			Ok(mut guard) => guard.get(&clientID).unwrap().veriKey.clone(),
+			Ok(guard) => match guard.get(&clientID) {
+				Some(p) => p.veriKey.clone(),
+				None => {
+					send(&worker.reply,"Error: Your profile not found", &clientID);
+					return Err(WorkerError::ClientNotFound(3))
+				},
+			},
 			Err(_) => return Err(WorkerError::MutexLockFail(3)),
 		};
 		match veriKey.verify(&publicKey, &singedPublicKey) {
 			Ok(_) => {
 			 	match self.clientProfiles.write() {
-					Ok(mut guard) => guard.get_mut(&clientID).unwrap().publicKey = publicKey.to_vec(),
+					Ok(mut guard) => match guard.get_mut(&clientID) {
+						Some(p) => p.publicKey = publicKey.to_vec(),
+						None => return Err(WorkerError::ClientNotFound(3)),
+					},
 					Err(_) => return Err(WorkerError::MutexLockFail(3)),
 				};
-		 		send(&worker.dealer, "Your publicKey has been save.", &clientID);
+		 		send(&worker.reply, "Your publicKey has been save.", &clientID);
 			},
 			Err(_) => {
-		 		send(&worker.dealer, "Error: Decryption Fail.", &clientID);
+		 		send(&worker.reply, "Error: Decryption Fail.", &clientID);
 				return Err(WorkerError::DecryptionFail(3))
 			},
 		}
- 		worker.threadSender.send(2);
+ 		self.notify(State::KeyExchange);
  		//println!("key_exchanged with {:?}", std::str::from_utf8(&clientID).unwrap());
 		return Ok(2)
 	}
@@ -543,7 +1351,7 @@ impl Server {
 	*/
 		//println!("{:?} in input_sharing", worker.ID);
 		if !self.check_exist(&clientID) {
-			send(&worker.dealer,"Error: Your profile not found", &clientID);
+			send(&worker.reply,"Error: Your profile not found", &clientID);
 			return Err(WorkerError::ClientNotFound(3))
 		}
 		let M = self.MAX.read().unwrap().clone();
@@ -552,13 +1360,13 @@ impl Server {
 		let shares = match msg {
 			RecvType::matrix(m) => {
 				if m.len() != M {		//TODO: only 5 sections
-					send(&worker.dealer, "Please share with specified parameters.", &clientID);
+					send(&worker.reply, "Please share with specified parameters.", &clientID);
 					return Err(WorkerError::UnexpectedFormat(3));
 				}
 				m
 			},
 			_ => {
-				send(&worker.dealer, "Please send your shares as matrix.", &clientID);
+				send(&worker.reply, "Please send your shares as matrix.", &clientID);
 				return Err(WorkerError::UnexpectedFormat(3));
 			},
 		};
@@ -570,20 +1378,31 @@ impl Server {
 			Ok(guard) => guard,
 			Err(_) => return Err(WorkerError::MutexLockFail(0)),
 		};
+		let round = *self.ROUND.read().unwrap() as u64;
 		let senderPk = match self.clientProfiles.read() {
-			Ok(guard) => guard.get(&clientID).unwrap().publicKey.clone(),
+			Ok(guard) => match guard.get(&clientID) {
+				Some(p) => p.publicKey.clone(),
+				None => {
+					send(&worker.reply,"Error: Your profile not found", &clientID);
+					return Err(WorkerError::ClientNotFound(3))
+				},
+			},
 			Err(_) => return Err(WorkerError::MutexLockFail(0)),
 		};
-
-		assert_eq!(shares.len(), listGuard.len());
+		// one share per client in the list
+		if shares.len() != listGuard.len() {
+			send(&worker.reply, "Please share with specified parameters.", &clientID);
+			return Err(WorkerError::UnexpectedFormat(3));
+		}
+		let relayed: usize = shares.iter().map(|s| s.len()).sum();
 		for i in 0..shares.len() {
 			/*
-			attach senderPK so that reciever knows who is this from
-			and which sharedKey to use
+			attach round and senderPK so that reciever knows who is this from,
+			which sharedKey to use and can drop stale shares
 			*/
-			let msg = vec![senderPk.clone(), shares[i].clone()];
+			let msg = vec![round.to_le_bytes().to_vec(), senderPk.clone(), shares[i].clone()];
 			//println!("{:?}", msg);
-			match send_vecs(&worker.dealer, msg, &listGuard[i]) {
+			match send_vecs(&worker.reply, msg, &listGuard[i]) {
 				Ok(_) => {
 					//println!("share (len: {:?}) from {:?} to {:?}",
 					//	shares[i].len(), str::from_utf8(&clientID).unwrap(), str::from_utf8(&listGuard[i]).unwrap());
@@ -592,10 +1411,14 @@ impl Server {
 			};
 		}
 		match self.clientProfiles.write() {
-			Ok(mut guard) => guard.get_mut(&clientID).unwrap().hasShared = true,
+			Ok(mut guard) => match guard.get_mut(&clientID) {
+				Some(p) => p.hasShared = true,
+				None => return Err(WorkerError::ClientNotFound(3)),
+			},
 			Err(_) => return Err(WorkerError::MutexLockFail(0)),
 		};
-		worker.threadSender.send(3);
+		self.metrics.add(metrics::RELAYED_BYTES, &self.labels(Some(round as usize), None), relayed as f64);
+		self.notify(State::InputSharing);
 		return Ok(3)
 	}
 
@@ -606,21 +1429,24 @@ impl Server {
 	*/
 		//println!("{:?} error_correction", worker.ID);
 		if !self.check_exist(&clientID) {
-			send(&worker.dealer,"Error: Your profile not found", &clientID);
+			send(&worker.reply,"Error: Your profile not found", &clientID);
 			return Err(WorkerError::ClientNotFound(4))
 		}
 		// M stays the same
 		let M = self.MAX.read().unwrap().clone();
-		let idx = self.clientList
-				.read().unwrap()
-				.iter().position(|s| s == &clientID)
-				.unwrap();
+		let idx = match self.client_index(&clientID) {
+			Some(i) => i,
+			None => {
+				send(&worker.reply,"Error: Your profile not found", &clientID);
+				return Err(WorkerError::ClientNotFound(4))
+			},
+		};
 		match msg {
 			RecvType::matrix(m) => {
 				// client_i dropouts then row_i is empty
 				// 3 tests results * 8 bytes per tests result
-				if m.len() != M || (m[0].len() != 3 * 8 && m[0].len() != 0) {
-					send(&worker.dealer, "Please send your degree test matrix.
+				if m.len() != M || m.iter().any(|t| t.len() != 3 * 8 && t.len() != 0) {
+					send(&worker.reply, "Please send your degree test matrix.
 											Format: [[Degree test], [Input Bit test], [Quadratic test], [Input bound test],
 											[L2-norm sum test], [L2-norm bit test], [L2-norm bound test]]", &clientID);
 					return Err(WorkerError::UnexpectedFormat(4))
@@ -636,31 +1462,31 @@ impl Server {
 				};
 			},
 			_ => {
-				send(&worker.dealer, "Please send your degree test matrix.
+				send(&worker.reply, "Please send your degree test matrix.
 					Format: [[Degree test], [Input Bit test], [Quadratic test], [Input bound test],
 					[L2-norm sum test], [L2-norm bit test], [L2-norm bound test]]", &clientID);
 				return Err(WorkerError::UnexpectedFormat(4))
 			},
 		};
-		worker.threadSender.send(4);
+		self.notify(State::ErrorCorrection);
 		return Ok(4);
 	}
 
 	fn result_collection(&self,
 		worker: &Worker, clientID: Vec<u8>, msg: RecvType) -> Result<usize, WorkerError> {
-		println!("result_collection");
+		debug!("result_collection");
 	/*
 		Check client exist
 		Get shares & signature
 	*/
 		if !self.check_exist(&clientID) {
-			send(&worker.dealer,"Error: Your profile not found", &clientID);
+			send(&worker.reply,"Error: Your profile not found", &clientID);
 			return Err(WorkerError::ClientNotFound(5))
 		}
 		let msg = match msg {
 			RecvType::matrix(m) => {
 				if m.len() != 2 {
-					send(&worker.dealer,
+					send(&worker.reply,
 						"Please send your shares with a signature. Format: [shares, Enc(shares)]",
 						&clientID);
 					return Err(WorkerError::UnexpectedFormat(5))
@@ -668,7 +1494,7 @@ impl Server {
 				m
 			},
 			_ => {
-				send(&worker.dealer,
+				send(&worker.reply,
 					"Please send your shares key with a signature. Format: [shares, Enc(shares)]",
 					&clientID);
 				return Err(WorkerError::UnexpectedFormat(5))
@@ -677,32 +1503,66 @@ impl Server {
 	/*
 		Verify & Safe
 	*/
-		let verifyResult = match self.clientProfiles.read() {
-			Ok(mut guard) => {
-			 	let veriKey = guard.get(&clientID).unwrap().veriKey.clone();
-				veriKey.verify(
-					&msg[0], 										//shares
-					&Signature::from_bytes(&msg[1]).unwrap()		//signature of shares
-				)
+		let signature = match Signature::from_bytes(&msg[1]) {
+			Ok(sig) => sig,
+			Err(_) => {
+				send(&worker.reply, "Error: Invalid signature.", &clientID);
+				return Err(WorkerError::DecryptionFail(5))
 			},
-			Err(_) => return Err(WorkerError::MutexLockFail(5)),
 		};
-		let idx = self.clientList
-				.read().unwrap()
-				.iter().position(|s| s == &clientID)
-				.unwrap();
+		let (veriKey, idx) = match (self.clientProfiles.read(), self.client_index(&clientID)) {
+			(Ok(guard), Some(idx)) => match guard.get(&clientID) {
+				Some(p) => (p.veriKey.clone(), idx),
+				None => {
+					send(&worker.reply,"Error: Your profile not found", &clientID);
+					return Err(WorkerError::ClientNotFound(5))
+				},
+			},
+			(Ok(_), None) => {
+				send(&worker.reply,"Error: Your profile not found", &clientID);
+				return Err(WorkerError::ClientNotFound(5))
+			},
+			(Err(_), _) => return Err(WorkerError::MutexLockFail(5)),
+		};
+		// signed with session and round, shares from another round fail here
+		let mut signed = self.ID.as_bytes().to_vec();
+		signed.extend(&(self.round() as u64).to_le_bytes());
+		signed.extend(&msg[0]);
+		let verifyResult = veriKey.verify(
+			&signed, 										//session | round | shares
+			&signature										//signature of shares
+		);
 		match verifyResult {
 			Ok(_) => {
-				let mut shares = self.shares.lock().unwrap();
-				shares[idx] = read_le_u64(&msg[0]);
-		 		send(&worker.dealer,
+				let (P, B) = match self.param.read() {
+					Ok(param) => (param.P, self.V / param.L),
+					Err(_) => return Err(WorkerError::MutexLockFail(5)),
+				};
+				let aggregation = match ShareCodec::new(P).decode(&msg[0]) {
+					Ok(a) => a,
+					Err(_) => {
+						send(&worker.reply, "Error: Malformed aggregated shares.", &clientID);
+						return Err(WorkerError::UnexpectedFormat(5))
+					},
+				};
+				// one share per block, anything else would break reconstruction
+				if aggregation.len() != B {
+					send(&worker.reply, "Error: Aggregated shares of the wrong length.", &clientID);
+					return Err(WorkerError::UnexpectedFormat(5))
+				}
+				let mut shares = match self.shares.lock() {
+					Ok(guard) => guard,
+					Err(_) => return Err(WorkerError::MutexLockFail(5)),
+				};
+				shares[idx] = aggregation;
+		 		send(&worker.reply,
 		 			"Your aggregated shares has been save.",
 		 			&clientID);
-		 		worker.threadSender.send(5);
+		 		self.notify(State::Aggregation);
 		 		return Ok(5)
 			},
 			Err(_) => {
-		 		send(&worker.dealer, "Error: Decryption Fail.", &clientID);
+		 		send(&worker.reply, "Error: Decryption Fail.", &clientID);
 				return Err(WorkerError::DecryptionFail(5))
 			},
 		}
@@ -721,32 +1581,10 @@ impl Server {
 		then do Lagrange
 
 	*/
-		let B = shares[0].len();
-		let N = shares.len();
-		let P = param.P as u128;
-		let R3 = param.useR3 as u128;
-		let R2 = param.useR2 as u128;
-		let pss = PackedSecretSharing::new(
-			P, R2, R3,
-			param.useD2, param.useD3, self.V, param.L, N
-		);
-		let mut sharesPoints = Vec::new();
-		let mut shares_remove_empty = Vec::new();
-		for i in 0..M {
-			if shares[i].len() == 0 {
-				println!("skip {:?} {:?}", i, shares[i]);
-		    	continue;
-			}
-			sharesPoints.push(R3.modpow((i+1) as u128, P) as u64);
-			shares_remove_empty.push(shares[i].clone());
-		}
-		println!("shares_remove_empty {:?}, sharesPoints {}", shares_remove_empty.len(), sharesPoints.len());
-		let ret = pss.reconstruct(&shares_remove_empty, sharesPoints.as_slice());
-		println!("Reconstruction DONE {:?}", ret);
-		return Ok(ret);
+		return Ok(reconstruct_sum(&shares[..M], param, self.V));
 	}
 
-	fn check_state(&self, state: usize) -> bool {
+	fn check_state(&self, state: State) -> bool {
 		if *self.STATE.read().unwrap() == state {return true;}
 		return false;
 	}
@@ -754,5 +1592,48 @@ impl Server {
 		if self.clientList.read().unwrap().contains(&clientID) {return true;}
 		return false;
 	}
+	// Position in the client list, None for an unknown ID
+	fn client_index(&self, clientID: &Vec<u8>) -> Option<usize> {
+		self.clientList.read().ok()?.iter().position(|s| s == clientID)
+	}
+}
+
+// SHA-256 over the frames, each prefixed by its length
+pub(crate) fn message_digest(frames: &[Vec<u8>]) -> Vec<u8> {
+	let mut hasher = Sha256::new();
+	for f in frames {
+		hasher.update(&(f.len() as u64).to_le_bytes());
+		hasher.update(f);
+	}
+	hasher.finalize().to_vec()
 }
 
+pub(crate) fn reconstruct_sum(shares: &[Vec<u64>], param: &Param, V: usize) -> Vec<u64> {
+/*
+	Session::reconstruction without the session,
+	the audit tool replays it on recorded shares
+*/
+	let N = shares.len();
+	let P = param.P as u128;
+	let R3 = param.useR3 as u128;
+	let R2 = param.useR2 as u128;
+	let pss = PackedSecretSharing::new(
+		P, R2, R3,
+		param.useD2, param.useD3, V, param.L, N
+	);
+	let mut sharesPoints = Vec::new();
+	let mut shares_remove_empty = Vec::new();
+	for i in 0..N {
+		if shares[i].len() == 0 {
+			debug!(client = i, "no aggregated shares, skipped");
+	    	continue;
+		}
+		sharesPoints.push(R3.modpow((i+1) as u128, P) as u64);
+		shares_remove_empty.push(shares[i].clone());
+	}
+	debug!(shares = shares_remove_empty.len(), points = sharesPoints.len(), "reconstructing");
+	let ret = pss.reconstruct(&shares_remove_empty, sharesPoints.as_slice());
+	info!(len = ret.len(), "reconstruction done");
+	trace!(sum = ?ret, "reconstructed");
+	ret
+}
@@ -7,142 +7,188 @@
 use std::str;
 use std::sync::*;
 use std::thread;
-use std::env;
+use std::process;
 
+use structopt::StructOpt;
+use p256::ecdsa::{SigningKey, VerifyKey};
 use zmq;
+use transport::{Transport, ZmqTransport};
+use tracing::{info, error};
 use server::*;
+use server::config::*;
 use server::param::*;
 use server::worker::*;
 
 fn main() {
 
-    let args: Vec<String> = env::args().collect();
-	println!("hello");
-    let context = zmq::Context::new();
-    let (tx, rx) = mpsc::channel();
-    let param = Param::new(
-            3073700804129980417u64,                // Prime 62 bits
-            1414118249734601779u64, 20,            // Root2, 2^x degree
-            308414859194273485u64, 15,             // Root3, 3^x degree
-        );
     /*
-        May provide IP addr & two different ports# 
+        Config file and/or named flags, see --help
     */
-    assert!(args.len() == 7 || args.len() == 9 || args.len() == 12); 
-    let server;
-    if args.len() == 9 {
-        server = Arc::new(Server::new_malicious(
-            args[1].parse::<usize>().unwrap(),          // MAX clients
-            args[2].parse::<usize>().unwrap(),          // Vector Length
-            args[3].parse::<usize>().unwrap(),          // Input Bit Limit
-            args[4].parse::<usize>().unwrap(),          // Dropouts
-            args[5].parse::<usize>().unwrap(),          // Session time
-            args[6].parse::<usize>().unwrap(),          // IS Session time
-            args[7].parse::<usize>().unwrap(),          // Corrupted Parties
-            args[8].parse::<bool>().unwrap(),           // Malicious Flag
-            param
-        ));
-    } else if args.len() == 7 {
-        server = Arc::new(Server::new_semi_honest(
-            args[1].parse::<usize>().unwrap(),          // MAX clients
-            args[2].parse::<usize>().unwrap(),          // Vector Length
-            args[3].parse::<usize>().unwrap(),          // Dropouts
-            args[4].parse::<usize>().unwrap(),          // Session time
-            args[5].parse::<usize>().unwrap(),          // IS Session time
-            args[6].parse::<bool>().unwrap(),           // Malicious Flag
-            param
-        ));
-    } else {
-        panic!("Worng Arguments!");
+    let opt = ServerOpt::from_args();
+    let cfg = match ServerConfig::from_opt(&opt) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2);
+        },
+    };
+    if opt.print_config {
+        print!("{}", cfg.to_toml());
+        if let Err(e) = cfg.validate() {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+        return;
+    }
+    if opt.print_verify_key {
+        match cfg.load_or_create_key() {
+            Ok(Some(k)) => {
+                let vk = VerifyKey::from(&SigningKey::new(&k).unwrap());
+                println!("{}", hex::encode(VerifyKey::to_encoded_point(&vk, true).to_bytes()));
+            },
+            Ok(None) => eprintln!("--print-verify-key needs --key-file"),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(2);
+            },
+        }
+        return;
     }
-    let primes = vec![2,3,5,7,11,13,17,19,23,29,31,37,41,43,47,53,59,61,67,71,
-    73,79,83,89,97,101,103,107,109,113,127,131,137,139,149,151,157,163,167,173,
-    179,181,191,193,197,199,211,223,227,229,233,239,241,251,257,263,269,271,277,
-    281,283,293,307,311,313,317,331,337,347,349,353,359,367,373,379,383,389,397,
-    401,409,419,421,431,433,439,443,449,457,461,463,467,479,487,491,499,503,509,
-    521,523,541,547,557,563,569,571,577,587,593,599,601,607,613,617,619,631,641,
-    643,647,653,659,661,673,677,683,691,701,709,719,727,733,739,743,751,757,761,
-    769,773,787,797,809,811,821,823,827,829,839,853,857,859,863,877,881,883,887,
-    907,911,919,929,937,941,947,953,967,971,977,983,991,997,1009];
-    if primes.contains(&args[2].parse::<usize>().unwrap()) {
-        panic!("vector length is prime!");
+    if let Err(e) = cfg.validate() {
+        eprintln!("{}", e);
+        process::exit(2);
     }
+    logging::init(cfg.log_format, &cfg.log_level);
+
+    let context = zmq::Context::new();
+    let transport: Arc<dyn Transport> = Arc::new(ZmqTransport::new(
+        context.clone(), cfg.ip.as_deref(), cfg.msg_port, cfg.broadcast_port, cfg.catchup_port));
+    /*
+        Async core: state transitions, timers and message dispatch
+        are tasks, handlers run on the blocking pool,
+        at most `workers` at a time
+    */
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .max_blocking_threads(cfg.workers)
+        .thread_name("server")
+        .enable_time()
+        .build()
+        .unwrap();
+    /*
+        The config describes one session;
+        more can be registered through Server::add_session
+    */
+    let session = match cfg.build_session() {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2);
+        },
+    };
+    let server = Arc::new(Server::new());
+    let session = match server.add_session(session) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("{:?}", e);
+            process::exit(2);
+        },
+    };
 
     // Server Thread
     /*
-        Runs frontend and backend of zmq sockets structure.
+        Client messages in, replies out,
+        sleeps in the transport while idle.
 
-        Reciever port: arg[10] 
+        Reciever port: msg_port
         (defualt: 8888)
     */
-    let ctx = context.clone();
+    let t = transport.clone();
     let svr = server.clone();
-    let arg = args.clone();
     let serverThread = thread::spawn(move || {
-        if arg.len() == 12 {
-             svr.server_task(ctx, Some(&arg[9]),  arg[10].parse::<usize>().unwrap());
-        } else {
-            svr.server_task(ctx, None, 8888);
-        }
+        svr.server_task(&*t);
     });
 
-    // State Thread
+    // Publisher Thread
     /*
-        Recieves information from worker threads,
-        constantly keeps track of count and timer,
-        changes state once enough client have participated.
+        Forwards every session's broadcasts
+        to one PUB port.
 
-        Publisher port: arg[11]
+        Publisher port: broadcast_port
         (default: 9999)
     */
-    let ctx = context.clone();
+    let t = transport.clone();
     let svr = server.clone();
-    let arg = args.clone();
-    let stateThread = thread::spawn(move || {
-        if arg.len() == 12 {
-             match svr.state_task(ctx, Some(&arg[9]), arg[11].parse::<usize>().unwrap(), rx) {
-                Ok(_) => (),
-                Err(e) => println!("{:?}", e),
-            };
-        } else {
-            match svr.state_task(ctx, None, 9999, rx) {
-                Ok(_) => (),
-                Err(e) => println!("{:?}", e),
-            };
-        }
+    let publisherThread = thread::spawn(move || {
+        svr.publisher_task(&*t);
     });
 
-    // Worker Thread
+    // Catch-up Thread
     /*
-        Handles msg passed from the frontend,
-        1 msg + 1 reply per loop,
-        infoms state thread once successfully process 1 msg.
+        Serves broadcasts by session and seq
+        to subscribers that missed them.
+
+        Catch-up port: catchup_port
+        (default: 9998)
     */
-	let mut workerThreadPool = Vec::new();
-	for i in 0..10 {
-		let worker = Worker::new(
-            &format!("Worker{}", i.to_string()),
-            context.clone(), 
-            tx.clone()
-        );
-        let svr = server.clone();
-    	let child = thread::spawn(move || {
-    		println!("spawning {:?}", i);
-            match svr.worker_task(worker) {
-                Ok(_) => (),
-                Err(e) => println!("{:?}", e),
-            };
-	    });
-	    workerThreadPool.push(child);
-    }
+    let t = transport.clone();
+    let svr = server.clone();
+    let catchupThread = thread::spawn(move || {
+        svr.catchup_task(&*t);
+    });
 
+    // Metrics Thread
+    /*
+        Prometheus text on GET /metrics,
+        localhost only.
+
+        Metrics port: metrics_port
+        (default: off)
+    */
+    let svr = server.clone();
+    let c = cfg.clone();
+    let metricsThread = c.metrics_port.map(|port| thread::spawn(move || {
+        match svr.metrics_task(None, port) {
+            Ok(_) => (),
+            Err(e) => error!(error = ?e, "metrics endpoint stopped"),
+        };
+    }));
+
+    // State Task
+    /*
+        One per session.
+        Sleeps until a handler reports a client
+        or the state's deadline passes,
+        changes state once enough client have participated.
+    */
+    let t = transport.clone();
+    let sess = session.clone();
+    let stateTask = runtime.spawn(async move {
+        match sess.state_task(&*t).await {
+            Ok(_) => (),
+            Err(e) => error!(error = ?e, "session stopped"),
+        };
+    });
+
+    // Dispatch Task
+    /*
+        Takes msg from the frontend, one task each,
+        routes it to its session which infoms that session's
+        state task once successfully process 1 msg.
+    */
+    let svr = server.clone();
+    match runtime.block_on(svr.dispatch_task(transport.clone(), cfg.workers)) {
+        Ok(_) => (),
+        Err(e) => error!(error = ?e, "dispatch stopped"),
+    };
 
-    for wt in workerThreadPool {
-    	wt.join().unwrap();
-    }
     serverThread.join().unwrap();
-    stateThread.join().unwrap();
+    publisherThread.join().unwrap();
+    catchupThread.join().unwrap();
+    if let Some(t) = metricsThread {
+        t.join().unwrap();
+    }
+    runtime.block_on(stateTask);
 
-    println!("Application shut down.");
+    info!("Application shut down.");
 
 }
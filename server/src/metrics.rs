@@ -0,0 +1,189 @@
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::sync::*;
+use std::time::Duration;
+
+use tracing::{info, debug};
+
+use crate::worker::ServerError;
+
+/*
+	Per-session metrics, Prometheus text format
+	Each Session keeps its own registry, Server::metrics_task
+	renders all of them on GET /metrics
+		name					type		labels
+		state_seconds			gauge		session, round, state
+		clients_registered		gauge		session
+		clients_surviving		gauge		session, round, state
+		dropouts				gauge		session, round, state
+		ec_failures				gauge		session, round
+		relayed_bytes_total		counter		session, round
+		reconstruction_seconds	gauge		session, round
+*/
+
+pub const STATE_SECONDS: &str = "secagg_state_seconds";
+pub const CLIENTS_REGISTERED: &str = "secagg_clients_registered";
+pub const CLIENTS_SURVIVING: &str = "secagg_clients_surviving";
+pub const DROPOUTS: &str = "secagg_dropouts";
+pub const EC_FAILURES: &str = "secagg_ec_failures";
+pub const RELAYED_BYTES: &str = "secagg_relayed_bytes_total";
+pub const RECONSTRUCTION_SECONDS: &str = "secagg_reconstruction_seconds";
+
+// (name, type, help), rendered in this order
+const FAMILIES: [(&str, &str, &str); 7] = [
+	(STATE_SECONDS, "gauge", "Time spent in a state"),
+	(CLIENTS_REGISTERED, "gauge", "Clients in the client list"),
+	(CLIENTS_SURVIVING, "gauge", "Clients whose input can still end up in the sum, checked when a state exits"),
+	(DROPOUTS, "gauge", "Clients lost in a state"),
+	(EC_FAILURES, "gauge", "Clients failing the error correction tests"),
+	(RELAYED_BYTES, "counter", "Share bytes relayed between clients"),
+	(RECONSTRUCTION_SECONDS, "gauge", "Time spent reconstructing the sum"),
+];
+
+pub type Labels = Vec<(&'static str, String)>;
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+	values: Mutex<BTreeMap<(&'static str, String), f64>>,	// (name, rendered labels) -> value
+}
+
+impl Metrics {
+
+	pub fn new() -> Metrics {
+		Metrics::default()
+	}
+
+	pub fn set(&self, name: &'static str, labels: &Labels, value: f64) {
+		self.values.lock().unwrap().insert((name, format_labels(labels)), value);
+	}
+
+	pub fn add(&self, name: &'static str, labels: &Labels, value: f64) {
+		*self.values.lock().unwrap().entry((name, format_labels(labels))).or_insert(0f64) += value;
+	}
+
+	pub fn get(&self, name: &'static str, labels: &Labels) -> Option<f64> {
+		self.values.lock().unwrap().get(&(name, format_labels(labels))).cloned()
+	}
+}
+
+fn format_labels(labels: &Labels) -> String {
+/*
+	{a="x",b="y"} with \, " and newlines escaped
+*/
+	let pairs: Vec<String> = labels.iter()
+		.map(|(k, v)| {
+			let v = v.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+			format!("{}=\"{}\"", k, v)
+		})
+		.collect();
+	format!("{{{}}}", pairs.join(","))
+}
+
+pub fn render(registries: &[&Metrics]) -> String {
+/*
+	One HELP/TYPE header per family,
+	then the samples of every registry
+*/
+	let mut text = String::new();
+	for (name, kind, help) in FAMILIES.iter() {
+		text += &format!("# HELP {} {}\n# TYPE {} {}\n", name, help, name, kind);
+		for m in registries {
+			let values = m.values.lock().unwrap();
+			for ((n, labels), v) in values.iter() {
+				if n == name {
+					text += &format!("{}{} {}\n", n, labels, v);
+				}
+			}
+		}
+	}
+	text
+}
+
+// A scraper gets this long to send its request and take the reply
+const SCRAPE_TIMEOUT_MS: u64 = 2000;
+
+pub fn serve<F>(ip: Option<&str>, port: usize, body: F) -> Result<usize, ServerError>
+	where F: Fn() -> String {
+/*
+	Bare HTTP/1.1, one request per connection
+	GET /metrics gets the text, anything else 404
+	Binds localhost unless an ip is given
+	Connections are served one at a time, each with a timeout
+	so a silent one can't hold the others up
+*/
+	let address = format!("{}:{}", ip.unwrap_or("127.0.0.1"), port);
+	info!("Metrics going http://{}/metrics", address);
+	let listener = TcpListener::bind(&address).map_err(|_| ServerError::MetricsFail(0))?;
+	for stream in listener.incoming() {
+		let mut stream = match stream {
+			Ok(s) => s,
+			Err(_) => continue,
+		};
+		let timeout = Some(Duration::from_millis(SCRAPE_TIMEOUT_MS));
+		if stream.set_read_timeout(timeout).is_err() || stream.set_write_timeout(timeout).is_err() {
+			continue;
+		}
+		let mut buf = [0u8; 1024];
+		let n = match stream.read(&mut buf) {
+			Ok(n) => n,
+			Err(e) => {
+				debug!(error = %e, "metrics request not read");
+				continue
+			},
+		};
+		let request = String::from_utf8_lossy(&buf[..n]);
+		let path = request.split_whitespace().nth(1).unwrap_or("");
+		let response = match (request.starts_with("GET "), path) {
+			(true, "/metrics") => {
+				let text = body();
+				format!("HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+					text.len(), text)
+			},
+			_ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+		};
+		if let Err(e) = stream.write_all(response.as_bytes()) {
+			debug!(error = %e, "metrics reply not sent");
+		}
+	}
+	Ok(0)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn set_add_get() {
+		let m = Metrics::new();
+		let labels: Labels = vec![("session", "s".to_string()), ("round", "0".to_string())];
+		assert_eq!(m.get(RELAYED_BYTES, &labels), None);
+		m.add(RELAYED_BYTES, &labels, 10f64);
+		m.add(RELAYED_BYTES, &labels, 5f64);
+		assert_eq!(m.get(RELAYED_BYTES, &labels), Some(15f64));
+		m.set(DROPOUTS, &labels, 2f64);
+		m.set(DROPOUTS, &labels, 1f64);
+		assert_eq!(m.get(DROPOUTS, &labels), Some(1f64));
+	}
+
+	#[test]
+	fn labels_are_escaped() {
+		let labels: Labels = vec![("session", "a\"b\\c\nd".to_string())];
+		assert_eq!(format_labels(&labels), "{session=\"a\\\"b\\\\c\\nd\"}");
+	}
+
+	#[test]
+	fn render_groups_by_family() {
+		let a = Metrics::new();
+		let b = Metrics::new();
+		a.set(CLIENTS_REGISTERED, &vec![("session", "a".to_string())], 4f64);
+		b.set(CLIENTS_REGISTERED, &vec![("session", "b".to_string())], 3f64);
+		let text = render(&[&a, &b]);
+		let header = text.find("# TYPE secagg_clients_registered gauge\n").unwrap();
+		let sa = text.find("secagg_clients_registered{session=\"a\"} 4\n").unwrap();
+		let sb = text.find("secagg_clients_registered{session=\"b\"} 3\n").unwrap();
+		assert!(header < sa && sa < sb);
+		// every family has its header even without samples
+		assert!(text.contains("# TYPE secagg_relayed_bytes_total counter\n"));
+	}
+}
@@ -0,0 +1,144 @@
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Serialize, Deserialize};
+
+use crate::worker::ServerError;
+
+/*
+	Aggregate of one round
+	Kept by the Session (Session::results), published on "<session>/RS-<round>"
+	and written to the output file, .csv or anything else as JSON
+*/
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoundResult {
+	pub session: String,
+	pub round: usize,
+	pub contributors: usize,		// clients whose inputs are in the sum
+	pub dropouts: Vec<usize>,		// indices in the client list
+	#[serde(default)]
+	pub rejected: Vec<usize>,		// clients whose aggregated shares were off, left out
+	pub timestamp: u64,				// unix seconds
+	pub sum: Vec<u64>,
+}
+
+impl RoundResult {
+	pub fn new(session: &str, round: usize, contributors: usize, dropouts: &[usize], sum: Vec<u64>) -> RoundResult {
+		let timestamp = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map(|d| d.as_secs())
+			.unwrap_or(0);
+		RoundResult {
+			session: session.to_string(),
+			round: round,
+			contributors: contributors,
+			dropouts: dropouts.to_vec(),
+			rejected: Vec::new(),
+			timestamp: timestamp,
+			sum: sum,
+		}
+	}
+
+	pub fn with_rejected(mut self, rejected: &[usize]) -> RoundResult {
+		self.rejected = rejected.to_vec();
+		self
+	}
+
+	// What the server signs: session, round, then the sum as u64 le
+	pub fn signed_bytes(session: &str, round: usize, sum: &[u64]) -> Vec<u8> {
+		let mut bytes = session.as_bytes().to_vec();
+		bytes.extend(&(round as u64).to_le_bytes());
+		for s in sum {
+			bytes.extend(&s.to_le_bytes());
+		}
+		bytes
+	}
+}
+
+pub fn write_results(path: &Path, results: &[RoundResult]) -> Result<(), ServerError> {
+/*
+	Rewrite the whole file with every round so far
+	CSV: one row per round, dropouts joined by ';'
+*/
+	let text = match path.extension().and_then(|e| e.to_str()) {
+		Some("csv") => {
+			let mut text = String::from("session,round,contributors,dropouts,timestamp");
+			let width = results.iter().map(|r| r.sum.len()).max().unwrap_or(0);
+			for i in 0..width {
+				text += &format!(",sum_{}", i);
+			}
+			text += "\n";
+			for r in results {
+				let dropouts: Vec<String> = r.dropouts.iter().map(|d| d.to_string()).collect();
+				text += &format!("{},{},{},{},{}", r.session, r.round, r.contributors, dropouts.join(";"), r.timestamp);
+				for s in r.sum.iter() {
+					text += &format!(",{}", s);
+				}
+				text += "\n";
+			}
+			text
+		},
+		_ => serde_json::to_string_pretty(results).map_err(|_| ServerError::OutputFail(1))?,
+	};
+	let tmp = path.with_extension("tmp");
+	let mut file = fs::File::create(&tmp).map_err(|_| ServerError::OutputFail(2))?;
+	file.write_all(text.as_bytes()).map_err(|_| ServerError::OutputFail(2))?;
+	fs::rename(&tmp, path).map_err(|_| ServerError::OutputFail(2))?;
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn results() -> Vec<RoundResult> {
+		vec![
+			RoundResult::new("out", 0, 3, &[], vec![1, 2, 3]),
+			RoundResult::new("out", 1, 2, &[0, 2], vec![4, 5, 6]).with_rejected(&[1]),
+		]
+	}
+
+	#[test]
+	fn signed_bytes_layout() {
+		let bytes = RoundResult::signed_bytes("out", 1, &[4, 5]);
+		let mut expected = b"out".to_vec();
+		expected.extend(&1u64.to_le_bytes());
+		expected.extend(&4u64.to_le_bytes());
+		expected.extend(&5u64.to_le_bytes());
+		assert_eq!(bytes, expected);
+	}
+
+	#[test]
+	fn json_round_trip() {
+		let path = std::env::temp_dir().join(format!("results-{}.json", std::process::id()));
+		write_results(&path, &results()).unwrap();
+		let read: Vec<RoundResult> = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+		fs::remove_file(&path).unwrap();
+		assert_eq!(read.len(), 2);
+		assert_eq!(read[1].dropouts, vec![0, 2]);
+		assert_eq!(read[1].rejected, vec![1]);
+		assert_eq!(read[1].sum, vec![4, 5, 6]);
+	}
+
+	#[test]
+	fn rejected_defaults_to_empty() {
+		let text = r#"{"session":"out","round":0,"contributors":1,"dropouts":[],"timestamp":0,"sum":[7]}"#;
+		let r: RoundResult = serde_json::from_str(text).unwrap();
+		assert!(r.rejected.is_empty());
+	}
+
+	#[test]
+	fn csv_rows() {
+		let path = std::env::temp_dir().join(format!("results-{}.csv", std::process::id()));
+		let results = results();
+		write_results(&path, &results).unwrap();
+		let text = fs::read_to_string(&path).unwrap();
+		fs::remove_file(&path).unwrap();
+		let lines: Vec<&str> = text.lines().collect();
+		assert_eq!(lines[0], "session,round,contributors,dropouts,timestamp,sum_0,sum_1,sum_2");
+		assert_eq!(lines[2], format!("out,1,2,0;2,{},4,5,6", results[1].timestamp));
+	}
+}
@@ -1,6 +1,9 @@
 use std::cmp::*;
 
-#[derive(Debug, Clone)]
+use serde::{Serialize, Deserialize};
+use tracing::debug;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Param {
 	pub P: u64,
 	pub R2: u64,
@@ -15,6 +18,8 @@ pub struct Param {
 	pub useR2: u64,
 	pub useR3: u64,
 	pub L: usize,
+	#[serde(default)]
+	pub T: usize,			// corruptions the params leave room for, 0 in semi-honest
 }
 
 impl Param {
@@ -56,6 +61,7 @@ impl Param {
 			useR2: 0u64,
 			useR3: 0u64,
 			L: 0,
+			T: 0,
 		}
 	}
 
@@ -63,11 +69,15 @@ impl Param {
 		Theoretical limit to ensure security.  
 		Actual polynomial limit should be D2, 
 		which must be smaller than Reconstruction Limit
+		None if too few clients are left or the roots run out
 	*/
 	pub fn calculate_semi_honest(&mut self, 
-		numClients: usize, vectorSize: usize, dropouts: usize) -> Vec<u64> {
+		numClients: usize, vectorSize: usize, dropouts: usize) -> Option<Vec<u64>> {
 		
-		let mut reconstructLimit = numClients - dropouts;
+		let mut reconstructLimit = numClients.checked_sub(dropouts)?;
+		if reconstructLimit == 0 {
+			return None;
+		}
 
 		// find the nearest exponent of two
 		/* Ex: degree2 = 300 -> 256
@@ -81,45 +91,52 @@ impl Param {
 		}
 		power2 -= 1;
 		// Make sure don't exceed the maximun power roots provided
-		assert!(power2 <= self.D2);
+		if power2 > self.D2 {
+			return None;
+		}
 
 		self.useD2 = 2usize.pow(power2 as u32);
 		self.useR2 = self.rootTwos[power2];
-		self.L = greatest_factor_under(vectorSize, self.useD2 as usize);
+		self.L = greatest_factor_under(vectorSize, self.useD2 as usize)?;
 
-		println!("deg2 < n {} - dropouts {} = reconstructLimit {}", numClients, dropouts, reconstructLimit);
-		println!("deg2 {:?} = blocklenth {} + corruption 0", self.useD2, self.L);
+		debug!(numClients, dropouts, reconstructLimit, deg2 = self.useD2, L = self.L, "semi-honest params");
 
 		// find the nearest exponent of three
 		/* Ex: degree3 = 1000 -> 729
 		*      power3 = 6 since 3^6 = 729
 		*/
+		// shares sit at points 1..=numClients, point 0 isn't handed out
 		let mut n = 3;
 		let mut power3 = 1;
-		while n < numClients {
+		while n <= numClients {
 			n *= 3;
 			power3 += 1;
 		}
 		// Make sure don't exceed the maximun power roots provided
-		assert!(power3 <= self.D3);
+		if power3 > self.D3 {
+			return None;
+		}
 
 		self.useD3 = 3usize.pow(power3 as u32);
 		self.useR3 = self.rootThrees[power3];
 
-		return vec![
+		return Some(vec![
 			self.P,					// prime
 			self.useR2,				// two-power root of unity
 			self.useR3,				// three-power root of unity
 			self.useD2 as u64,		// degree2
 			self.useD3 as u64,		// degree3
 			self.L as u64			// block length			
-		];
+		]);
 	}
 
 	pub fn calculate_malicious(&mut self, 
-		numClients: usize, vectorSize: usize, dropouts: usize, corruption: usize) -> Vec<u64> {
+		numClients: usize, vectorSize: usize, dropouts: usize, corruption: usize) -> Option<Vec<u64>> {
 		
-		let mut reconstructLimit = (numClients - (dropouts + 2 * corruption))/2;
+		let mut reconstructLimit = numClients.checked_sub(dropouts + 2 * corruption)?/2;
+		if reconstructLimit == 0 {
+			return None;
+		}
 
 		// find the nearest exponent of two
 		/* Ex: degree2 = 300 -> 256
@@ -133,41 +150,47 @@ impl Param {
 		}
 		power2 -= 1;
 		// Make sure don't exceed the maximun power roots provided
-		assert!(power2 <= self.D2);
+		// and leave room for the corruption in degree2
+		if power2 > self.D2 || n/2 <= corruption {
+			return None;
+		}
 		self.useD2 = n/2;
 		self.useR2 = self.rootTwos[power2];
-		self.L = greatest_factor_under(vectorSize, self.useD2 - corruption);
+		self.T = corruption;
+		self.L = greatest_factor_under(vectorSize, self.useD2 - corruption)?;
 
-		println!("deg2 < n {} - (d {} + 2t {}) = reconstructLimit {}", numClients, dropouts, corruption, reconstructLimit);
-		println!("deg2 {:?} = blocklenth {} + corruption {}", self.useD2, self.L, corruption);
+		debug!(numClients, dropouts, corruption, reconstructLimit, deg2 = self.useD2, L = self.L, "malicious params");
 		
 		// find the nearest exponent of three
 		/* Ex: degree3 = 1000 -> 729
 		*      power3 = 6 since 3^6 = 729
 		*/
+		// shares sit at points 1..=numClients, point 0 isn't handed out
 		let mut n = 3;
 		let mut power3 = 1;
-		while n < numClients {
+		while n <= numClients {
 			n *= 3;
 			power3 += 1;
 		}
 		// Make sure don't exceed the maximun power roots provided
-		assert!(power3 <= self.D3);
+		if power3 > self.D3 {
+			return None;
+		}
 		self.useD3 = 3usize.pow(power3 as u32);
 		self.useR3 = self.rootThrees[power3];
 
-		return vec![
+		return Some(vec![
 			self.P,					// prime
 			self.useR2,				// two-power root of unity
 			self.useR3,				// three-power root of unity
 			self.useD2 as u64,		// degree2
 			self.useD3 as u64,		// degree3
 			self.L as u64			// block length
-		];
+		]);
 	}
 }
 
-fn greatest_factor_under(mut a: usize, b: usize) -> usize {
+fn greatest_factor_under(mut a: usize, b: usize) -> Option<usize> {
 	/* Find the greatest facotr of a under b
 	   n/k = q
 	   where k is the largest factor where k < b
@@ -175,15 +198,17 @@ fn greatest_factor_under(mut a: usize, b: usize) -> usize {
 	   kq = n < bq
 	   then we find the smallest factor q s.t. q | n
 	*/
-	assert!(a >= b);
+	if a < b || b == 0 {
+		return None;
+	}
 	for i in 2..a {
 		if a % i == 0 {
 			if b * i >= a { 
-				return a/i; 
+				return Some(a/i); 
 			}
 		}
 	}
-	return 1;
+	return Some(1);
 }
 
 
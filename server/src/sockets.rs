@@ -1,7 +1,7 @@
-use zmq::Message;
-use zmq::Socket;
-use zmq::SNDMORE;
+use transport::Outgoing;
 use std::str;
+use std::convert::TryInto;
+use std::vec::IntoIter;
 
 use std::thread::sleep;
 use std::time::Duration;
@@ -13,12 +13,56 @@ pub enum RecvType {
 	matrix(Vec<Vec<u8>>),
 }
 
-pub fn take_id(socket: &Socket) -> Vec<u8> {
-	socket.recv_bytes(0).unwrap()
+impl RecvType {
+	// Back to payload frames, strings as one frame
+	pub fn frames(&self) -> Vec<Vec<u8>> {
+		match self {
+			RecvType::bytes(b) => vec![b.clone()],
+			RecvType::string(s) => vec![s.as_bytes().to_vec()],
+			RecvType::matrix(m) => m.clone(),
+		}
+	}
+}
+
+pub fn take_id(frames: &mut IntoIter<Vec<u8>>) -> Vec<u8> {
+	frames.next().unwrap_or_default()
+}
+
+pub fn take_session(frames: &mut IntoIter<Vec<u8>>) -> Option<String> {
+	/*
+		Every client message starts with the session it belongs to
+		None if the frame is malformed or nothing follows it
+	*/
+	let frame = frames.next()?;
+	if frames.len() == 0 {
+		return None
+	}
+	String::from_utf8(frame).ok()
+}
+
+pub fn take_round(frames: &mut IntoIter<Vec<u8>>) -> Option<usize> {
+	/*
+		Followed by its round number (u64 le)
+		None if the frame is malformed or nothing follows it
+	*/
+	let frame = frames.next()?;
+	if frames.len() == 0 {
+		return None
+	}
+	match frame.as_slice().try_into() {
+		Ok(b) => Some(u64::from_le_bytes(b) as usize),
+		Err(_) => None,
+	}
+}
+
+// Broadcast topics carry session and round, e.g. "modelA/IS-2"
+pub fn session_topic(session: &str, topic: &str, round: usize) -> String {
+	format!("{}/{}-{}", session, topic, round)
 }
 
-pub fn recv(socket: &Socket) -> RecvType {
-	let mut data = socket.recv_multipart(0).unwrap();
+// Payload frames of a message, what's left after take_round
+pub fn recv(frames: IntoIter<Vec<u8>>) -> RecvType {
+	let mut data: Vec<Vec<u8>> = frames.collect();
 	let mut stringRes = String::new();
 	let mut isString = true;
 	for d in &data {
@@ -43,66 +87,51 @@ pub fn recv(socket: &Socket) -> RecvType {
 }
 
 // Data can be Vec<Vec<u8>> or Vec<String> or Vec<str>
-pub fn send_vecs<I, T>(socket: &Socket, data: I, identity: &Vec<u8>) -> Result<usize, usize>
+pub fn send_vecs<I, T>(socket: &dyn Outgoing, data: I, identity: &Vec<u8>) -> Result<usize, usize>
 where 
     I: IntoIterator<Item = T> + std::fmt::Debug,
-    T: Into<Message>,
+    T: AsRef<[u8]>,
 {
-	// println!("send_vecs {:?}", data);
-	socket.send(identity, SNDMORE);
-    let result = socket.send_multipart(data, 0);
-    match result {
-        Ok(T) => Ok(0),
-        Err(Error) => Err(0),
+	let mut frames = vec![identity.clone()];
+	frames.extend(data.into_iter().map(|d| d.as_ref().to_vec()));
+    match socket.send(frames) {
+        Ok(_) => Ok(0),
+        Err(_) => Err(0),
     }
 }
 
 // Data can be Vec<u8> or &str
-pub fn send<T>(socket: &Socket, data: T, identity: &Vec<u8>) -> Result<usize, usize>
+pub fn send<T>(socket: &dyn Outgoing, data: T, identity: &Vec<u8>) -> Result<usize, usize>
 where
-    T: Into<Message> + std::fmt::Debug,
+    T: AsRef<[u8]> + std::fmt::Debug,
 {
-	// println!("send {:?} to {:?}", data, String::from_utf8(identity.to_vec()).unwrap());
-	socket.send(identity, SNDMORE);
-    let result = socket.send(data, 0);
-    match result {
-        Ok(T) => Ok(0),
-        Err(Error) => Err(0),
+    match socket.send(vec![identity.clone(), data.as_ref().to_vec()]) {
+        Ok(_) => Ok(0),
+        Err(_) => Err(0),
     }
 }
 
-pub fn publish<T>(socket: &Socket, data: T, topic: &str) -> Result<usize, usize>
-where
-    T: Into<Message> + std::clone::Clone + std::fmt::Debug,
-{
-	println!("pushing {}", topic);
-	for _ in 0..100 {
-		socket.send(topic.as_bytes(), zmq::SNDMORE);
-		match socket.send(data.clone(), 0){
-	        Ok(T) => continue,
-	        Err(Error) => return Err(0),
-		 };
+// What the server signs for a broadcast: topic, seq, then each frame length-prefixed
+pub fn broadcast_bytes(topic: &str, seq: u64, data: &[Vec<u8>]) -> Vec<u8> {
+	let mut bytes = topic.as_bytes().to_vec();
+	bytes.extend(&seq.to_le_bytes());
+	for d in data {
+		bytes.extend(&(d.len() as u64).to_le_bytes());
+		bytes.extend(d);
 	}
-	sleep(Duration::from_millis(10));
-	return Ok(0)
+	bytes
 }
 
-
-
-
-pub fn publish_vecs<I, T>(socket: &Socket, data: I, topic: &str) -> Result<usize, usize>
-where
-    I: IntoIterator<Item = T> + std::clone::Clone + std::fmt::Debug,
-    T: Into<Message>,
-{
-	//println!("+++++++++++++++++++++++++ {:?} data {:?}", topic, data);
-    for _ in 0..1004 {
-		socket.send(topic.as_bytes(), zmq::SNDMORE);
-		match socket.send_multipart(data.clone(), 0){
-	        Ok(T) => continue,
-	        Err(Error) => return Err(0),
-		 };
+pub fn announce(socket: &dyn Outgoing, topic: &str, seq: u64, data: Vec<Vec<u8>>) -> Result<usize, usize> {
+/*
+	Published once as [topic, seq, signature, data...]
+	subscribers that miss one see the gap in seq
+	and fetch it from the catch-up endpoint
+*/
+	let mut frames = vec![topic.as_bytes().to_vec(), seq.to_le_bytes().to_vec()];
+	frames.extend(data);
+	match socket.send(frames) {
+		Ok(_) => Ok(0),
+		Err(_) => Err(0),
 	}
-	sleep(Duration::from_millis(10));
-	return Ok(0)
 }
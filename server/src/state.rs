@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+use std::sync::*;
+
+use serde::{Serialize, Deserialize};
+
+use crate::Session;
+use p256::ecdsa::{SigningKey, Signature, signature::Signer};
+
+use crate::sockets::{RecvType, announce, broadcast_bytes};
+use transport::Outgoing;
+use crate::transcript::{Transcript, Entry, hex_frames};
+use tracing::error;
+use crate::worker::*;
+
+/* Server state machine
+	States are an enum, the order between them is a transition table
+	of guarded rows, first row whose guard holds wins.
+	Each state registers
+		handler		processes one client message while in the state
+		on_enter	runs when the state is entered, gets the previous state
+		on_exit		runs when the state is left, gets the next state
+	A new phase is a new variant, its rows and its StateHooks.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum State {
+	Handshake,
+	KeyExchange,
+	InputSharing,
+	ErrorCorrection,
+	Aggregation,
+	Done,
+}
+
+impl State {
+	// Number used in logs and error codes
+	pub fn code(&self) -> usize {
+		match self {
+			State::Handshake => 1,
+			State::KeyExchange => 2,
+			State::InputSharing => 3,
+			State::ErrorCorrection => 4,
+			State::Aggregation => 5,
+			State::Done => 6,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Guard {
+	Always,
+	Malicious,
+	SemiHonest,
+	MoreRounds,
+	LastRound,
+}
+
+impl Guard {
+	pub fn holds(&self, session: &Session) -> bool {
+		match self {
+			Guard::Always => true,
+			Guard::Malicious => session.is_malicious(),
+			Guard::SemiHonest => !session.is_malicious(),
+			Guard::MoreRounds => session.has_next_round(),
+			Guard::LastRound => !session.has_next_round(),
+		}
+	}
+}
+
+/*
+	Per state_task bookkeeping shared by the hooks
+*/
+pub struct StateCtx<'a> {
+	pub publisher: Box<dyn Outgoing>,
+	pub recvCnt: usize,
+	pub spBytes: Vec<u8>,
+	pub dropouts: Vec<usize>,
+	pub result: Option<Vec<u64>>,
+	pub log: &'a RwLock<Vec<Announcement>>,		// the session's broadcasts, index = seq
+	pub signKey: &'a SigningKey,				// server identity, signs every broadcast
+	pub transcript: Option<&'a Transcript>,		// every broadcast is recorded
+}
+
+// (topic, [signature, frames...]) of one broadcast
+pub type Announcement = (String, Vec<Vec<u8>>);
+
+impl<'a> StateCtx<'a> {
+	pub fn broadcast(&mut self, topic: String, frames: Vec<Vec<u8>>) {
+	/*
+		Sign topic (session, round), seq and frames
+		Log first so the catch-up endpoint can serve it
+		by the time a subscriber notices the seq
+	*/
+		let mut log = self.log.write().unwrap();
+		let seq = log.len() as u64;
+		let signature: Signature = self.signKey.sign(&broadcast_bytes(&topic, seq, &frames));
+		let mut signed = vec![signature.as_ref().to_vec()];
+		signed.extend(frames);
+		log.push((topic.clone(), signed.clone()));
+		drop(log);
+		if let Some(t) = self.transcript {
+			let entry = Entry::Broadcast { topic: topic.clone(), seq: seq as usize, frames: hex_frames(&signed) };
+			if let Err(e) = t.append(entry) {
+				error!(error = ?e, "transcript write failed");
+			}
+		}
+		announce(&*self.publisher, &topic, seq, signed);
+	}
+}
+
+/*
+	Everything state_task reacts to, from one channel
+		Handled		a handler finished with one client in that state
+		Deadline	the timer armed for that instant fired,
+					stale ones (state already left, deadline moved) are ignored
+		Admin		operator command, see Session::command
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+	Handled(State),
+	Deadline(tokio::time::Instant),
+	Admin(AdminCommand),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AdminCommand {
+	Advance,					// leave the current state now
+	Extend(std::time::Duration),	// push the current deadline back
+	Abort,						// stop the session, clients get the reason on AB
+}
+
+pub type Handler = fn(&Session, &Worker, Vec<u8>, RecvType) -> Result<usize, WorkerError>;
+pub type Hook = fn(&Session, &mut StateCtx, State) -> Result<(), ServerError>;
+
+#[derive(Clone, Copy, Default)]
+pub struct StateHooks {
+	pub handler: Option<Handler>,
+	pub on_enter: Option<Hook>,
+	pub on_exit: Option<Hook>,
+}
+
+#[derive(Clone)]
+pub struct StateMachine {
+	initial: State,
+	table: Vec<(State, Guard, State)>,
+	hooks: HashMap<State, StateHooks>,
+}
+
+impl StateMachine {
+
+	pub fn new(initial: State) -> StateMachine {
+		StateMachine {
+			initial: initial,
+			table: Vec::new(),
+			hooks: HashMap::new(),
+		}
+	}
+
+	pub fn initial(&self) -> State {
+		self.initial
+	}
+
+	pub fn transition(mut self, from: State, guard: Guard, to: State) -> StateMachine {
+		self.table.push((from, guard, to));
+		self
+	}
+
+	pub fn register(mut self, state: State, hooks: StateHooks) -> StateMachine {
+		self.hooks.insert(state, hooks);
+		self
+	}
+
+	pub fn next(&self, session: &Session, from: State) -> Option<State> {
+		self.table.iter()
+			.find(|(f, g, _)| *f == from && g.holds(session))
+			.map(|(_, _, to)| *to)
+	}
+
+	pub fn handler(&self, state: State) -> Option<Handler> {
+		self.hooks.get(&state).and_then(|h| h.handler)
+	}
+
+	pub fn advance(&self, session: &Session, ctx: &mut StateCtx, from: State) -> Result<State, ServerError> {
+	/*
+		Pick the next state, run exit hook of the old one
+		then entry hook of the new one
+	*/
+		let to = match self.next(session, from) {
+			Some(s) => s,
+			None => return Err(ServerError::UnknownState(from.code())),
+		};
+		if let Some(exit) = self.hooks.get(&from).and_then(|h| h.on_exit) {
+			exit(session, ctx, to)?;
+		}
+		if let Some(enter) = self.hooks.get(&to).and_then(|h| h.on_enter) {
+			enter(session, ctx, from)?;
+		}
+		Ok(to)
+	}
+}
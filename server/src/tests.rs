@@ -1,174 +1,526 @@
-use polynomials::*;
+use std::cmp::min;
+
 use pss::*;
 use pss::ModPow;
 use crate::param::*;
+use rand_core::{RngCore, OsRng};
+use tracing::{trace, debug};
+use crate::blame::{Blame, EcCheck, EcTest};
 
 
-pub fn lagrange_degree(poly: &Vec<u64>, param: &Param) -> bool {
+pub fn test_suit(corrections: &Vec<Vec<u64>>, param: &Param) -> EcCheck {
 	/*
-		Can't use yet
-		underlying lib does not support field operation
+		Each holder's corrections [DT, sumA, sumB] are points on three polynomials
+			DT			degree < D2, linear in the shares
+			sumA, sumB	degree < 2*D2 - 1, products of two shares
+		Decode each allowing T holders off, a holder that lied or got
+		an inconsistent share shows up in off instead of failing the tests.
+		Secrets are the decoded polynomials at the first L powers of R2
 	*/
-	let R3 = param.useR3 as u128;
-	let P = param.P as u128;
-
-	let mut xs = Vec::new();
-	let mut ys = Vec::new();
-	let mut products_inc: Vec<Polynomial<i128>> = Vec::new();
-	let mut products_dec: Vec<Polynomial<i128>> = Vec::new();
-	for i in 0..poly.len() {
-		if poly[i] == 0 {
-	    	continue;
-		}
-		println!("point1");
-		let new = poly![-1*(poly[i] as i128), 1];
-		if products_inc.is_empty() {
-			products_inc.push(new);
-		} else {
-			let last = products_inc[products_inc.len()-1].clone();
-			products_inc.push(last * new);
-			println!("point2");
-		}
-		ys.push(R3.modpow((i+1) as u128, P) as i128);
-		xs.push(poly[i].clone() as i128);
-	}
-	for i in poly.len()..0 {
-		if poly[i] == 0 {
-			continue;
-		}
-		let new = poly![-1*(poly[i] as i128), 1];
-		if products_dec.is_empty() {
-			products_dec.push(new);
-		} else {
-			println!("point3");
-			let last = products_dec[products_dec.len()-1].clone();
-			products_dec.push(last * new);
-		}
-	}
-	products_dec.reverse();
-	let mut construction = poly![0];
-	let M = xs.len();
-	for i in 0..M {	
-		let numerator = products_inc[i-1].clone() * products_dec[i+1].clone();
-		let mut denominator = 1i128;
-		for j in 0..M {
-			if j != i  { 
-				denominator *= xs[j] - xs[i];
-			}
-		}
-		construction += (numerator * ys[i] as i128 / denominator);
-	}
-	if construction.degree() == param.useD2 {
-		return true;
-	} else {
-		return false;
-	}
-}
-
-/*
-shares of party i
-	
-	[ party j [t1, t2, t3],
-	  party j [t1, t2, t3],
-	 ...
-	 ]
-
-	 		[s00, s01, s02],	//shares of party 0
-		    [s10, s11, s13],	//shares of party 1
-		    ...
-		    [sm0, sm1, sm3]		//shares of party m
-
-*/
-pub fn test_suit1(corrections: &Vec<Vec<u64>>, param: &Param, dropout: &mut Vec<usize>, pss: &PackedSecretSharing<u128>) -> bool {
 	let M = corrections.len();
 	let P = param.P as u128;
+	let R2 = param.useR2 as u128;
 	let R3 = param.useR3 as u128;
-
-	//let mut pss = PackedSecretSharing::new(
-	//	P, param.useR2 as u128, R3, 
-	//	param.useD2, param.useD3, 3*param.L, param.L, M
-	//);
-
-	let mut evalPoints = Vec::new();
-	let mut corrections_remove_empty = Vec::new();
+	let L = param.L;
+	debug!(recvShares = M, d2 = param.useD2, d3 = param.useD3, t = param.T, "EC decode");
+	let mut points = Vec::new();
+	let mut holders = Vec::new();
+	let mut off = Vec::new();
 	for j in 0..M {
-		if corrections[j].len() == 0 {
-			dropout.push(j);
-	    	continue;
+		match corrections[j].len() {
+			0 => (),
+			3 => {
+				points.push(R3.modpow((j+1) as u128, P));
+				holders.push(j);
+			},
+			_ => off.push(j),
 		}
-		evalPoints.push(R3.modpow((j+1) as u128, P) as u64);
-		corrections_remove_empty.push(corrections[j].clone());
 	}
-	let result = pss.reconstruct(&corrections_remove_empty, evalPoints.as_slice());
-	
+	let secretPoints: Vec<u128> = (0..L).map(|s| R2.modpow(s as u128, P)).collect();
+	let mut result = vec![0u64; 3*L];
+	let mut undecodable = false;
+	for r in 0..3 {
+		let k = match r {
+			0 => param.useD2,
+			_ => 2*param.useD2 - 1,
+		};
+		let values: Vec<u128> = holders.iter().map(|&j| corrections[j][r] as u128 % P).collect();
+		let poly = match decode(&points, &values, k, param.T, P) {
+			Some(f) => f,
+			None => {
+				undecodable = true;
+				continue
+			},
+		};
+		for (i, &j) in holders.iter().enumerate() {
+			if eval(&poly, points[i], P) != values[i] && !off.contains(&j) {
+				off.push(j);
+			}
+		}
+		for s in 0..L {
+			result[r*L + s] = eval(&poly, secretPoints[s], P) as u64;
+		}
+	}
+	off.sort();
+	/*
+		Degree test secrets in result[0..L] aren't checked on purpose:
+		DT is a random combination of the dealer's shares, so its secrets
+		are the same combination of the dealer's inputs and can be anything.
+		The test is the decoding itself, a dealer whose shares aren't on one
+		polynomial of degree < D2 leaves holders off or the row undecodable
+	*/
 	let mut sum = 0;
-	for i in 2*param.L..3*param.L {
+	for i in 2*L..3*L {
 		sum = (sum + result[i]) % param.P;
 	}
-	/*	
+	// Reconstructed test values, middle section and sum should be 0s
+	trace!(result = ?result, sum = sum, off = ?off, "EC result");
+	/*
 		Input Bit Test, Quadratic test, L2-norm bit test
 		secrets should be 0
 	*/
-	for i in param.L..2*param.L {
-		if result[i] != 0 {
-			return false;
-		}
-	}
-	/*	
+	let nonzero: Vec<usize> = (L..2*L).filter(|&i| result[i] != 0).collect();
+	/*
 		Input Bound Test, L2-norm sum test, L2-norm bound test
 		secrets sums to 0
 	*/
-	if sum != 0 { 
-		return false; 
+	let check = EcCheck {
+		holders: holders,
+		off: off,
+		undecodable: undecodable,
+		nonzero: nonzero,
+		sum: sum,
+	};
+	debug!(passed = check.passed(), off = check.off.len(), "EC checked");
+	return check;
+}
+
+pub fn batch_test(corrections: &[Vec<Vec<u64>>], clients: &[usize], param: &Param) -> Vec<(usize, EcCheck)> {
+	/*
+		EC check for a group of clients, returns the ones that aren't clean
+		(failing, or with holders off) with the evidence from their own decoding
+		The tests are linear in the shares, so a random linear combination
+		of the group's corrections passes iff every client does
+		(a cheater slips through with probability 1/P).
+		One reconstruction when everyone is honest,
+		otherwise split the group in halves and recurse
+	*/
+	if clients.is_empty() {
+		return Vec::new();
+	}
+	if clients.len() == 1 {
+		let i = clients[0];
+		let check = test_suit(&corrections[i], param);
+		return match check.clean() {
+			true => Vec::new(),
+			false => vec![(i, check)],
+		};
+	}
+	match combine(corrections, clients, param) {
+		Some(combined) if test_suit(&combined, param).clean() => {
+			debug!(clients = clients.len(), "EC batch passed");
+			Vec::new()
+		},
+		_ => {
+			debug!(clients = clients.len(), "EC batch failed, bisecting");
+			let (left, right) = clients.split_at(clients.len() / 2);
+			let mut failed = batch_test(corrections, left, param);
+			failed.extend(batch_test(corrections, right, param));
+			failed
+		},
+	}
+}
+
+fn combine(corrections: &[Vec<Vec<u64>>], clients: &[usize], param: &Param) -> Option<Vec<Vec<u64>>> {
+	/*
+		combined[j] = sum of r_i * corrections[i][j] over the group, fresh r_i in [1, P)
+		Only share holders j that sent something for every client in the group are kept,
+		None if too few of them are left to reconstruct
+	*/
+	let M = corrections[clients[0]].len();
+	let P = param.P as u128;
+	let holders: Vec<usize> = (0..M)
+		.filter(|&j| clients.iter().all(|&i| !corrections[i][j].is_empty()))
+		.collect();
+	if holders.len() < param.useD2 {
+		return None;
+	}
+	let width = corrections[clients[0]][holders[0]].len();
+	let mut combined = vec![Vec::new(); M];
+	for &j in holders.iter() {
+		combined[j] = vec![0u64; width];
+	}
+	for &i in clients {
+		let r = (OsRng.next_u64() as u128) % (P - 1) + 1;
+		for &j in holders.iter() {
+			if corrections[i][j].len() != width {
+				return None;
+			}
+			for k in 0..width {
+				let term = r * (corrections[i][j][k] as u128 % P) % P;
+				combined[j][k] = ((combined[j][k] as u128 + term) % P) as u64;
+			}
+		}
 	}
-	return true;
+	Some(combined)
 }
 
-pub fn test_suit(corrections: &Vec<Vec<u64>>, param: &Param) -> bool {
+pub fn find_cheaters(corrections: &[Vec<Vec<u64>>], clients: &[usize], param: &Param, list: &[Vec<u8>]) -> Vec<Blame> {
+	/*
+		Who to exclude after EC, by position in the client list
+		Dealers failing the tests are blamed for them, one whose shares
+		are off for more than T holders doesn't decode: Degree.
+		A holder off dealer i's polynomials means one of the two cheated,
+		i dealt it an inconsistent share or it lied in its corrections
+			off for more than T dealers		at most T clients are corrupt,
+											so it lied: Responses
+			otherwise						nobody is blamed, a lone holder
+											can't tell on an honest dealer
+		Decoding already corrected those T holders or fewer, and an
+		inconsistent share leaves the holder's aggregate off in AG.
+	*/
+	let checks = batch_test(corrections, clients, param);
 	let M = corrections.len();
-	let P = param.P as u128;
-	let R3 = param.useR3 as u128;
+	let mut dealers = vec![Vec::new(); M];
+	for (i, check) in checks.iter() {
+		for &k in check.off.iter() {
+			dealers[k].push(*i);
+		}
+	}
+	let liars: Vec<usize> = (0..M).filter(|&k| dealers[k].len() > param.T).collect();
+	let client = |i: usize| list.get(i).map(|c| String::from_utf8_lossy(c).to_string()).unwrap_or_default();
 
-	let mut pss = PackedSecretSharing::new(
-		P, param.useR2 as u128, R3,
-		param.useD2, param.useD3, 3*param.L, param.L, M
-	);
-	println!("EC reconstruct recvShares:{} > 2*d2:{:?}, d3:{}", M, param.useD2, param.useD3);
-	let mut evalPoints = Vec::new();
-	let mut corrections_remove_empty = Vec::new();
-	for j in 0..M {
-		if corrections[j].len() != 0 {
-	    	evalPoints.push(R3.modpow((j+1) as u128, P) as u64);
-			corrections_remove_empty.push(corrections[j].clone());
+	let mut blamed = Vec::new();
+	for (i, check) in checks {
+		let mut failed = check.failed_tests();
+		if liars.contains(&i) {
+			failed.push(EcTest::Responses);
+		}
+		if failed.is_empty() {
+			continue;
 		}
+		blamed.push(Blame {
+			index: i,
+			client: client(i),
+			failed: failed,
+			evidence: check,
+			dealers: match liars.contains(&i) {
+				true => dealers[i].clone(),
+				false => Vec::new(),
+			},
+		});
 	}
-	println!("remove_empty {:?}, sharesPoints {}", corrections_remove_empty.len(), evalPoints.len());
-	let result = pss.reconstruct2(&corrections_remove_empty, evalPoints.as_slice());
+	// Liars whose own shares were clean
+	for &k in liars.iter() {
+		if blamed.iter().any(|b| b.index == k) {
+			continue;
+		}
+		let evidence = match corrections[k].iter().any(|c| c.len() != 0) {
+			true => test_suit(&corrections[k], param),
+			false => EcCheck::default(),
+		};
+		blamed.push(Blame {
+			index: k,
+			client: client(k),
+			failed: vec![EcTest::Responses],
+			evidence: evidence,
+			dealers: dealers[k].clone(),
+		});
+	}
+	blamed.sort_by_key(|b| b.index);
+	blamed
+}
+
+pub fn check_aggregates(shares: &[Vec<u64>], param: &Param, V: usize) -> Option<Vec<usize>> {
 	/*
-		Todo: check Degree test in result[0..param.L] 
-			  degree has to be D2
+		Aggregated shares are points on one polynomial of degree < D2 per block
+		Decode a random linear combination of the blocks allowing T points off,
+		returns the clients whose shares are off (a wrong length is off too),
+		None if more than T are
 	*/
-	let mut sum = 0;
-	for i in 2*param.L..3*param.L {
-		sum = (sum + result[i]) % param.P;
+	let P = param.P as u128;
+	let R3 = param.useR3 as u128;
+	let B = V / param.L;
+	let rand: Vec<u128> = (0..B).map(|_| (OsRng.next_u64() as u128) % P).collect();
+	let mut points = Vec::new();
+	let mut values = Vec::new();
+	let mut holders = Vec::new();
+	let mut off = Vec::new();
+	for (j, s) in shares.iter().enumerate() {
+		if s.is_empty() {
+			continue;
+		}
+		if s.len() != B {
+			off.push(j);
+			continue;
+		}
+		let mut v = 0u128;
+		for b in 0..B {
+			v = v.add_mod((s[b] as u128 % P).mul_mod(rand[b], P), P);
+		}
+		points.push(R3.modpow((j+1) as u128, P));
+		values.push(v);
+		holders.push(j);
+	}
+	let poly = decode(&points, &values, param.useD2, param.T, P)?;
+	for (i, &j) in holders.iter().enumerate() {
+		if eval(&poly, points[i], P) != values[i] {
+			off.push(j);
+		}
+	}
+	if off.len() > param.T {
+		return None;
 	}
-	println!("EC result (middle section should be 0s): {:?} \n sum (should be 0): {:?}", result, sum);
+	off.sort();
+	debug!(off = ?off, "aggregated shares checked");
+	Some(off)
+}
+
+pub fn decode(points: &[u128], values: &[u128], k: usize, errors: usize, P: u128) -> Option<Vec<u128>> {
 	/*
-		Input Bit Test, Quadratic test, L2-norm bit test
-		secrets should be 0
+		Berlekamp-Welch
+		values should be a polynomial with k coefficients at points,
+		up to `errors` of them may be off (fewer if there are too few points to tell).
+		Returns the polynomial, None if more are off
+		Find Q of degree < k + e and E monic of degree e with
+			Q(x_i) = y_i * E(x_i)	for every point
+		then the polynomial is Q / E, E vanishes where values are off
+	*/
+	let n = points.len();
+	if k == 0 || n < k {
+		return None;
+	}
+	let e = min(errors, (n - k) / 2);
+	/* One row per point, unknowns [Q_0 ... Q_k+e-1, E_0 ... E_e-1],
+	   E_e = 1 goes to the right hand side
 	*/
-	for i in param.L..2*param.L {
-		if result[i] != 0 {
-			return false;
+	let width = k + 2*e;
+	let mut rows = Vec::with_capacity(n);
+	for i in 0..n {
+		let x = points[i] % P;
+		let y = values[i] % P;
+		let mut row = vec![0u128; width + 1];
+		let mut xj = 1u128;
+		for j in 0..k+e {
+			row[j] = xj;
+			if j < e {
+				row[k+e+j] = 0u128.sub_mod(y.mul_mod(xj, P), P);
+			}
+			xj = xj.mul_mod(x, P);
 		}
+		row[width] = y.mul_mod(x.modpow(e as u128, P), P);
+		rows.push(row);
 	}
+	// Gauss-Jordan, free unknowns are left 0
+	let mut pivots = Vec::new();
+	let mut r = 0;
+	for c in 0..width {
+		if r == n {
+			break;
+		}
+		let p = match (r..n).find(|&i| rows[i][c] != 0) {
+			Some(p) => p,
+			None => continue,
+		};
+		rows.swap(r, p);
+		let inv = rows[r][c].modpow(P - 2, P);
+		for j in c..=width {
+			rows[r][j] = rows[r][j].mul_mod(inv, P);
+		}
+		for i in 0..n {
+			if i != r && rows[i][c] != 0 {
+				let f = rows[i][c];
+				for j in c..=width {
+					let t = f.mul_mod(rows[r][j], P);
+					rows[i][j] = rows[i][j].sub_mod(t, P);
+				}
+			}
+		}
+		pivots.push(c);
+		r += 1;
+	}
+	if rows[r..].iter().any(|row| row[width] != 0) {
+		return None;
+	}
+	let mut solution = vec![0u128; width];
+	for (i, &c) in pivots.iter().enumerate() {
+		solution[c] = rows[i][width];
+	}
+	// Q / E, the remainder must be 0
+	let mut q = solution[..k+e].to_vec();
+	let mut E = solution[k+e..].to_vec();
+	E.push(1);
+	let mut poly = vec![0u128; k];
+	for d in (0..k).rev() {
+		let c = q[d+e];
+		poly[d] = c;
+		for j in 0..=e {
+			q[d+j] = q[d+j].sub_mod(c.mul_mod(E[j], P), P);
+		}
+	}
+	if q.iter().any(|&c| c != 0) {
+		return None;
+	}
+	let off = (0..n).filter(|&i| eval(&poly, points[i], P) != values[i] % P).count();
+	if off > e {
+		return None;
+	}
+	Some(poly)
+}
+
+// Horner, coefficients lowest first
+pub fn eval(poly: &[u128], x: u128, P: u128) -> u128 {
+	poly.iter().rev().fold(0u128, |acc, c| acc.mul_mod(x, P).add_mod(*c, P))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
 	/*
-		Input Bound Test, L2-norm sum test, L2-norm bound test
-		secrets sums to 0
+		Corrections built straight from polynomials at the share points,
+		16 clients, corruption 1: D2 = 4, L = 2, T = 1
 	*/
-	if sum != 0 {
-		return false;
+	const M: usize = 16;
+
+	fn param() -> Param {
+		let mut param = Param::new(3073700804129980417, 1414118249734601779, 20, 308414859194273485, 15);
+		param.calculate_malicious(M, 32, 0, 1).unwrap();
+		param
+	}
+
+	fn point(param: &Param, j: usize) -> u128 {
+		(param.useR3 as u128).modpow((j+1) as u128, param.P as u128)
+	}
+
+	fn random_poly(k: usize, P: u128) -> Vec<u128> {
+		(0..k).map(|_| (OsRng.next_u64() as u128) % P).collect()
+	}
+
+	// random polynomial with k coefficients that is 0 at the L secret points
+	fn zero_at_secrets(param: &Param, k: usize) -> Vec<u128> {
+		let P = param.P as u128;
+		let mut poly = random_poly(k - param.L, P);
+		for s in 0..param.L {
+			let r = (param.useR2 as u128).modpow(s as u128, P);
+			// poly * (x - r)
+			let mut next = vec![0u128; poly.len() + 1];
+			for (d, c) in poly.iter().enumerate() {
+				next[d+1] = next[d+1].add_mod(*c, P);
+				next[d] = next[d].sub_mod(c.mul_mod(r, P), P);
+			}
+			poly = next;
+		}
+		poly
+	}
+
+	// one dealer's rows, [DT, sumA, sumB] for every holder
+	fn honest_rows(param: &Param) -> Vec<Vec<u64>> {
+		let P = param.P as u128;
+		let k = 2*param.useD2 - 1;
+		let polys = vec![random_poly(param.useD2, P), zero_at_secrets(param, k), zero_at_secrets(param, k)];
+		(0..M).map(|j| polys.iter().map(|f| eval(f, point(param, j), P) as u64).collect()).collect()
+	}
+
+	fn bump(x: &mut u64, P: u64) {
+		*x = (*x as u128).add_mod(1, P as u128) as u64;
+	}
+
+	#[test]
+	fn decode_corrects_up_to_t() {
+		let param = param();
+		let P = param.P as u128;
+		let poly = random_poly(param.useD2, P);
+		let points: Vec<u128> = (0..M).map(|j| point(&param, j)).collect();
+		let mut values: Vec<u128> = points.iter().map(|x| eval(&poly, *x, P)).collect();
+		assert_eq!(decode(&points, &values, param.useD2, param.T, P), Some(poly.clone()));
+		values[3] = values[3].add_mod(1, P);
+		assert_eq!(decode(&points, &values, param.useD2, param.T, P), Some(poly));
+		values[9] = values[9].add_mod(1, P);
+		assert_eq!(decode(&points, &values, param.useD2, param.T, P), None);
+	}
+
+	#[test]
+	fn honest_dealer_is_clean() {
+		let param = param();
+		let check = test_suit(&honest_rows(&param), &param);
+		assert!(check.clean(), "{:?}", check);
+		assert_eq!(check.holders.len(), M);
+	}
+
+	#[test]
+	fn one_holder_off_is_corrected() {
+		let param = param();
+		let mut rows = honest_rows(&param);
+		bump(&mut rows[5][0], param.P);
+		let check = test_suit(&rows, &param);
+		assert!(check.passed() && !check.clean());
+		assert_eq!(check.off, vec![5]);
+	}
+
+	#[test]
+	fn more_than_t_holders_off_fail_degree() {
+		let param = param();
+		let mut rows = honest_rows(&param);
+		bump(&mut rows[2][0], param.P);
+		bump(&mut rows[7][0], param.P);
+		let check = test_suit(&rows, &param);
+		assert!(check.undecodable);
+		assert_eq!(check.failed_tests(), vec![EcTest::Degree]);
+	}
+
+	#[test]
+	fn nonzero_secret_fails_bit_test() {
+		let param = param();
+		let P = param.P as u128;
+		let f = random_poly(2*param.useD2 - 1, P);
+		let mut rows = honest_rows(&param);
+		for j in 0..M {
+			rows[j][1] = eval(&f, point(&param, j), P) as u64;
+		}
+		let check = test_suit(&rows, &param);
+		assert!(check.failed_tests().contains(&EcTest::BitAndQuadratic));
+	}
+
+	#[test]
+	fn find_cheaters_blames_dealers_and_liars() {
+		let param = param();
+		let list: Vec<Vec<u8>> = (0..M).map(|i| format!("Client {}", i).into_bytes()).collect();
+		let mut corrections: Vec<Vec<Vec<u64>>> = (0..M).map(|_| honest_rows(&param)).collect();
+		let clients: Vec<usize> = (0..M).collect();
+		assert!(find_cheaters(&corrections, &clients, &param, &list).is_empty());
+		// one holder off for one dealer: corrected, nobody blamed
+		bump(&mut corrections[4][11][0], param.P);
+		assert!(find_cheaters(&corrections, &clients, &param, &list).is_empty());
+		// dealer 6 off for two holders, holder 13 off for every dealer
+		bump(&mut corrections[6][0][0], param.P);
+		bump(&mut corrections[6][1][0], param.P);
+		for i in 0..M {
+			bump(&mut corrections[i][13][2], param.P);
+		}
+		let blamed = find_cheaters(&corrections, &clients, &param, &list);
+		let indices: Vec<usize> = blamed.iter().map(|b| b.index).collect();
+		assert_eq!(indices, vec![6, 13]);
+		assert!(blamed[0].failed.contains(&EcTest::Degree));
+		assert_eq!(blamed[1].failed, vec![EcTest::Responses]);
+		assert_eq!(blamed[1].client, "Client 13");
+	}
+
+	#[test]
+	fn aggregates_off_the_polynomial() {
+		let param = param();
+		let P = param.P as u128;
+		let V = 32;
+		let B = V / param.L;
+		let blocks: Vec<Vec<u128>> = (0..B).map(|_| random_poly(param.useD2, P)).collect();
+		let mut shares: Vec<Vec<u64>> = (0..M)
+			.map(|j| blocks.iter().map(|f| eval(f, point(&param, j), P) as u64).collect())
+			.collect();
+		shares[8] = Vec::new();
+		assert_eq!(check_aggregates(&shares, &param, V), Some(Vec::new()));
+		bump(&mut shares[3][1], param.P);
+		assert_eq!(check_aggregates(&shares, &param, V), Some(vec![3]));
+		shares[12].pop();
+		assert_eq!(check_aggregates(&shares, &param, V), None);
 	}
-	println!("EC true");
-	return true;
 }
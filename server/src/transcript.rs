@@ -0,0 +1,208 @@
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::*;
+
+use serde::{Serialize, Deserialize};
+use sha2::{Sha256, Digest};
+
+use crate::param::Param;
+use crate::state::State;
+use crate::worker::ServerError;
+
+/*
+	Session transcript
+	Append-only, one JSON Record per line:
+		Setup		client list and sharing params, written when KE exits
+		Broadcast	every frame the session published, signature first
+		Message		every client message as it arrived, IS shares still
+					encrypted as they were relayed, with the handler's verdict
+	Frames and client IDs are hex.
+	Each record is chained to the one before it,
+		hash = SHA-256(prev | index u64 le | entry JSON)
+	the first prev is 64 zeros. Replayed by the audit tool.
+*/
+
+pub const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Entry {
+	Setup {
+		maxClients: usize,			// rows of the EC matrix
+		vectorSize: usize,
+		malicious: bool,
+		param: Param,
+		clients: Vec<String>,		// client list order, indices used everywhere else
+	},
+	Broadcast {
+		topic: String,
+		seq: usize,
+		frames: Vec<String>,		// [signature, data...]
+	},
+	Message {
+		client: String,
+		round: usize,
+		state: State,				// state the server was in when handling it
+		frames: Vec<String>,		// payload, after session and round
+		accepted: bool,
+	},
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Record {
+	pub index: u64,
+	pub prev: String,
+	pub hash: String,
+	pub entry: Entry,
+}
+
+impl Record {
+	pub fn hash(prev: &str, index: u64, entry: &Entry) -> Result<String, ServerError> {
+		let json = serde_json::to_vec(entry).map_err(|_| ServerError::TranscriptFail(0))?;
+		let mut hasher = Sha256::new();
+		hasher.update(prev.as_bytes());
+		hasher.update(&index.to_le_bytes());
+		hasher.update(&json);
+		Ok(hex::encode(hasher.finalize()))
+	}
+
+	// Hash matches the entry and chains to prev
+	pub fn verify(&self, prev: &str) -> bool {
+		self.prev == prev && Record::hash(prev, self.index, &self.entry).ok().as_deref() == Some(self.hash.as_str())
+	}
+}
+
+struct Tail {
+	file: fs::File,
+	index: u64,				// of the next record
+	last: String,			// hash of the last record
+}
+
+pub struct Transcript {
+	tail: Mutex<Tail>,
+}
+
+impl Transcript {
+
+	pub fn open(path: &Path) -> Result<Transcript, ServerError> {
+	/*
+		Append to path, an existing transcript (resumed session)
+		is checked and its chain carried on
+	*/
+		let (index, last) = match path.exists() {
+			true => {
+				let records = read(path)?;
+				let mut last = GENESIS.to_string();
+				for r in records.iter() {
+					if !r.verify(&last) {
+						return Err(ServerError::TranscriptFail(3));
+					}
+					last = r.hash.clone();
+				}
+				(records.len() as u64, last)
+			},
+			false => (0, GENESIS.to_string()),
+		};
+		let file = fs::OpenOptions::new()
+			.create(true)
+			.append(true)
+			.open(path)
+			.map_err(|_| ServerError::TranscriptFail(1))?;
+		Ok(Transcript {
+			tail: Mutex::new(Tail {
+				file: file,
+				index: index,
+				last: last,
+			}),
+		})
+	}
+
+	pub fn append(&self, entry: Entry) -> Result<(), ServerError> {
+		let mut tail = self.tail.lock().map_err(|_| ServerError::MutexLockFail(0))?;
+		let hash = Record::hash(&tail.last, tail.index, &entry)?;
+		let record = Record {
+			index: tail.index,
+			prev: tail.last.clone(),
+			hash: hash.clone(),
+			entry: entry,
+		};
+		let mut line = serde_json::to_vec(&record).map_err(|_| ServerError::TranscriptFail(0))?;
+		line.push(b'\n');
+		tail.file.write_all(&line).map_err(|_| ServerError::TranscriptFail(2))?;
+		tail.index += 1;
+		tail.last = hash;
+		Ok(())
+	}
+}
+
+pub fn read(path: &Path) -> Result<Vec<Record>, ServerError> {
+	let file = fs::File::open(path).map_err(|_| ServerError::TranscriptFail(1))?;
+	let mut records = Vec::new();
+	for line in BufReader::new(file).lines() {
+		let line = line.map_err(|_| ServerError::TranscriptFail(1))?;
+		if line.trim().is_empty() {
+			continue;
+		}
+		records.push(serde_json::from_str(&line).map_err(|_| ServerError::TranscriptFail(4))?);
+	}
+	Ok(records)
+}
+
+pub fn hex_frames(frames: &[Vec<u8>]) -> Vec<String> {
+	frames.iter().map(hex::encode).collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn entries() -> Vec<Entry> {
+		vec![
+			Entry::Broadcast { topic: "tr/KE".to_string(), seq: 0, frames: hex_frames(&[vec![1u8], vec![2u8]]) },
+			Entry::Message {
+				client: hex::encode(b"c0"), round: 0, state: State::InputSharing,
+				frames: hex_frames(&[vec![3u8; 4]]), accepted: true,
+			},
+		]
+	}
+
+	#[test]
+	fn append_read_and_resume() {
+		let path = std::env::temp_dir().join(format!("transcript-{}.jsonl", std::process::id()));
+		let _ = fs::remove_file(&path);
+		let t = Transcript::open(&path).unwrap();
+		for e in entries() {
+			t.append(e).unwrap();
+		}
+		drop(t);
+		// a resumed session carries the chain on
+		let t = Transcript::open(&path).unwrap();
+		t.append(entries().swap_remove(0)).unwrap();
+		drop(t);
+		let records = read(&path).unwrap();
+		fs::remove_file(&path).unwrap();
+
+		assert_eq!(records.len(), 3);
+		let mut last = GENESIS.to_string();
+		for (i, r) in records.iter().enumerate() {
+			assert_eq!(r.index, i as u64);
+			assert!(r.verify(&last));
+			last = r.hash.clone();
+		}
+	}
+
+	#[test]
+	fn open_refuses_tampered_chain() {
+		let path = std::env::temp_dir().join(format!("transcript-bad-{}.jsonl", std::process::id()));
+		let _ = fs::remove_file(&path);
+		let t = Transcript::open(&path).unwrap();
+		for e in entries() {
+			t.append(e).unwrap();
+		}
+		drop(t);
+		let text = fs::read_to_string(&path).unwrap().replace("\"accepted\":true", "\"accepted\":false");
+		fs::write(&path, text).unwrap();
+		assert!(matches!(Transcript::open(&path), Err(ServerError::TranscriptFail(3))));
+		fs::remove_file(&path).unwrap();
+	}
+}
@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::fmt;
 use std::str;
 use std::{thread, time};
 use std::sync::*;
@@ -7,6 +8,9 @@ use std::time::Duration;
 
 use p256::ecdsa::VerifyKey;
 use crate::Profile;
+use crate::state::State;
+use transport::Outgoing;
+use tracing::debug;
 
 #[derive(Debug)]
 pub enum WorkerError {
@@ -19,6 +23,9 @@ pub enum WorkerError {
 	UnknownState(usize),
 	WrongState(usize),
 	SharingFail(usize),
+	WrongRound(usize),
+	NotEnrolled(usize),
+	Replayed(usize),
 }
 
 #[derive(Debug)]
@@ -31,59 +38,60 @@ pub enum ServerError {
 	UnknownState(usize),
 	TimerFail(usize),
 	ThreadSenderFail(usize),
+	InvalidSession(usize),
+	SessionExisted(usize),
+	CheckpointFail(usize),
+	OutputFail(usize),
+	InvalidKey(usize),
+	MetricsFail(usize),
+	BlameFail(usize),
+	TranscriptFail(usize),
+	TransportFail(usize),
+	Aborted(Abort),
 }
 
+/*
+	Why a session stopped short,
+	sent to clients on "<session>/AB-<round>"
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Abort {
+	NoClients,
+	NoParams { clients: usize },						// no sharing params for this many
+	TooFewClients { state: State, have: usize, need: usize },		// can't reconstruct
+	TooFewContributors { state: State, have: usize, need: usize },	// sum would expose inputs
+	Operator,											// AdminCommand::Abort
+}
+
+impl fmt::Display for Abort {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Abort::NoClients => write!(f, "Abort: no client joined"),
+			Abort::NoParams { clients } =>
+				write!(f, "Abort: no sharing parameters for {} clients", clients),
+			Abort::TooFewClients { state, have, need } =>
+				write!(f, "Abort: {} clients left after {:?}, reconstruction needs {}", have, state, need),
+			Abort::TooFewContributors { state, have, need } =>
+				write!(f, "Abort: {} contributors left after {:?}, at least {} required", have, state, need),
+			Abort::Operator => write!(f, "Abort: stopped by the operator"),
+		}
+	}
+}
+
+/*
+	A handler's reply endpoint,
+	the transport routes replies by identity
+*/
 pub struct Worker {
 	pub ID: String,
-	pub dealer : zmq::Socket,
-	pub threadSender: mpsc::Sender<usize>,
+	pub reply: Box<dyn Outgoing>,
 }
 
 impl Worker {
-	pub fn new(ID: &str, 
-		context: zmq::Context, threadSender: mpsc::Sender<usize>) -> Worker {
-		let dealer = context.socket(zmq::DEALER).unwrap();
-		dealer.set_identity(ID.as_bytes());
-		assert!(dealer.connect("inproc://backend").is_ok());
+	pub fn new(ID: &str, reply: Box<dyn Outgoing>) -> Worker {
 		Worker {
 			ID: ID.to_string(),
-			dealer: dealer,
-			threadSender: threadSender,
-		}
-	}
-}
-
-pub fn timer_task(receiver: mpsc::Receiver<usize>, timesUp: Arc<RwLock<bool>>) -> Result<usize, ServerError> {
-	let mut T = 0;
-	loop {
-		if T == 0  {
-			T = match receiver.recv() {
-				Ok(t) => t,
-				Err(_) => {println!("timer recv"); return Err(ServerError::TimerFail(0))},
-			};
-			println!("Start {}", T);
-		} else {
-			let mut i = 0;
-			while i < T/10 {
-				match receiver.try_recv() {
-					Ok(t) => {
-						T = t;
-						println!("Restart {}", T);
-						break
-					},
-					Err(e) => {
-						thread::sleep(Duration::from_millis(10));
-						i += 1
-					},
-				};
-			}
-			if i == T/10 {
-				T = 0;
-				match timesUp.write() {
-					Ok(mut guard) => *guard = true,
-					Err(_) => (),
-				};
-			}
+			reply: reply,
 		}
 	}
 }
@@ -120,7 +128,7 @@ pub fn format_clientData(profiles: &mut HashMap<Vec<u8>, Profile>,
         	},
         }
     }
-    println!("dropouts {:?}", dropouts);
+    debug!(field = field, dropouts = ?dropouts, "format_clientData");
    	for i in dropouts {
    		let key = list.remove(i);
    		profiles.remove(&key);
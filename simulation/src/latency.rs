@@ -0,0 +1,137 @@
+use std::sync::*;
+use std::thread::sleep;
+use std::time::Duration;
+
+use rand::Rng;
+use rand_chacha::ChaCha8Rng;
+use transport::*;
+
+use crate::scenario::Latency;
+
+/*
+	A client's view of the transport with a delay on its link
+	Messages it sends and broadcasts it receives each wait
+	a draw from its Latency first, in order, like a slow FIFO link.
+	Each direction draws from its own seeded stream
+	so thread timing can't reorder the draws.
+	Catch-up requests go through undelayed.
+	Server ends are passed straight to the inner transport.
+*/
+
+struct Delay {
+	latency: Latency,
+	rng: Mutex<ChaCha8Rng>,
+}
+
+impl Delay {
+	fn new(latency: Latency, rng: ChaCha8Rng) -> Delay {
+		Delay {
+			latency: latency,
+			rng: Mutex::new(rng),
+		}
+	}
+
+	fn wait(&self) {
+		if self.latency.max_ms == 0 {
+			return;
+		}
+		let ms = match self.rng.lock() {
+			Ok(mut rng) => rng.gen_range(self.latency.min_ms, self.latency.max_ms + 1),
+			Err(_) => self.latency.max_ms,
+		};
+		sleep(Duration::from_millis(ms));
+	}
+}
+
+pub struct Delayed {
+	inner: Arc<dyn Transport>,
+	latency: Latency,
+	sent: ChaCha8Rng,				// draws for messages to the server
+	received: ChaCha8Rng,			// draws for broadcasts
+}
+
+impl Delayed {
+	pub fn new(inner: Arc<dyn Transport>, latency: Latency,
+		sent: ChaCha8Rng, received: ChaCha8Rng) -> Delayed {
+		Delayed {
+			inner: inner,
+			latency: latency,
+			sent: sent,
+			received: received,
+		}
+	}
+}
+
+struct DelayedConnection {
+	inner: Box<dyn Connection>,
+	delay: Delay,
+}
+
+impl Outgoing for DelayedConnection {
+	fn send(&self, frames: Frames) -> Result<(), TransportError> {
+		self.delay.wait();
+		self.inner.send(frames)
+	}
+}
+
+impl Incoming for DelayedConnection {
+	fn recv(&self, timeout: Option<Duration>) -> Result<Option<Frames>, TransportError> {
+		self.inner.recv(timeout)
+	}
+}
+
+struct DelayedIncoming {
+	inner: Box<dyn Incoming>,
+	delay: Delay,
+}
+
+impl Incoming for DelayedIncoming {
+	fn recv(&self, timeout: Option<Duration>) -> Result<Option<Frames>, TransportError> {
+		let frames = self.inner.recv(timeout)?;
+		if frames.is_some() {
+			self.delay.wait();
+		}
+		Ok(frames)
+	}
+}
+
+impl Transport for Delayed {
+
+	fn listen(&self) -> Result<Box<dyn Incoming>, TransportError> {
+		self.inner.listen()
+	}
+
+	fn replier(&self) -> Result<Box<dyn Outgoing>, TransportError> {
+		self.inner.replier()
+	}
+
+	fn publisher(&self) -> Result<Box<dyn Outgoing>, TransportError> {
+		self.inner.publisher()
+	}
+
+	fn relay(&self) -> Result<(), TransportError> {
+		self.inner.relay()
+	}
+
+	fn responder(&self) -> Result<Box<dyn Connection>, TransportError> {
+		self.inner.responder()
+	}
+
+	fn connect(&self, ID: &[u8]) -> Result<Box<dyn Connection>, TransportError> {
+		Ok(Box::new(DelayedConnection {
+			inner: self.inner.connect(ID)?,
+			delay: Delay::new(self.latency, self.sent.clone()),
+		}))
+	}
+
+	fn subscribe(&self) -> Result<Box<dyn Incoming>, TransportError> {
+		Ok(Box::new(DelayedIncoming {
+			inner: self.inner.subscribe()?,
+			delay: Delay::new(self.latency, self.received.clone()),
+		}))
+	}
+
+	fn requester(&self) -> Result<Box<dyn Connection>, TransportError> {
+		self.inner.requester()
+	}
+}
@@ -0,0 +1,348 @@
+#![allow(non_snake_case)]
+
+use std::fmt;
+use std::sync::*;
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::{Serialize, Deserialize};
+use tracing::{info, warn};
+use transport::{Transport, MemoryTransport};
+use server::{Server, RoundResult, DEFAULT_SESSION};
+use server::blame::BlameReport;
+use client::Client;
+
+pub mod scenario;
+pub mod latency;
+
+pub use scenario::{Scenario, Phase, Dropout, Latency, Slow, Cheat, Stream, SimOpt};
+pub use client::adversary::Behaviour;
+use latency::Delayed;
+
+/*
+	Server and clients in one process
+	One MemoryTransport carries everything, the server runs its usual
+	tasks on it and each client is a thread driving the stock Client.
+	Inputs, dropouts and latencies come from the Scenario, so a run
+	can be repeated and its sum checked against the inputs
+	of the clients that stayed long enough to count.
+	Cheating clients run one of the client's adversary behaviours
+	and have to show up in what the server excluded.
+
+	The server's I/O threads block on the transport for good,
+	they're left behind when run returns.
+*/
+
+#[derive(Debug)]
+pub enum SimError {
+	Invalid(String),		// scenario or session rejected
+	Setup(String),			// server couldn't start
+}
+
+impl fmt::Display for SimError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			SimError::Invalid(s) => write!(f, "invalid scenario: {}", s),
+			SimError::Setup(s) => write!(f, "cannot start simulation: {}", s),
+		}
+	}
+}
+
+// What the report says went wrong
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Mismatch {
+	Aborted { reason: String },
+	NoResult { round: usize },
+	Sum { round: usize, expected: Vec<u64>, published: Vec<u64> },
+	Contributors { round: usize, expected: usize, published: usize },
+	ClientSum { round: usize, client: usize },		// a finishing client got another sum
+	ClientFailed { client: usize, error: String },	// an honest client that kept going didn't finish
+	NotExcluded { round: usize, client: usize },	// a cheater the server let through
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientOutcome {
+	pub index: usize,
+	pub ID: String,
+	pub behaviour: Behaviour,
+	pub position: Option<usize>,			// in the server's client list, dropouts count by it
+	pub dropped: Option<Dropout>,			// where the script stopped it
+	pub error: Option<String>,				// where the protocol stopped it
+	pub sums: Vec<Option<Vec<u64>>>,		// RS of every round it reached
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoundReport {
+	pub round: usize,
+	pub contributors: Vec<usize>,			// clients whose inputs should be in the sum
+	pub expected: Vec<u64>,					// their inputs summed mod P
+	pub result: Option<RoundResult>,		// what the server published
+	pub blame: Option<BlameReport>,			// malicious rounds, who EC excluded and why
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Report {
+	pub seed: u64,
+	pub elapse_ms: u64,
+	pub aborted: Option<String>,
+	pub rounds: Vec<RoundReport>,
+	pub clients: Vec<ClientOutcome>,
+}
+
+impl Report {
+
+	pub fn mismatches(&self) -> Vec<Mismatch> {
+	/*
+		Every round must be published with the expected sum and count,
+		every client the script didn't stop must finish with it,
+		every cheater must be in the dropouts, or among the rejected
+		aggregated shares for a bad aggregate
+	*/
+		let mut mismatches = Vec::new();
+		if let Some(reason) = &self.aborted {
+			mismatches.push(Mismatch::Aborted { reason: reason.clone() });
+		}
+		for r in self.rounds.iter() {
+			let published = match &r.result {
+				Some(res) if !res.sum.is_empty() => &res.sum,
+				_ => {
+					mismatches.push(Mismatch::NoResult { round: r.round });
+					continue
+				},
+			};
+			if !published.starts_with(&r.expected) {
+				mismatches.push(Mismatch::Sum {
+					round: r.round, expected: r.expected.clone(), published: published.clone(),
+				});
+			}
+			for c in self.clients.iter() {
+				if let Some(Some(sum)) = c.sums.get(r.round) {
+					if sum != published {
+						mismatches.push(Mismatch::ClientSum { round: r.round, client: c.index });
+					}
+				}
+			}
+			let res = r.result.as_ref().unwrap();
+			if res.contributors != r.contributors.len() {
+				mismatches.push(Mismatch::Contributors {
+					round: r.round, expected: r.contributors.len(), published: res.contributors,
+				});
+			}
+			for c in self.clients.iter().filter(|c| !c.behaviour.is_honest()) {
+				let excluded = match c.behaviour {
+					Behaviour::BadAggregate => &res.rejected,
+					// refused on its signature, a stale aggregate would show in the sum
+					Behaviour::ReplayAggregate => continue,
+					_ => &res.dropouts,
+				};
+				if c.position.map_or(true, |p| !excluded.contains(&p)) {
+					mismatches.push(Mismatch::NotExcluded { round: r.round, client: c.index });
+				}
+			}
+		}
+		for c in self.clients.iter().filter(|c| c.behaviour.is_honest()) {
+			if let (None, Some(e)) = (&c.dropped, &c.error) {
+				mismatches.push(Mismatch::ClientFailed { client: c.index, error: e.clone() });
+			}
+		}
+		mismatches
+	}
+
+	pub fn passed(&self) -> bool {
+		self.mismatches().is_empty()
+	}
+}
+
+pub fn run(scenario: &Scenario) -> Result<Report, SimError> {
+	let BENCH_TIMER = Instant::now();
+	scenario.validate().map_err(|e| SimError::Invalid(e.to_string()))?;
+	let cfg = scenario.server_config();
+	let session = cfg.build_session().map_err(|e| SimError::Invalid(e.to_string()))?;
+	let P = cfg.field.prime;
+
+	let transport: Arc<dyn Transport> = Arc::new(MemoryTransport::new());
+	let runtime = tokio::runtime::Builder::new_multi_thread()
+		.max_blocking_threads(scenario.workers)
+		.thread_name("simulation")
+		.enable_time()
+		.build()
+		.map_err(|e| SimError::Setup(e.to_string()))?;
+	let server = Arc::new(Server::new());
+	let session = server.add_session(session).map_err(|e| SimError::Setup(format!("{:?}", e)))?;
+	/*
+		Same tasks as the server binary,
+		memory needs no publisher relay
+	*/
+	let t = transport.clone();
+	let svr = server.clone();
+	thread::spawn(move || svr.server_task(&*t));
+	let t = transport.clone();
+	let svr = server.clone();
+	thread::spawn(move || svr.catchup_task(&*t));
+	runtime.spawn(server.clone().dispatch_task(transport.clone(), scenario.workers));
+	let t = transport.clone();
+	let sess = session.clone();
+	let stateTask = runtime.spawn(async move {
+		sess.state_task(&*t).await
+	});
+	/*
+		One thread per client, each behind its own delayed link
+		Outcomes come back on a channel, a client that never
+		reports is counted as failed
+	*/
+	let (tx, rx) = channel();
+	for i in 0..scenario.clients {
+		let link: Arc<dyn Transport> = Arc::new(Delayed::new(transport.clone(), scenario.latency(i),
+			scenario.rng(i, Stream::Sent), scenario.rng(i, Stream::Received)));
+		let s = scenario.clone();
+		let tx = tx.clone();
+		thread::spawn(move || {
+			let _ = tx.send(run_client(&s, i, link));
+		});
+	}
+	drop(tx);
+
+	let aborted = match runtime.block_on(stateTask) {
+		Ok(Ok(_)) => None,
+		Ok(Err(e)) => Some(format!("{:?}", e)),
+		Err(e) => Some(e.to_string()),
+	};
+	/*
+		The session is done, clients only have the last
+		broadcast left to read
+	*/
+	let deadline = Instant::now() + Duration::from_millis(scenario.session_time as u64);
+	let mut clients: Vec<ClientOutcome> = (0..scenario.clients).map(|i| ClientOutcome {
+		index: i,
+		ID: Scenario::client_id(i),
+		behaviour: scenario.behaviour(i),
+		position: None,
+		dropped: None,
+		error: Some("no answer".to_string()),
+		sums: Vec::new(),
+	}).collect();
+	while let Some(wait) = deadline.checked_duration_since(Instant::now()) {
+		match rx.recv_timeout(wait) {
+			Ok(o) => {
+				let i = o.index;
+				clients[i] = o;
+			},
+			Err(_) => break,
+		}
+	}
+	runtime.shutdown_background();
+
+	let rounds = (0..scenario.rounds).map(|r| {
+		let contributors: Vec<usize> = (0..scenario.clients)
+			.filter(|&i| scenario.contributes(i, r))
+			.collect();
+		let mut expected = vec![0u64; scenario.vector_len];
+		for &i in contributors.iter() {
+			for (e, x) in expected.iter_mut().zip(scenario.inputs(i)[r].iter()) {
+				*e = ((*e as u128 + *x as u128) % P as u128) as u64;
+			}
+		}
+		RoundReport {
+			round: r,
+			contributors: contributors,
+			expected: expected,
+			result: session.result(r),
+			blame: session.blame_report(r),
+		}
+	}).collect();
+
+	let report = Report {
+		seed: scenario.seed,
+		elapse_ms: BENCH_TIMER.elapsed().as_millis() as u64,
+		aborted: aborted,
+		rounds: rounds,
+		clients: clients,
+	};
+	info!(seed = report.seed, elapse_ms = report.elapse_ms, passed = report.passed(), "simulation done");
+	Ok(report)
+}
+
+fn run_client(scenario: &Scenario, index: usize, transport: Arc<dyn Transport>) -> ClientOutcome {
+/*
+	Stock client steps as in client/src/main.rs,
+	stopping or cheating where the script says
+*/
+	let ID = Scenario::client_id(index);
+	let dropout = scenario.dropout(index);
+	let inputs = scenario.inputs(index);
+	let mut outcome = ClientOutcome {
+		index: index,
+		ID: ID.clone(),
+		behaviour: scenario.behaviour(index),
+		position: None,
+		dropped: None,
+		error: None,
+		sums: Vec::new(),
+	};
+	let stops = |round: usize, phase: Phase| match dropout {
+		Some(d) if d.round == round && d.phase == phase => {
+			info!(client = index, round, phase = %phase, "scripted dropout");
+			true
+		},
+		_ => false,
+	};
+	macro_rules! step {
+		($call:expr) => {
+			if let Err(e) = $call {
+				warn!(client = index, error = ?e, "client stopped");
+				outcome.error = Some(format!("{:?}", e));
+				return outcome;
+			}
+		};
+	}
+
+	let mut client = Client::with_transport(&ID, scenario.vector_len, scenario.input_bit_limit, transport);
+	client.set_behaviour(outcome.behaviour);
+	if stops(0, Phase::Handshake) {
+		outcome.dropped = dropout;
+		return outcome;
+	}
+	step!(client.handshake(DEFAULT_SESSION));
+	if stops(0, Phase::KeyExchange) {
+		outcome.dropped = dropout;
+		return outcome;
+	}
+	step!(client.key_exchange());
+	outcome.position = client.index();
+	loop {
+		let round = client.round();
+		let span = client.span();
+		let _entered = span.enter();
+		if stops(round, Phase::InputSharing) {
+			outcome.dropped = dropout;
+			return outcome;
+		}
+		let mut input = inputs[round].clone();
+		match scenario.malicious {
+			true => {
+				step!(client.input_sharing_ml(&mut input));
+				step!(client.shares_recieving());
+				if stops(round, Phase::ErrorCorrection) {
+					outcome.dropped = dropout;
+					return outcome;
+				}
+				step!(client.error_correction());
+			},
+			false => {
+				step!(client.input_sharing_sh(&mut input));
+				step!(client.shares_recieving());
+			},
+		}
+		if stops(round, Phase::Aggregation) {
+			outcome.dropped = dropout;
+			return outcome;
+		}
+		step!(client.aggregation());
+		outcome.sums.push(client.aggregate_result().ok());
+		if !client.next_round() {
+			break;
+		}
+	}
+	outcome
+}
@@ -0,0 +1,81 @@
+#![allow(non_snake_case)]
+
+use std::process;
+
+use structopt::StructOpt;
+use server::logging::{self, LogFormat};
+use simulation::*;
+
+/*
+	Run a scenario and check the aggregate
+	of every round against the scripted inputs.
+
+	Exit code: 0 sums match, 1 mismatch, 2 invalid scenario
+*/
+fn main() {
+
+	let opt = SimOpt::from_args();
+	let scenario = match Scenario::from_opt(&opt) {
+		Ok(s) => s,
+		Err(e) => {
+			eprintln!("{}", e);
+			process::exit(2);
+		},
+	};
+	if opt.print_config {
+		print!("{}", scenario.to_toml());
+		if let Err(e) = scenario.validate() {
+			eprintln!("{}", e);
+			process::exit(2);
+		}
+		return;
+	}
+	logging::init(LogFormat::Text, &opt.log_level);
+
+	let report = match run(&scenario) {
+		Ok(r) => r,
+		Err(e) => {
+			eprintln!("{}", e);
+			process::exit(2);
+		},
+	};
+	let mismatches = report.mismatches();
+
+	if opt.json {
+		println!("{}", serde_json::to_string_pretty(&report).unwrap());
+	} else {
+		println!("seed {}, {} clients, {} ms", report.seed, report.clients.len(), report.elapse_ms);
+		for r in report.rounds.iter() {
+			let published = match &r.result {
+				Some(res) if res.rejected.is_empty() =>
+					format!("sum from {} clients, dropouts {:?}", res.contributors, res.dropouts),
+				Some(res) => format!("sum from {} clients, dropouts {:?}, rejected {:?}",
+					res.contributors, res.dropouts, res.rejected),
+				None => "no result".to_string(),
+			};
+			println!("round {}: expected {} contributors, server {}", r.round, r.contributors.len(), published);
+			if let Some(b) = &r.blame {
+				for e in b.excluded.iter() {
+					println!("  EC excluded {} ({}), failed {:?}", e.index, e.client, e.failed);
+				}
+			}
+		}
+		for c in report.clients.iter() {
+			if !c.behaviour.is_honest() {
+				println!("{} cheated with {}", c.ID, c.behaviour);
+			}
+			if let Some(d) = &c.dropped {
+				println!("{} dropped at {} in round {}", c.ID, d.phase, d.round);
+			}
+		}
+		for m in mismatches.iter() {
+			println!("MISMATCH {:?}", m);
+		}
+		if mismatches.is_empty() {
+			println!("OK");
+		}
+	}
+	if !mismatches.is_empty() {
+		process::exit(1);
+	}
+}
@@ -0,0 +1,496 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::{Serialize, Deserialize};
+use structopt::StructOpt;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use server::config::{ServerConfig, ConfigError};
+use client::adversary::Behaviour;
+
+/*
+	What one simulation runs
+	Same loading as the server config: an optional TOML / JSON file,
+	named flags on top, validated before anything starts.
+	Everything random (inputs, latencies) comes from the seed,
+	so a scenario replays the same way every time.
+*/
+
+/*
+	Steps of one client, in protocol order
+	Dropping at a step means the client never sends that step's
+	message and is gone from then on.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+	Handshake,
+	KeyExchange,
+	InputSharing,
+	ErrorCorrection,		// malicious only
+	Aggregation,
+}
+
+impl FromStr for Phase {
+	type Err = String;
+	fn from_str(s: &str) -> Result<Phase, String> {
+		match s {
+			"handshake" | "HS" => Ok(Phase::Handshake),
+			"key_exchange" | "KE" => Ok(Phase::KeyExchange),
+			"input_sharing" | "IS" => Ok(Phase::InputSharing),
+			"error_correction" | "EC" => Ok(Phase::ErrorCorrection),
+			"aggregation" | "AG" => Ok(Phase::Aggregation),
+			_ => Err(format!("unknown phase {}, use handshake, key_exchange, \
+				input_sharing, error_correction or aggregation", s)),
+		}
+	}
+}
+
+impl fmt::Display for Phase {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Phase::Handshake => write!(f, "handshake"),
+			Phase::KeyExchange => write!(f, "key_exchange"),
+			Phase::InputSharing => write!(f, "input_sharing"),
+			Phase::ErrorCorrection => write!(f, "error_correction"),
+			Phase::Aggregation => write!(f, "aggregation"),
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Dropout {
+	pub client: usize,				// index, the client's ID is "Client <index>"
+	#[serde(default)]
+	pub round: usize,
+	pub phase: Phase,
+}
+
+impl FromStr for Dropout {
+	type Err = String;
+	fn from_str(s: &str) -> Result<Dropout, String> {
+	/*
+		<client>@<phase>[:<round>], e.g. 3@input_sharing or 3@AG:1
+	*/
+		let (client, rest) = s.split_once('@')
+			.ok_or_else(|| format!("dropout {} must be <client>@<phase>[:<round>]", s))?;
+		let (phase, round) = match rest.split_once(':') {
+			Some((p, r)) => (p, r.parse().map_err(|_| format!("bad round in dropout {}", s))?),
+			None => (rest, 0),
+		};
+		Ok(Dropout {
+			client: client.parse().map_err(|_| format!("bad client in dropout {}", s))?,
+			round: round,
+			phase: phase.parse()?,
+		})
+	}
+}
+
+// One-way delay of every message, drawn uniformly from min_ms..=max_ms
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Latency {
+	pub min_ms: u64,
+	pub max_ms: u64,
+}
+
+impl FromStr for Latency {
+	type Err = String;
+	fn from_str(s: &str) -> Result<Latency, String> {
+	/*
+		<ms> or <min_ms>-<max_ms>
+	*/
+		let bad = || format!("latency {} must be <ms> or <min_ms>-<max_ms>", s);
+		match s.split_once('-') {
+			Some((min, max)) => Ok(Latency {
+				min_ms: min.parse().map_err(|_| bad())?,
+				max_ms: max.parse().map_err(|_| bad())?,
+			}),
+			None => {
+				let ms = s.parse().map_err(|_| bad())?;
+				Ok(Latency { min_ms: ms, max_ms: ms })
+			},
+		}
+	}
+}
+
+// Latency of one client instead of the scenario's
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Slow {
+	pub client: usize,
+	pub min_ms: u64,
+	pub max_ms: u64,
+}
+
+impl FromStr for Slow {
+	type Err = String;
+	fn from_str(s: &str) -> Result<Slow, String> {
+	/*
+		<client>=<latency>, e.g. 2=50-200
+	*/
+		let (client, latency) = s.split_once('=')
+			.ok_or_else(|| format!("slow client {} must be <client>=<latency>", s))?;
+		let latency: Latency = latency.parse()?;
+		Ok(Slow {
+			client: client.parse().map_err(|_| format!("bad client in {}", s))?,
+			min_ms: latency.min_ms,
+			max_ms: latency.max_ms,
+		})
+	}
+}
+
+// A client cheating with one of the adversary behaviours, every round
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Cheat {
+	pub client: usize,
+	pub behaviour: Behaviour,
+}
+
+impl FromStr for Cheat {
+	type Err = String;
+	fn from_str(s: &str) -> Result<Cheat, String> {
+	/*
+		<client>=<behaviour>, e.g. 4=wrong_bits
+	*/
+		let (client, behaviour) = s.split_once('=')
+			.ok_or_else(|| format!("cheat {} must be <client>=<behaviour>", s))?;
+		Ok(Cheat {
+			client: client.parse().map_err(|_| format!("bad client in cheat {}", s))?,
+			behaviour: behaviour.parse()?,
+		})
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+	Inputs = 0,
+	Sent = 1,				// latency of messages to the server
+	Received = 2,			// latency of broadcasts
+}
+
+const STREAMS: u64 = 3;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Scenario {
+	pub seed: u64,
+	pub clients: usize,
+	pub vector_len: usize,
+	pub dropouts: usize,					// D the session tolerates
+	pub malicious: bool,
+	pub corruption: Option<usize>,			// malicious only
+	pub input_bit_limit: Option<usize>,		// malicious only
+	pub rounds: usize,
+	pub session_time: usize,				// ms per state
+	pub is_session_time: usize,				// ms for input sharing
+	pub min_contributors: usize,
+	pub workers: usize,
+	pub latency: Latency,					// every client unless listed in slow
+	pub slow: Vec<Slow>,
+	pub drop: Vec<Dropout>,
+	pub cheat: Vec<Cheat>,					// malicious only, at most corruption of them
+}
+
+impl Default for Scenario {
+	fn default() -> Scenario {
+		Scenario {
+			seed: 0,
+			clients: 10,
+			vector_len: 16,
+			dropouts: 2,
+			malicious: false,
+			corruption: None,
+			input_bit_limit: None,
+			rounds: 1,
+			session_time: 2000,
+			is_session_time: 2000,
+			min_contributors: 2,
+			workers: 4,
+			latency: Latency::default(),
+			slow: Vec::new(),
+			drop: Vec::new(),
+			cheat: Vec::new(),
+		}
+	}
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "simulation", about = "Server and clients in one process, checks the aggregate")]
+pub struct SimOpt {
+	/// TOML or JSON scenario file, flags override it
+	#[structopt(short, long, parse(from_os_str))]
+	pub config: Option<PathBuf>,
+	/// Print the effective scenario and exit
+	#[structopt(long)]
+	pub print_config: bool,
+	/// Print the full report as JSON
+	#[structopt(long)]
+	pub json: bool,
+
+	/// Seed for inputs and latencies
+	#[structopt(long)]
+	pub seed: Option<u64>,
+	#[structopt(long)]
+	pub clients: Option<usize>,
+	#[structopt(long)]
+	pub vector_len: Option<usize>,
+	/// Dropouts the session tolerates
+	#[structopt(long)]
+	pub dropouts: Option<usize>,
+	/// Run the malicious protocol (needs --corruption and --input-bit-limit)
+	#[structopt(long)]
+	pub malicious: bool,
+	#[structopt(long)]
+	pub corruption: Option<usize>,
+	#[structopt(long)]
+	pub input_bit_limit: Option<usize>,
+	#[structopt(long)]
+	pub rounds: Option<usize>,
+	/// Time allowed for each state, ms
+	#[structopt(long)]
+	pub session_time: Option<usize>,
+	/// Time allowed for input sharing, ms
+	#[structopt(long)]
+	pub is_session_time: Option<usize>,
+	#[structopt(long)]
+	pub min_contributors: Option<usize>,
+	#[structopt(long)]
+	pub workers: Option<usize>,
+	/// Delay of every message, <ms> or <min_ms>-<max_ms>
+	#[structopt(long)]
+	pub latency: Option<Latency>,
+	/// Latency of one client, <client>=<ms> or <client>=<min_ms>-<max_ms>, repeatable
+	#[structopt(long)]
+	pub slow: Vec<Slow>,
+	/// Scripted dropout, <client>@<phase>[:<round>], repeatable
+	#[structopt(long)]
+	pub drop: Vec<Dropout>,
+	/// Adversarial client, <client>=<behaviour>, repeatable
+	#[structopt(long)]
+	pub cheat: Vec<Cheat>,
+	/// Log filter: error, warn, info, debug or trace
+	#[structopt(long, default_value = "warn")]
+	pub log_level: String,
+}
+
+impl Scenario {
+
+	pub fn load(path: &Path) -> Result<Scenario, ConfigError> {
+		let text = match fs::read_to_string(path) {
+			Ok(t) => t,
+			Err(e) => return Err(ConfigError::Read(format!("{}: {}", path.display(), e))),
+		};
+		match path.extension().and_then(|e| e.to_str()) {
+			Some("json") => serde_json::from_str(&text)
+				.map_err(|e| ConfigError::Parse(format!("{}: {}", path.display(), e))),
+			_ => toml::from_str(&text)
+				.map_err(|e| ConfigError::Parse(format!("{}: {}", path.display(), e))),
+		}
+	}
+
+	pub fn from_opt(opt: &SimOpt) -> Result<Scenario, ConfigError> {
+	/*
+		File (or defaults) first, then flags on top,
+		listed dropouts, slow clients and cheaters add to the file's
+	*/
+		let mut s = match &opt.config {
+			Some(path) => Scenario::load(path)?,
+			None => Scenario::default(),
+		};
+		if let Some(v) = opt.seed { s.seed = v; }
+		if let Some(v) = opt.clients { s.clients = v; }
+		if let Some(v) = opt.vector_len { s.vector_len = v; }
+		if let Some(v) = opt.dropouts { s.dropouts = v; }
+		if opt.malicious { s.malicious = true; }
+		if let Some(v) = opt.corruption { s.corruption = Some(v); }
+		if let Some(v) = opt.input_bit_limit { s.input_bit_limit = Some(v); }
+		if let Some(v) = opt.rounds { s.rounds = v; }
+		if let Some(v) = opt.session_time { s.session_time = v; }
+		if let Some(v) = opt.is_session_time { s.is_session_time = v; }
+		if let Some(v) = opt.min_contributors { s.min_contributors = v; }
+		if let Some(v) = opt.workers { s.workers = v; }
+		if let Some(v) = opt.latency { s.latency = v; }
+		s.slow.extend(opt.slow.iter().cloned());
+		s.drop.extend(opt.drop.iter().cloned());
+		s.cheat.extend(opt.cheat.iter().cloned());
+		Ok(s)
+	}
+
+	// Builder steps for library callers and tests
+	pub fn with_seed(mut self, seed: u64) -> Scenario {
+		self.seed = seed;
+		self
+	}
+
+	pub fn with_latency(mut self, min_ms: u64, max_ms: u64) -> Scenario {
+		self.latency = Latency { min_ms: min_ms, max_ms: max_ms };
+		self
+	}
+
+	pub fn with_slow(mut self, client: usize, min_ms: u64, max_ms: u64) -> Scenario {
+		self.slow.push(Slow { client: client, min_ms: min_ms, max_ms: max_ms });
+		self
+	}
+
+	pub fn with_dropout(mut self, client: usize, round: usize, phase: Phase) -> Scenario {
+		self.drop.push(Dropout { client: client, round: round, phase: phase });
+		self
+	}
+
+	pub fn with_cheater(mut self, client: usize, behaviour: Behaviour) -> Scenario {
+		self.cheat.push(Cheat { client: client, behaviour: behaviour });
+		self
+	}
+
+	pub fn server_config(&self) -> ServerConfig {
+		ServerConfig {
+			clients: self.clients,
+			vector_len: self.vector_len,
+			dropouts: self.dropouts,
+			malicious: self.malicious,
+			corruption: self.corruption,
+			input_bit_limit: self.input_bit_limit,
+			session_time: self.session_time,
+			is_session_time: self.is_session_time,
+			rounds: self.rounds,
+			min_contributors: self.min_contributors,
+			workers: self.workers,
+			..ServerConfig::default()
+		}
+	}
+
+	pub fn validate(&self) -> Result<(), ConfigError> {
+	/*
+		Session values are checked as the server would,
+		then the script: at most one dropout per client,
+		in a round and phase that exist, and cheaters
+		that follow the script otherwise, no more than the session's corruption
+	*/
+		self.server_config().validate()?;
+		let invalid = |s: String| Err(ConfigError::Invalid(s));
+		let latencies = self.slow.iter().map(|s| (s.min_ms, s.max_ms))
+			.chain(std::iter::once((self.latency.min_ms, self.latency.max_ms)));
+		for (min, max) in latencies {
+			if min > max {
+				return invalid(format!("latency {}-{} has min above max", min, max));
+			}
+		}
+		for s in self.slow.iter() {
+			if s.client >= self.clients {
+				return invalid(format!("slow client {} out of range", s.client));
+			}
+		}
+		for (i, d) in self.drop.iter().enumerate() {
+			if d.client >= self.clients {
+				return invalid(format!("dropout client {} out of range", d.client));
+			}
+			if d.round >= self.rounds {
+				return invalid(format!("dropout round {} out of range", d.round));
+			}
+			if d.phase <= Phase::KeyExchange && d.round != 0 {
+				return invalid(format!("{} only happens in round 0", d.phase));
+			}
+			if d.phase == Phase::ErrorCorrection && !self.malicious {
+				return invalid("error_correction dropouts need a malicious scenario".to_string());
+			}
+			if self.drop[..i].iter().any(|x| x.client == d.client) {
+				return invalid(format!("client {} drops out twice", d.client));
+			}
+		}
+		for (i, c) in self.cheat.iter().enumerate() {
+			if c.client >= self.clients {
+				return invalid(format!("cheating client {} out of range", c.client));
+			}
+			if !self.malicious {
+				return invalid("cheaters need a malicious scenario".to_string());
+			}
+			if self.cheat[..i].iter().any(|x| x.client == c.client) {
+				return invalid(format!("client {} cheats twice", c.client));
+			}
+			if self.dropout(c.client).is_some() {
+				return invalid(format!("client {} both cheats and drops out", c.client));
+			}
+		}
+		let cheaters = self.cheat.iter().filter(|c| !c.behaviour.is_honest()).count();
+		if cheaters > self.corruption.unwrap_or(0) {
+			return invalid(format!("{} cheaters, the session's corruption allows {}",
+				cheaters, self.corruption.unwrap_or(0)));
+		}
+		Ok(())
+	}
+
+	pub fn to_toml(&self) -> String {
+		toml::to_string_pretty(self).unwrap()
+	}
+
+	pub fn client_id(index: usize) -> String {
+		format!("Client {}", index)
+	}
+
+	pub fn dropout(&self, client: usize) -> Option<Dropout> {
+		self.drop.iter().find(|d| d.client == client).cloned()
+	}
+
+	pub fn behaviour(&self, client: usize) -> Behaviour {
+		self.cheat.iter().find(|c| c.client == client).map_or(Behaviour::Honest, |c| c.behaviour)
+	}
+
+	pub fn latency(&self, client: usize) -> Latency {
+		match self.slow.iter().find(|s| s.client == client) {
+			Some(s) => Latency { min_ms: s.min_ms, max_ms: s.max_ms },
+			None => self.latency,
+		}
+	}
+
+	/*
+		Each client draws from its own ChaCha streams of the seed,
+		one per Stream, so adding a client or a thread
+		doesn't shift anyone else's values
+	*/
+	pub fn rng(&self, client: usize, stream: Stream) -> ChaCha8Rng {
+		let mut rng = ChaCha8Rng::seed_from_u64(self.seed);
+		rng.set_stream(STREAMS * client as u64 + stream as u64);
+		rng
+	}
+
+	pub fn inputs(&self, client: usize) -> Vec<Vec<u64>> {
+	/*
+		One vector per round, values as the stock client draws them:
+		0..10, reduced below 2^input_bit_limit when malicious
+	*/
+		let mut rng = self.rng(client, Stream::Inputs);
+		let inputBitMod = match (self.malicious, self.input_bit_limit) {
+			(true, Some(S)) => (1u64 << S) - 1,
+			_ => u64::MAX,
+		};
+		(0..self.rounds)
+			.map(|_| (0..self.vector_len).map(|_| rng.gen_range(0, 10) % inputBitMod).collect())
+			.collect()
+	}
+
+	pub fn contributes(&self, client: usize, round: usize) -> bool {
+	/*
+		A client's input is in a round's sum once its IS shares are out,
+		dropping later in that round doesn't take it back
+		Cheaters are excluded, but for bad or replayed aggregated shares:
+		those are left out and the input stays
+		The error for the replay in round 1 is read with round 2's shares,
+		so a replay_aggregate client stops after sharing in round 2
+	*/
+		match self.behaviour(client) {
+			Behaviour::Honest | Behaviour::BadAggregate => (),
+			Behaviour::ReplayAggregate if round <= 2 => (),
+			_ => return false,
+		}
+		match self.dropout(client) {
+			Some(d) => round < d.round || (round == d.round && d.phase > Phase::InputSharing),
+			None => true,
+		}
+	}
+}
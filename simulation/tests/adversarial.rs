@@ -0,0 +1,110 @@
+#![allow(non_snake_case)]
+
+use server::blame::EcTest;
+use simulation::*;
+
+/*
+	One cheater per run, next to honest clients,
+	the server has to catch it and still publish the honest sum
+	16 clients, corruption 1, 3-bit inputs
+*/
+
+fn scenario(behaviour: Behaviour) -> Scenario {
+	let s = Scenario {
+		clients: 16,
+		vector_len: 32,
+		malicious: true,
+		corruption: Some(1),
+		input_bit_limit: Some(3),
+		session_time: 10000,
+		is_session_time: 10000,
+		..Scenario::default()
+	}.with_seed(7);
+	match behaviour {
+		Behaviour::Honest => s,
+		_ => s.with_cheater(5, behaviour),
+	}
+}
+
+fn run_ok(behaviour: Behaviour) -> Report {
+	let report = run(&scenario(behaviour)).unwrap();
+	assert!(report.passed(), "{}: {:?}", behaviour, report.mismatches());
+	report
+}
+
+fn blamed(behaviour: Behaviour, test: EcTest) {
+	let report = run_ok(behaviour);
+	let blame = report.rounds[0].blame.as_ref().unwrap();
+	let b = blame.excluded.iter().find(|b| Some(b.index) == report.clients[5].position).unwrap();
+	assert_eq!(b.failed, vec![test]);
+	assert_eq!(blame.excluded.len(), 1);
+}
+
+#[test]
+fn honest() {
+	let report = run_ok(Behaviour::Honest);
+	let result = report.rounds[0].result.as_ref().unwrap();
+	assert!(result.dropouts.is_empty() && result.rejected.is_empty());
+	assert!(report.rounds[0].blame.as_ref().unwrap().excluded.is_empty());
+}
+
+#[test]
+fn out_of_range() {
+	blamed(Behaviour::OutOfRange, EcTest::Bounds);
+}
+
+#[test]
+fn wrong_bits() {
+	blamed(Behaviour::WrongBits, EcTest::BitAndQuadratic);
+}
+
+#[test]
+fn fake_squares() {
+	blamed(Behaviour::FakeSquares, EcTest::BitAndQuadratic);
+}
+
+#[test]
+fn inconsistent_shares() {
+	blamed(Behaviour::InconsistentShares, EcTest::Degree);
+}
+
+#[test]
+fn wrong_tests() {
+	blamed(Behaviour::WrongTests, EcTest::Responses);
+}
+
+#[test]
+fn bad_aggregate() {
+	let report = run_ok(Behaviour::BadAggregate);
+	let result = report.rounds[0].result.as_ref().unwrap();
+	assert_eq!(result.rejected, vec![report.clients[5].position.unwrap()]);
+	assert!(result.dropouts.is_empty());
+}
+
+#[test]
+fn replay() {
+	let report = run_ok(Behaviour::Replay);
+	let position = report.clients[5].position.unwrap();
+	assert_eq!(report.rounds[0].result.as_ref().unwrap().dropouts, vec![position]);
+	assert!(report.rounds[0].blame.as_ref().unwrap().excluded.is_empty());
+}
+
+#[test]
+fn replay_aggregate() {
+	// round 0's aggregated shares again in round 1, refused on the signature
+	let s = Scenario { rounds: 2, ..scenario(Behaviour::ReplayAggregate) };
+	let report = run(&s).unwrap();
+	assert!(report.passed(), "{:?}", report.mismatches());
+	for r in report.rounds.iter() {
+		let result = r.result.as_ref().unwrap();
+		assert!(result.dropouts.is_empty() && result.rejected.is_empty());
+		assert_eq!(result.contributors, 16);
+	}
+	assert_eq!(report.clients[5].sums.len(), 2);
+}
+
+#[test]
+fn too_many_cheaters() {
+	let s = scenario(Behaviour::WrongTests).with_cheater(6, Behaviour::Replay);
+	assert!(s.validate().is_err());
+}
@@ -0,0 +1,90 @@
+#![allow(non_snake_case)]
+
+use simulation::*;
+
+/*
+	Multi-round runs with scripted dropouts and latency,
+	every round has to publish the sum of the inputs that count
+*/
+
+fn semi_honest() -> Scenario {
+	Scenario {
+		clients: 10,
+		vector_len: 16,
+		dropouts: 2,
+		rounds: 3,
+		..Scenario::default()
+	}.with_seed(7).with_latency(0, 20)
+}
+
+fn malicious() -> Scenario {
+	Scenario {
+		clients: 16,
+		vector_len: 32,
+		malicious: true,
+		corruption: Some(1),
+		input_bit_limit: Some(3),
+		rounds: 2,
+		..Scenario::default()
+	}.with_seed(7).with_latency(0, 10)
+}
+
+fn run_ok(scenario: &Scenario) -> Report {
+	let report = run(scenario).unwrap();
+	assert!(report.passed(), "{:?}", report.mismatches());
+	assert_eq!(report.rounds.len(), scenario.rounds);
+	report
+}
+
+fn contributors(report: &Report) -> Vec<usize> {
+	report.rounds.iter().map(|r| r.contributors.len()).collect()
+}
+
+#[test]
+fn semi_honest_no_dropouts() {
+	let report = run_ok(&semi_honest());
+	assert_eq!(contributors(&report), vec![10, 10, 10]);
+	for r in report.rounds.iter() {
+		assert!(r.result.as_ref().unwrap().dropouts.is_empty());
+	}
+}
+
+#[test]
+fn semi_honest_dropouts() {
+	// 5 leaves at AG of round 0, its input still counts there only, 3 at IS of round 1
+	let s = semi_honest()
+		.with_dropout(5, 0, Phase::Aggregation)
+		.with_dropout(3, 1, Phase::InputSharing);
+	let report = run_ok(&s);
+	assert_eq!(contributors(&report), vec![10, 8, 8]);
+	assert_eq!(report.clients[5].dropped.unwrap().phase, Phase::Aggregation);
+	assert!(report.clients.iter().filter(|c| c.dropped.is_none()).all(|c| c.sums.len() == 3));
+}
+
+#[test]
+fn semi_honest_slow_client() {
+	let report = run_ok(&semi_honest().with_slow(2, 50, 150));
+	assert_eq!(contributors(&report), vec![10, 10, 10]);
+}
+
+#[test]
+fn malicious_dropouts() {
+	// 4 leaves at EC of round 0 and still counts there, 7 at IS of round 1
+	let s = malicious()
+		.with_dropout(4, 0, Phase::ErrorCorrection)
+		.with_dropout(7, 1, Phase::InputSharing);
+	let report = run_ok(&s);
+	assert_eq!(contributors(&report), vec![16, 14]);
+	assert!(report.rounds[0].blame.as_ref().unwrap().excluded.is_empty());
+}
+
+#[test]
+fn same_seed_same_sums() {
+	let s = semi_honest().with_dropout(6, 0, Phase::InputSharing);
+	let a = run_ok(&s);
+	let b = run_ok(&s);
+	for (x, y) in a.rounds.iter().zip(b.rounds.iter()) {
+		assert_eq!(x.expected, y.expected);
+		assert_eq!(x.result.as_ref().unwrap().sum, y.result.as_ref().unwrap().sum);
+	}
+}
@@ -0,0 +1,83 @@
+#![allow(non_snake_case)]
+
+use std::time::Duration;
+
+pub mod zeromq;
+pub mod memory;
+
+pub use zeromq::ZmqTransport;
+pub use memory::MemoryTransport;
+
+/*
+	How server and clients reach each other
+	Every message is a list of frames.
+		point-to-point	client -> server	[ID, payload...] at the server,
+											the ID is added by the transport
+						server -> client	[ID, payload...], routed by ID
+		broadcast		server -> all		[topic, seq, signature, data...]
+		catch-up		request/reply		one reply per request
+	Server and clients only see these traits,
+	ZmqTransport puts them on tcp ports, MemoryTransport on channels
+	so a server and many clients can run in one process.
+*/
+
+pub type Frames = Vec<Vec<u8>>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransportError {
+	Closed(usize),			// other end is gone
+	SocketFail(usize),
+	AlreadyTaken(usize),	// server end handed out once only
+}
+
+pub trait Outgoing: Send {
+	fn send(&self, frames: Frames) -> Result<(), TransportError>;
+}
+
+pub trait Incoming: Send {
+	// Blocks up to timeout (forever if None), Ok(None) when nothing came
+	fn recv(&self, timeout: Option<Duration>) -> Result<Option<Frames>, TransportError>;
+}
+
+impl<T: Outgoing + ?Sized> Outgoing for Box<T> {
+	fn send(&self, frames: Frames) -> Result<(), TransportError> {
+		(**self).send(frames)
+	}
+}
+
+impl<T: Incoming + ?Sized> Incoming for Box<T> {
+	fn recv(&self, timeout: Option<Duration>) -> Result<Option<Frames>, TransportError> {
+		(**self).recv(timeout)
+	}
+}
+
+// Both directions of one endpoint
+pub trait Connection: Outgoing + Incoming {}
+
+impl<T: Outgoing + Incoming> Connection for T {}
+
+pub trait Transport: Send + Sync {
+	/*
+		Server end
+			listen		client messages as [ID, payload...], taken once
+			replier		one per handler, [ID, payload...] back to that client
+			publisher	one per session, broadcasts to every subscriber
+			relay		blocks carrying publisher traffic out, if the transport needs it
+			responder	catch-up requests, each recv answered by the next send, taken once
+	*/
+	fn listen(&self) -> Result<Box<dyn Incoming>, TransportError>;
+	fn replier(&self) -> Result<Box<dyn Outgoing>, TransportError>;
+	fn publisher(&self) -> Result<Box<dyn Outgoing>, TransportError>;
+	fn relay(&self) -> Result<(), TransportError>;
+	fn responder(&self) -> Result<Box<dyn Connection>, TransportError>;
+	/*
+		Client end
+			connect		to the server as ID, its replies come back here
+			subscribe	every broadcast from now on
+			requester	catch-up, send a request then recv its reply,
+						one that timed out is dropped and a new one made
+	*/
+	fn connect(&self, ID: &[u8]) -> Result<Box<dyn Connection>, TransportError>;
+	fn subscribe(&self) -> Result<Box<dyn Incoming>, TransportError>;
+	fn requester(&self) -> Result<Box<dyn Connection>, TransportError>;
+}
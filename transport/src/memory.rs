@@ -0,0 +1,226 @@
+use std::collections::HashMap;
+use std::sync::*;
+use std::sync::mpsc::{channel, Sender, Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+use crate::*;
+
+/*
+	In-process transport, channels instead of sockets
+	Clone it to hand the same hub to the server and every client.
+	Same delivery as ZMQ: replies go to the connection with that ID,
+	broadcasts only reach subscribers that exist when they're sent.
+*/
+
+#[derive(Clone, Default)]
+pub struct MemoryTransport {
+	hub: Arc<Hub>,
+}
+
+struct Hub {
+	inbox: Sender<Frames>,								// client messages, [ID, payload...]
+	inboxReciever: Mutex<Option<Receiver<Frames>>>,		// taken by listen
+	clients: Mutex<HashMap<Vec<u8>, Sender<Frames>>>,	// ID -> its connection, last connect wins
+	subscribers: Mutex<Vec<Sender<Frames>>>,
+	catchup: Sender<(Sender<Frames>, Frames)>,			// (where to reply, request)
+	catchupReciever: Mutex<Option<Receiver<(Sender<Frames>, Frames)>>>,	// taken by responder
+}
+
+impl Default for Hub {
+	fn default() -> Hub {
+		let (tx, rx) = channel();
+		let (catchupTx, catchupRx) = channel();
+		Hub {
+			inbox: tx,
+			inboxReciever: Mutex::new(Some(rx)),
+			clients: Mutex::new(HashMap::new()),
+			subscribers: Mutex::new(Vec::new()),
+			catchup: catchupTx,
+			catchupReciever: Mutex::new(Some(catchupRx)),
+		}
+	}
+}
+
+impl MemoryTransport {
+	pub fn new() -> MemoryTransport {
+		MemoryTransport::default()
+	}
+}
+
+fn recv_timeout<T>(rx: &Receiver<T>, timeout: Option<Duration>) -> Result<Option<T>, TransportError> {
+	match timeout {
+		Some(t) => match rx.recv_timeout(t) {
+			Ok(m) => Ok(Some(m)),
+			Err(RecvTimeoutError::Timeout) => Ok(None),
+			Err(RecvTimeoutError::Disconnected) => Err(TransportError::Closed(0)),
+		},
+		None => rx.recv().map(Some).map_err(|_| TransportError::Closed(0)),
+	}
+}
+
+struct MemoryIncoming(Mutex<Receiver<Frames>>);
+
+impl Incoming for MemoryIncoming {
+	fn recv(&self, timeout: Option<Duration>) -> Result<Option<Frames>, TransportError> {
+		let rx = self.0.lock().map_err(|_| TransportError::Closed(1))?;
+		recv_timeout(&rx, timeout)
+	}
+}
+
+struct MemoryReplier(Arc<Hub>);
+
+impl Outgoing for MemoryReplier {
+	fn send(&self, mut frames: Frames) -> Result<(), TransportError> {
+	/*
+		Like a ROUTER, a reply to an unknown ID is dropped
+	*/
+		if frames.is_empty() {
+			return Err(TransportError::SocketFail(1));
+		}
+		let ID = frames.remove(0);
+		let clients = self.0.clients.lock().map_err(|_| TransportError::Closed(1))?;
+		if let Some(tx) = clients.get(&ID) {
+			let _ = tx.send(frames);
+		}
+		Ok(())
+	}
+}
+
+struct MemoryPublisher(Arc<Hub>);
+
+impl Outgoing for MemoryPublisher {
+	fn send(&self, frames: Frames) -> Result<(), TransportError> {
+		let mut subscribers = self.0.subscribers.lock().map_err(|_| TransportError::Closed(1))?;
+		subscribers.retain(|tx| tx.send(frames.clone()).is_ok());
+		Ok(())
+	}
+}
+
+struct MemoryResponder {
+	requests: Mutex<Receiver<(Sender<Frames>, Frames)>>,
+	pending: Mutex<Option<Sender<Frames>>>,		// requester of the last recv
+}
+
+impl Incoming for MemoryResponder {
+	fn recv(&self, timeout: Option<Duration>) -> Result<Option<Frames>, TransportError> {
+		let rx = self.requests.lock().map_err(|_| TransportError::Closed(1))?;
+		match recv_timeout(&rx, timeout)? {
+			Some((reply, request)) => {
+				*self.pending.lock().map_err(|_| TransportError::Closed(1))? = Some(reply);
+				Ok(Some(request))
+			},
+			None => Ok(None),
+		}
+	}
+}
+
+impl Outgoing for MemoryResponder {
+	fn send(&self, frames: Frames) -> Result<(), TransportError> {
+		match self.pending.lock().map_err(|_| TransportError::Closed(1))?.take() {
+			Some(reply) => {
+				// requester may have given up already
+				let _ = reply.send(frames);
+				Ok(())
+			},
+			None => Err(TransportError::SocketFail(1)),
+		}
+	}
+}
+
+struct MemoryConnection {
+	ID: Vec<u8>,
+	inbox: Sender<Frames>,
+	replies: Mutex<Receiver<Frames>>,
+}
+
+impl Outgoing for MemoryConnection {
+	fn send(&self, frames: Frames) -> Result<(), TransportError> {
+		let mut msg = vec![self.ID.clone()];
+		msg.extend(frames);
+		self.inbox.send(msg).map_err(|_| TransportError::Closed(2))
+	}
+}
+
+impl Incoming for MemoryConnection {
+	fn recv(&self, timeout: Option<Duration>) -> Result<Option<Frames>, TransportError> {
+		let rx = self.replies.lock().map_err(|_| TransportError::Closed(1))?;
+		recv_timeout(&rx, timeout)
+	}
+}
+
+struct MemoryRequester {
+	catchup: Sender<(Sender<Frames>, Frames)>,
+	reply: Sender<Frames>,
+	replies: Mutex<Receiver<Frames>>,
+}
+
+impl Outgoing for MemoryRequester {
+	fn send(&self, frames: Frames) -> Result<(), TransportError> {
+		self.catchup.send((self.reply.clone(), frames)).map_err(|_| TransportError::Closed(2))
+	}
+}
+
+impl Incoming for MemoryRequester {
+	fn recv(&self, timeout: Option<Duration>) -> Result<Option<Frames>, TransportError> {
+		let rx = self.replies.lock().map_err(|_| TransportError::Closed(1))?;
+		recv_timeout(&rx, timeout)
+	}
+}
+
+impl Transport for MemoryTransport {
+
+	fn listen(&self) -> Result<Box<dyn Incoming>, TransportError> {
+		match self.hub.inboxReciever.lock().map_err(|_| TransportError::Closed(1))?.take() {
+			Some(rx) => Ok(Box::new(MemoryIncoming(Mutex::new(rx)))),
+			None => Err(TransportError::AlreadyTaken(0)),
+		}
+	}
+
+	fn replier(&self) -> Result<Box<dyn Outgoing>, TransportError> {
+		Ok(Box::new(MemoryReplier(self.hub.clone())))
+	}
+
+	fn publisher(&self) -> Result<Box<dyn Outgoing>, TransportError> {
+		Ok(Box::new(MemoryPublisher(self.hub.clone())))
+	}
+
+	fn relay(&self) -> Result<(), TransportError> {
+		// publishers reach subscribers directly
+		Ok(())
+	}
+
+	fn responder(&self) -> Result<Box<dyn Connection>, TransportError> {
+		match self.hub.catchupReciever.lock().map_err(|_| TransportError::Closed(1))?.take() {
+			Some(rx) => Ok(Box::new(MemoryResponder {
+				requests: Mutex::new(rx),
+				pending: Mutex::new(None),
+			})),
+			None => Err(TransportError::AlreadyTaken(1)),
+		}
+	}
+
+	fn connect(&self, ID: &[u8]) -> Result<Box<dyn Connection>, TransportError> {
+		let (tx, rx) = channel();
+		self.hub.clients.lock().map_err(|_| TransportError::Closed(1))?.insert(ID.to_vec(), tx);
+		Ok(Box::new(MemoryConnection {
+			ID: ID.to_vec(),
+			inbox: self.hub.inbox.clone(),
+			replies: Mutex::new(rx),
+		}))
+	}
+
+	fn subscribe(&self) -> Result<Box<dyn Incoming>, TransportError> {
+		let (tx, rx) = channel();
+		self.hub.subscribers.lock().map_err(|_| TransportError::Closed(1))?.push(tx);
+		Ok(Box::new(MemoryIncoming(Mutex::new(rx))))
+	}
+
+	fn requester(&self) -> Result<Box<dyn Connection>, TransportError> {
+		let (tx, rx) = channel();
+		Ok(Box::new(MemoryRequester {
+			catchup: self.hub.catchup.clone(),
+			reply: tx,
+			replies: Mutex::new(rx),
+		}))
+	}
+}
@@ -0,0 +1,193 @@
+use std::time::Duration;
+
+use tracing::info;
+
+use crate::*;
+
+/*
+	ZMQ over tcp, the sockets server and client always used
+		server	ROUTER msg_port, PUB broadcast_port, REP catchup_port
+		client	DEALER (identity = ID), SUB, REQ
+	Handlers reply through PUSH sockets to inproc://backend,
+	the listener routes them out while it waits for messages.
+	Sessions publish on inproc://publisher, relay proxies it to the PUB port.
+	Server and client ends must come from transports sharing one context.
+*/
+
+#[derive(Clone)]
+pub struct ZmqTransport {
+	context: zmq::Context,
+	ip: Option<String>,				// None: bind every interface, connect to localhost
+	msgPort: usize,
+	broadcastPort: usize,
+	catchupPort: usize,
+}
+
+impl ZmqTransport {
+
+	pub fn new(context: zmq::Context, ip: Option<&str>,
+		msgPort: usize, broadcastPort: usize, catchupPort: usize) -> ZmqTransport {
+		ZmqTransport {
+			context: context,
+			ip: ip.map(|s| s.to_string()),
+			msgPort: msgPort,
+			broadcastPort: broadcastPort,
+			catchupPort: catchupPort,
+		}
+	}
+
+	fn bind_addr(&self, port: usize) -> String {
+		format!("tcp://{}:{}", self.ip.as_deref().unwrap_or("*"), port)
+	}
+
+	fn connect_addr(&self, port: usize) -> String {
+		format!("tcp://{}:{}", self.ip.as_deref().unwrap_or("localhost"), port)
+	}
+
+	fn socket(&self, kind: zmq::SocketType) -> Result<zmq::Socket, TransportError> {
+		self.context.socket(kind).map_err(|_| TransportError::SocketFail(0))
+	}
+}
+
+fn poll_ms(timeout: Option<Duration>) -> i64 {
+	timeout.map_or(-1, |t| t.as_millis() as i64)
+}
+
+fn send_frames(socket: &zmq::Socket, frames: Frames) -> Result<(), TransportError> {
+	socket.send_multipart(frames, 0).map_err(|_| TransportError::SocketFail(1))
+}
+
+fn recv_frames(socket: &zmq::Socket, timeout: Option<Duration>) -> Result<Option<Frames>, TransportError> {
+	let mut items = [socket.as_poll_item(zmq::POLLIN)];
+	if zmq::poll(&mut items, poll_ms(timeout)).is_err() {
+		return Err(TransportError::SocketFail(2));
+	}
+	if !items[0].is_readable() {
+		return Ok(None);
+	}
+	socket.recv_multipart(0).map(Some).map_err(|_| TransportError::SocketFail(2))
+}
+
+// One socket, both directions
+struct ZmqSocket(zmq::Socket);
+
+impl Outgoing for ZmqSocket {
+	fn send(&self, frames: Frames) -> Result<(), TransportError> {
+		send_frames(&self.0, frames)
+	}
+}
+
+impl Incoming for ZmqSocket {
+	fn recv(&self, timeout: Option<Duration>) -> Result<Option<Frames>, TransportError> {
+		recv_frames(&self.0, timeout)
+	}
+}
+
+struct ZmqListener {
+	frontend: zmq::Socket,			// ROUTER, clients
+	backend: zmq::Socket,			// PULL, handler replies
+}
+
+impl Incoming for ZmqListener {
+	fn recv(&self, timeout: Option<Duration>) -> Result<Option<Frames>, TransportError> {
+	/*
+		Sleeps in poll on both sockets,
+		replies are sent on as they come
+	*/
+		loop {
+			let mut items = [
+				self.frontend.as_poll_item(zmq::POLLIN),
+				self.backend.as_poll_item(zmq::POLLIN),
+			];
+			if zmq::poll(&mut items, poll_ms(timeout)).is_err() {
+				return Err(TransportError::SocketFail(2));
+			}
+			if items[1].is_readable() {
+				if let Ok(frames) = self.backend.recv_multipart(0) {
+					let _ = self.frontend.send_multipart(frames, 0);
+				}
+			}
+			if items[0].is_readable() {
+				return self.frontend.recv_multipart(0).map(Some).map_err(|_| TransportError::SocketFail(2));
+			}
+			if !items[1].is_readable() {
+				return Ok(None);
+			}
+		}
+	}
+}
+
+impl Transport for ZmqTransport {
+
+	fn listen(&self) -> Result<Box<dyn Incoming>, TransportError> {
+		let frontend = self.socket(zmq::ROUTER)?;
+		let backend = self.socket(zmq::PULL)?;
+		let addr = self.bind_addr(self.msgPort);
+		info!("Reciever going {}", addr);
+		frontend.bind(&addr).map_err(|_| TransportError::SocketFail(3))?;
+		backend.bind("inproc://backend").map_err(|_| TransportError::AlreadyTaken(0))?;
+		Ok(Box::new(ZmqListener {
+			frontend: frontend,
+			backend: backend,
+		}))
+	}
+
+	fn replier(&self) -> Result<Box<dyn Outgoing>, TransportError> {
+		let reply = self.socket(zmq::PUSH)?;
+		reply.connect("inproc://backend").map_err(|_| TransportError::SocketFail(3))?;
+		Ok(Box::new(ZmqSocket(reply)))
+	}
+
+	fn publisher(&self) -> Result<Box<dyn Outgoing>, TransportError> {
+		let publisher = self.socket(zmq::PUB)?;
+		publisher.connect("inproc://publisher").map_err(|_| TransportError::SocketFail(3))?;
+		Ok(Box::new(ZmqSocket(publisher)))
+	}
+
+	fn relay(&self) -> Result<(), TransportError> {
+	/*
+		Sessions publish on inproc PUB sockets,
+		forward all of them to the one tcp PUB port
+	*/
+		let frontend = self.socket(zmq::XSUB)?;
+		let backend = self.socket(zmq::XPUB)?;
+		let addr = self.bind_addr(self.broadcastPort);
+		info!("Publisher going {}", addr);
+		backend.bind(&addr).map_err(|_| TransportError::SocketFail(3))?;
+		frontend.bind("inproc://publisher").map_err(|_| TransportError::AlreadyTaken(1))?;
+		zmq::proxy(&frontend, &backend).map_err(|_| TransportError::SocketFail(4))
+	}
+
+	fn responder(&self) -> Result<Box<dyn Connection>, TransportError> {
+		let responder = self.socket(zmq::REP)?;
+		let addr = self.bind_addr(self.catchupPort);
+		info!("Catch-up going {}", addr);
+		responder.bind(&addr).map_err(|_| TransportError::SocketFail(3))?;
+		Ok(Box::new(ZmqSocket(responder)))
+	}
+
+	fn connect(&self, ID: &[u8]) -> Result<Box<dyn Connection>, TransportError> {
+		let sender = self.socket(zmq::DEALER)?;
+		let addr = self.connect_addr(self.msgPort);
+		info!("Sender connecting {}", addr);
+		sender.set_identity(ID).map_err(|_| TransportError::SocketFail(3))?;
+		sender.connect(&addr).map_err(|_| TransportError::SocketFail(3))?;
+		Ok(Box::new(ZmqSocket(sender)))
+	}
+
+	fn subscribe(&self) -> Result<Box<dyn Incoming>, TransportError> {
+		let subscriber = self.socket(zmq::SUB)?;
+		let addr = self.connect_addr(self.broadcastPort);
+		info!("Subscriber connecting to {}", addr);
+		subscriber.connect(&addr).map_err(|_| TransportError::SocketFail(3))?;
+		subscriber.set_subscribe(b"").map_err(|_| TransportError::SocketFail(3))?;
+		Ok(Box::new(ZmqSocket(subscriber)))
+	}
+
+	fn requester(&self) -> Result<Box<dyn Connection>, TransportError> {
+		let req = self.socket(zmq::REQ)?;
+		req.set_linger(0).map_err(|_| TransportError::SocketFail(3))?;
+		req.connect(&self.connect_addr(self.catchupPort)).map_err(|_| TransportError::SocketFail(3))?;
+		Ok(Box::new(ZmqSocket(req)))
+	}
+}